@@ -0,0 +1,61 @@
+//! Run the sim without a user interface, edited over OSC:
+//!
+//! `cargo run --example remote -- [listen port] [gate port] [sync]`
+//!
+//! Messages sent to the listen port edit the grid, see `command_from_osc`,
+//! and the gates of watched entities are sent to the gate port. With `sync`
+//! the tempo is shared with the other instances on the local network.
+
+use sim::{NullBackend, OscClient, OscServer, Remote, Stream, StreamConfig, TempoSync};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::ops::Range;
+use std::thread;
+use std::time::Duration;
+
+/// Commands that can be queued between two updates of the engine
+const QUEUE_SIZE: usize = 256;
+/// Ports the instances syncing their tempo listen on
+const SYNC_PORTS: Range<u16> = 47310..47318;
+
+fn port(arg: Option<String>, default: u16) -> u16 {
+    arg.and_then(|arg| arg.parse().ok()).unwrap_or(default)
+}
+
+fn main() -> Result<(), sim::Error> {
+    let mut args = std::env::args().skip(1);
+    let listen = port(args.next(), 9000);
+    let gates = port(args.next(), 9001);
+    let sync = args.next().is_some_and(|arg| arg == "sync");
+
+    // the engine keeps running on its own thread until the process is stopped
+    let (_stream, mut controller) = Stream::start(
+        NullBackend::new(),
+        StreamConfig::new(48000, 256),
+        QUEUE_SIZE,
+    )?;
+    let server = OscServer::bind(listen)?;
+    let client = OscClient::connect(SocketAddr::from((Ipv4Addr::LOCALHOST, gates)))?;
+    let mut remote = Remote::new()
+        .with_osc_server(server)
+        .with_osc_client(client);
+
+    if sync {
+        remote = remote.with_tempo_sync(TempoSync::join(Ipv4Addr::BROADCAST, SYNC_PORTS, 120.0)?);
+    }
+
+    println!("listening on {}, sending gates to {}", listen, gates);
+
+    loop {
+        controller.update();
+
+        if let Err(e) = remote.update(&mut controller) {
+            eprintln!("{}", e);
+        }
+
+        if let Some(e) = controller.take_error() {
+            eprintln!("{}", e);
+        }
+
+        thread::sleep(Duration::from_millis(5));
+    }
+}
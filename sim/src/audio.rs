@@ -1,34 +1,709 @@
-use crate::grid::Grid;
-use screech::{BasicTracker, Screech};
+use crate::entity::{
+    Entity, EntityKind, EntityMutKind, EntitySource, EntityType, Setting, VoiceAllocator,
+};
+use crate::grid::{Grid, Position, Rect};
+use crate::midi::MidiMessage;
+use crate::tracker::SignalTracker;
+use crate::transport::Clock;
+use crate::Error;
+use screech::traits::{Source, Tracker};
+use screech::{Input, Output, Screech};
+use std::sync::Arc;
 
 pub struct Audio {
     screech: Screech,
+    silence: Vec<f32>,
+    /// connections from the grid currently applied to screech
+    connections: Vec<(Output, Input)>,
+    /// amount of samples rendered so far
+    time: u64,
+    /// peak level of every entity in the last buffer
+    levels: Vec<(Position, f32)>,
+    /// MIDI messages sent by entities in the last buffer, with their time in samples
+    midi: Vec<(u64, Position, MidiMessage)>,
+    /// entities with their gate state reported after sampling
+    watched: Vec<(Position, bool)>,
+    /// gates opened or closed by watched entities in the last buffer
+    gates: Vec<(u64, Position, bool)>,
+    /// buffers lent to the sources of watched entities to collect their gates
+    gate_buffers: Vec<Vec<(usize, bool)>>,
+    /// entity with its primary output copied after sampling
+    tap: Option<Position>,
+    /// primary output of the tapped entity in the last buffer
+    tapped: Vec<f32>,
+    /// transport position passed to the entities before sampling
+    clock: Clock,
+    sample_rate: usize,
+    /// source sampled along with the grid to free the signals of removed entities
+    release_id: usize,
+    /// sources of removed entities, freed during the next buffer
+    released: Vec<usize>,
+    /// messages drained from an entity before they are timed
+    events: Vec<(usize, MidiMessage)>,
+    /// entities playing the other voices of the entities downstream of voice allocators,
+    /// sorted like the entities of the grid
+    copies: Vec<VoiceCopy>,
+    /// outputs of the copies summed into the last entities downstream of an allocator,
+    /// by source id of the entity on the grid
+    mixed: Vec<(usize, Vec<(Output, Output)>)>,
+    /// amount of voices of every voice allocator when the copies were made, by source id
+    voice_counts: Vec<(usize, usize)>,
+    /// last setting change an entity rejected while dispatching the scheduled changes
+    rejected: Option<Error>,
 }
 
 impl Audio {
     pub fn new(sample_rate: usize, buffer_size: usize) -> Self {
-        let tracker = Box::new(BasicTracker::<256>::new(buffer_size));
+        // the tracker grows with the amount of sources in the grid
+        let tracker = Box::new(SignalTracker::new(buffer_size));
         let mut screech = Screech::with_tracker(tracker, sample_rate);
+        let release_id = screech.create_source_id();
 
         // setup new output buffer
         screech.create_main_out("left_out");
         screech.create_main_out("right_out");
 
-        Audio { screech }
+        Audio {
+            screech,
+            silence: vec![0.0; buffer_size],
+            connections: vec![],
+            time: 0,
+            levels: vec![],
+            midi: vec![],
+            watched: vec![],
+            gates: vec![],
+            gate_buffers: vec![],
+            tap: None,
+            tapped: vec![],
+            clock: Clock::new(120.0),
+            sample_rate,
+            release_id,
+            released: vec![],
+            events: vec![],
+            copies: vec![],
+            mixed: vec![],
+            voice_counts: vec![],
+            rejected: None,
+        }
+    }
+
+    /// Time in samples of the start of the next buffer, used to schedule setting changes
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn get_levels(&self) -> &[(Position, f32)] {
+        &self.levels
+    }
+
+    pub fn get_midi(&self) -> &[(u64, Position, MidiMessage)] {
+        &self.midi
+    }
+
+    /// Report the gates of the primary output of the entity at a position
+    pub fn watch_gate(&mut self, position: Position, watch: bool) {
+        let watched = self.is_watched(position);
+
+        if watch && !watched {
+            self.watched.push((position, false));
+        } else if !watch {
+            self.watched.retain(|(p, _)| *p != position);
+        }
+    }
+
+    pub fn is_watched(&self, position: Position) -> bool {
+        self.watched.iter().any(|(p, _)| *p == position)
+    }
+
+    /// Move the watched gates inside a rectangle along with their entities
+    pub fn move_watched(&mut self, rect: &Rect, offset: Position) {
+        for (position, _) in self.watched.iter_mut() {
+            if rect.intersect_position(*position) {
+                *position = position.add(offset);
+            }
+        }
+    }
+
+    pub fn get_watched(&self) -> impl Iterator<Item = Position> + '_ {
+        self.watched.iter().map(|(position, _)| *position)
+    }
+
+    pub fn get_gates(&self) -> &[(u64, Position, bool)] {
+        &self.gates
+    }
+
+    /// Setting change rejected by a full entity since the last call,
+    /// sampling goes on without it so it is not a sampling error
+    pub fn take_rejected(&mut self) -> Option<Error> {
+        self.rejected.take()
+    }
+
+    /// Copy the primary output of the entity at a position after sampling
+    pub fn set_tap(&mut self, position: Option<Position>) {
+        self.tap = position;
+    }
+
+    /// Primary output of the tapped entity in the last buffer,
+    /// empty when there is no entity at the tapped position
+    pub fn get_tap(&self) -> &[f32] {
+        &self.tapped
+    }
+
+    /// Silence for hosts that do not sample the grid, for example while stopped
+    pub fn get_silence(&self) -> (&[f32], &[f32]) {
+        (&self.silence, &self.silence)
+    }
+
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    pub fn get_sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    pub fn get_buffer_size(&self) -> usize {
+        self.silence.len()
+    }
+
+    /// Create an entity with its signals registered in screech
+    pub fn create_entity(&mut self, entity_type: EntityType) -> Box<dyn Entity> {
+        entity_type.create(&mut self.screech)
+    }
+
+    /// Amount of sources waiting to be freed
+    #[cfg(test)]
+    pub fn get_released_count(&self) -> usize {
+        self.released.len()
+    }
+
+    /// Drop an entity that was taken off the grid, its signals are freed while sampling
+    pub fn release_entity(&mut self, entity: Box<dyn Entity>) {
+        self.released.push(*entity.get_source_id());
     }
 
+    /// Sample all entities in the grid, on failure the error is stored
+    /// on the grid so it can be shown and silence is returned instead
     pub fn sample(&mut self, grid: &mut Grid) -> (&[f32], &[f32]) {
-        let mut sources = vec![];
+        grid.error = self.try_sample(grid).err();
+        self.get_main_out(grid)
+    }
+
+    /// Output of the last sampled buffer, silence when sampling failed
+    pub fn get_main_out(&self, grid: &Grid) -> (&[f32], &[f32]) {
+        match (
+            &grid.error,
+            self.screech.get_main_out("left_out"),
+            self.screech.get_main_out("right_out"),
+        ) {
+            (None, Some(left), Some(right)) => (&left.samples, &right.samples),
+            _ => (&self.silence, &self.silence),
+        }
+    }
+
+    /// Sample the entities in the order of the grid,
+    /// delayed connections read the output of the previous block
+    pub fn try_sample(&mut self, grid: &mut Grid) -> Result<(), Error> {
+        let connected = self.update_connections(grid);
+        if let Err(e) = grid.dispatch_settings(self.time, self.silence.len()) {
+            self.rejected = Some(e);
+        }
+        self.update_voices(grid, connected);
+        let start = self.time;
+        self.time += self.silence.len() as u64;
+        self.tapped.clear();
+
+        // the sources borrow the grid so they are built for every buffer,
+        // with their full capacity up front so they never grow while being built
+        let mut sources = Vec::with_capacity(grid.get_entities().len() + self.copies.len());
+        let mut copies = self.copies.iter_mut().peekable();
+
+        for entity in grid.get_mut_entities() {
+            let id = *entity.get_source_id();
+
+            // the other voices are sampled first so the entity can add them to its outputs
+            while let Some(copy) = copies.next_if(|copy| copy.original == id) {
+                copy.entity.set_clock(&self.clock);
+
+                let mut source = EntitySource::new(copy.entity.as_mut());
+                source.copy = true;
+                sources.push(source);
+            }
+
+            entity.set_clock(&self.clock);
+
+            let position = entity.get_position();
+            let mut source = EntitySource::new(entity.as_mut());
+            source.mix = self
+                .mixed
+                .iter()
+                .find(|(original, _)| *original == id)
+                .map(|(_, mix)| mix.as_slice())
+                .unwrap_or(&[]);
+
+            source.gate = self
+                .watched
+                .iter()
+                .find(|(p, _)| *p == position)
+                .map(|(_, gate)| *gate);
+
+            if source.gate.is_some() {
+                source.gates = self.gate_buffers.pop().unwrap_or_default();
+            }
+
+            // the buffer moves into the source and back to keep its capacity
+            if self.tap == Some(position) {
+                source.tap = Some(std::mem::take(&mut self.tapped));
+            }
+            sources.push(source);
+        }
+
+        let mut release = Release {
+            id: self.release_id,
+            sources: &mut self.released,
+        };
+        let mut refs: Vec<&mut dyn Source> = Vec::with_capacity(sources.len() + 1);
+        refs.extend(
+            sources
+                .iter_mut()
+                .map(|source| source as &mut dyn Source)
+                .chain([&mut release as &mut dyn Source]),
+        );
+
+        let sampled = self.screech.sample(&mut refs);
+
+        if let Err(e) = sampled {
+            return_gate_buffers(&mut self.gate_buffers, &mut sources);
+            return Err(Error::Sampling(format!("{:?}", e)));
+        }
+
+        self.levels.clear();
+        self.levels.extend(
+            sources
+                .iter()
+                .filter(|source| !source.copy)
+                .map(|source| (source.entity.get_position(), source.level)),
+        );
+
+        self.midi.clear();
+        self.gates.clear();
+
+        for source in sources.iter_mut() {
+            let position = source.entity.get_position();
+
+            source.entity.drain_midi(&mut self.events);
+            self.midi.extend(
+                self.events
+                    .drain(..)
+                    .map(|(offset, message)| (start + offset as u64, position, message)),
+            );
+
+            if let Some(gate) = source.gate {
+                for (_, watched) in self.watched.iter_mut().filter(|(p, _)| *p == position) {
+                    *watched = gate;
+                }
+
+                self.gates.extend(
+                    source
+                        .gates
+                        .iter()
+                        .map(|(offset, gate)| (start + *offset as u64, position, *gate)),
+                );
+            }
+        }
+
+        for source in sources.iter_mut() {
+            if let Some(tap) = source.tap.take() {
+                self.tapped = tap;
+            }
+        }
+
+        return_gate_buffers(&mut self.gate_buffers, &mut sources);
+
+        let result = match sources.iter_mut().find(|source| source.result.is_err()) {
+            Some(source) => {
+                let error = std::mem::replace(&mut source.result, Ok(())).unwrap_err();
+                Err(Error::Entity(source.entity.get_position(), Box::new(error)))
+            }
+            None => Ok(()),
+        };
+
+        result
+    }
+
+    /// Apply the connections of the grid to screech, returns if they changed
+    fn update_connections(&mut self, grid: &Grid) -> bool {
+        let connections = grid.get_connections();
+        let unchanged = self.connections.len() == connections.len()
+            && self
+                .connections
+                .iter()
+                .zip(connections.iter())
+                .all(|((output, input), c)| *output == c.output && *input == c.input);
+
+        if unchanged {
+            return false;
+        }
+
+        // initializing an input again clears the outputs connected to it
+        for (_, input) in self.connections.iter() {
+            self.screech
+                .init_input(input.get_source_id(), input.get_signal_id());
+        }
+
+        for c in connections.iter() {
+            self.screech.connect_signal(&c.output, &c.input);
+        }
 
-        for module in grid.get_mut_entities() {
-            sources.push(module.as_mut_source());
+        self.connections = connections.iter().map(|c| (c.output, c.input)).collect();
+        true
+    }
+
+    /// Copy the entities downstream of every voice allocator for its other voices,
+    /// the copies are made again when the connections or the amount of voices change
+    /// and follow the settings of their entity otherwise
+    fn update_voices(&mut self, grid: &Grid, connected: bool) {
+        let voice_counts = grid
+            .get_entities()
+            .iter()
+            .filter_map(|entity| match entity.as_kind() {
+                EntityKind::VoiceAllocator(voices) => {
+                    Some((*voices.get_source_id(), voices.get_voice_count()))
+                }
+                _ => None,
+            });
+
+        if connected || !voice_counts.clone().eq(self.voice_counts.iter().copied()) {
+            self.voice_counts = voice_counts.collect();
+            self.copy_voices(grid);
+        }
+
+        // moving entities can change the order of the grid without changing connections
+        let mut copies = self.copies.iter().peekable();
+        for entity in grid.get_entities() {
+            while copies
+                .next_if(|copy| copy.original == *entity.get_source_id())
+                .is_some()
+            {}
+        }
+
+        if copies.peek().is_some() {
+            let entities = grid.get_entities();
+            self.copies.sort_unstable_by_key(|copy| {
+                let index = entities
+                    .iter()
+                    .position(|entity| *entity.get_source_id() == copy.original);
+                (index, copy.voice)
+            });
+        }
+
+        for copy in self.copies.iter_mut() {
+            let entity = grid
+                .get_entities()
+                .iter()
+                .find(|entity| *entity.get_source_id() == copy.original);
+
+            if let Some(entity) = entity {
+                copy.follow(entity.as_ref(), self.sample_rate);
+            }
+        }
+    }
+
+    /// Release the copies and copy the entities downstream of the allocators again
+    fn copy_voices(&mut self, grid: &Grid) {
+        for copy in self.copies.drain(..) {
+            self.released.push(*copy.entity.get_source_id());
+        }
+        self.mixed.clear();
+
+        let entities = grid.get_entities();
+        let find = |position: Position| entities.iter().find(|e| e.get_position() == position);
+        let mut taken = vec![];
+
+        for allocator in entities.iter() {
+            let voices = match allocator.as_kind() {
+                EntityKind::VoiceAllocator(voices) => voices,
+                _ => continue,
+            };
+            let patch = downstream(grid, allocator.get_position(), &mut taken);
+            let start = self.copies.len();
+
+            for voice in 1..voices.get_voice_count() {
+                let first = self.copies.len();
+
+                for entity in patch.iter().filter_map(|&position| find(position)) {
+                    let copy = self.create_entity(entity.as_kind().get_type());
+                    self.copies
+                        .push(VoiceCopy::new(entity.as_ref(), copy, voice));
+                }
+
+                self.connect_voice(grid, voices, voice, first);
+            }
+
+            // the last entities of the patch carry the sum of all voices
+            let ends = patch.iter().filter(|&&position| {
+                !grid
+                    .get_connections()
+                    .iter()
+                    .any(|c| c.from == position && !c.delayed && patch.contains(&c.to))
+            });
+
+            for entity in ends.filter_map(|&position| find(position)) {
+                let id = *entity.get_source_id();
+                let outputs = entity.as_kind().get_outputs();
+                let mix = self.copies[start..]
+                    .iter()
+                    .filter(|copy| copy.original == id)
+                    .flat_map(|copy| {
+                        outputs.iter().map(|output| {
+                            let copied =
+                                Output::new(*copy.entity.get_source_id(), output.get_signal_id());
+                            (copied, *output)
+                        })
+                    })
+                    .collect();
+
+                self.mixed.push((id, mix));
+            }
+        }
+
+        self.copies.sort_by_key(|copy| {
+            let index = entities
+                .iter()
+                .position(|entity| *entity.get_source_id() == copy.original);
+            (index, copy.voice)
+        });
+    }
+
+    /// Connect the copies of a voice like the grid connects their entities,
+    /// reading this voice of the allocator and the other copies of this voice
+    fn connect_voice(&mut self, grid: &Grid, voices: &VoiceAllocator, voice: usize, first: usize) {
+        let copies = &self.copies[first..];
+
+        for copy in copies.iter() {
+            for side in [
+                Position::LEFT,
+                Position::UP,
+                Position::RIGHT,
+                Position::DOWN,
+            ] {
+                let neighbour = copy.entity.get_position().add(side);
+                let copied = copies.iter().find(|c| c.entity.get_position() == neighbour);
+                let kind = match copied {
+                    Some(c) => c.entity.as_kind(),
+                    None => match grid
+                        .get_entities()
+                        .iter()
+                        .find(|e| e.get_position() == neighbour)
+                    {
+                        Some(entity) => entity.as_kind(),
+                        None => continue,
+                    },
+                };
+
+                for (output, input) in copy.entity.find_connections(&kind, side) {
+                    self.screech
+                        .connect_signal(&voices.voice_output(output, voice), &input);
+                }
+            }
+        }
+    }
+}
+
+/// Keep the gate buffers of the sources for the next buffer
+fn return_gate_buffers(buffers: &mut Vec<Vec<(usize, bool)>>, sources: &mut [EntitySource]) {
+    for source in sources.iter_mut().filter(|source| source.gate.is_some()) {
+        let mut gates = std::mem::take(&mut source.gates);
+        gates.clear();
+        buffers.push(gates);
+    }
+}
+
+/// Entity playing another voice of an entity downstream of a voice allocator
+struct VoiceCopy {
+    /// source id of the entity on the grid
+    original: usize,
+    /// voice of the allocator played by the copy, from 1 as the entity plays the first
+    voice: usize,
+    entity: Box<dyn Entity>,
+    /// settings of the entity last applied to the copy
+    settings: Vec<Setting>,
+    /// current settings of the entity, written before comparing
+    current: Vec<Setting>,
+}
+
+impl VoiceCopy {
+    fn new(entity: &dyn Entity, mut copy: Box<dyn Entity>, voice: usize) -> Self {
+        let settings = entity.get_settings();
+
+        for setting in settings.iter() {
+            copy.update_setting(setting);
+        }
+        copy.set_position(entity.get_position());
+
+        VoiceCopy {
+            original: *entity.get_source_id(),
+            voice,
+            entity: copy,
+            current: settings.clone(),
+            settings,
         }
+    }
+
+    /// Apply the position, the changed settings and the sample of the entity to the copy
+    fn follow(&mut self, entity: &dyn Entity, sample_rate: usize) {
+        self.entity.set_position(entity.get_position());
+        entity.write_settings(&mut self.current);
+
+        for (setting, current) in self.settings.iter_mut().zip(self.current.iter()) {
+            if setting.value != current.value {
+                setting.value = current.value;
+                self.entity.update_setting(setting);
+            }
+        }
+
+        if let (Some(sample), EntityMutKind::Sampler(sampler)) =
+            (entity.as_kind().get_sample(), self.entity.as_mut_kind())
+        {
+            if !sampler.get_sample().is_some_and(|s| Arc::ptr_eq(s, sample)) {
+                sampler.load(sample.clone(), sample_rate);
+            }
+        }
+    }
+}
+
+/// Positions of the entities receiving signals from a voice allocator directly or through
+/// other entities, in the order of the grid. Entities that take every voice stop the search,
+/// entities already reached from another allocator are left to that allocator.
+fn downstream(grid: &Grid, allocator: Position, taken: &mut Vec<Position>) -> Vec<Position> {
+    let takes_voices = |position: Position| {
+        grid.get_entities()
+            .iter()
+            .any(|e| e.get_position() == position && e.as_kind().get_voices().is_some())
+    };
+    let mut reached = vec![allocator];
+    let mut i = 0;
+
+    while i < reached.len() {
+        let from = reached[i];
+
+        for c in grid.get_connections().iter() {
+            if c.from == from
+                && !c.delayed
+                && !reached.contains(&c.to)
+                && !taken.contains(&c.to)
+                && !takes_voices(c.to)
+            {
+                reached.push(c.to);
+            }
+        }
+
+        i += 1;
+    }
+
+    taken.extend_from_slice(&reached[1..]);
+
+    grid.get_entities()
+        .iter()
+        .map(|entity| entity.get_position())
+        .filter(|position| reached[1..].contains(position))
+        .collect()
+}
+
+/// Frees the signals of removed entities, screech only hands out its tracker while sampling
+struct Release<'a> {
+    id: usize,
+    sources: &'a mut Vec<usize>,
+}
+
+impl Source for Release<'_> {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) {
+        for id in self.sources.drain(..) {
+            tracker.clear_source(id);
+        }
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::SettingValue;
+
+    #[test]
+    fn test_release_entity() {
+        let mut audio = Audio::new(4, 4);
+        let mut grid = Grid::new();
+        let position = Position::new(0, 0);
+
+        let step = audio.create_entity(EntityType::Step);
+        grid.add_entity(step, position).unwrap();
+        let trigger = audio.create_entity(EntityType::Trigger);
+        let replaced = grid.add_entity(trigger, position).unwrap().unwrap();
+        audio.release_entity(replaced);
+        assert_eq!(audio.released.len(), 1);
+
+        // the signals of the step are freed while sampling
+        audio.try_sample(&mut grid).unwrap();
+        assert!(audio.released.is_empty());
+    }
+
+    #[test]
+    fn test_voice_copies() {
+        let mut audio = Audio::new(4, 4);
+        let mut grid = Grid::new();
+
+        let voices = audio.create_entity(EntityType::VoiceAllocator);
+        grid.add_entity(voices, Position::new(0, 0)).unwrap();
+        let mut sampler = audio.create_entity(EntityType::Sampler);
+        if let EntityMutKind::Sampler(sampler) = sampler.as_mut_kind() {
+            sampler.load(
+                Arc::new(crate::Sample::new("s", 4, vec![vec![0.1, 0.2, 0.3, 0.4]])),
+                4,
+            );
+        }
+        grid.add_entity(sampler, Position::new(1, 0)).unwrap();
+
+        if let Some(EntityMutKind::VoiceAllocator(voices)) = grid
+            .get_mut_entity(Position::new(0, 0))
+            .map(|entity| entity.as_mut_kind())
+        {
+            voices.note_on(0.0);
+            voices.note_on(0.5);
+        }
+
+        // every voice plays its own copy of the sampler and the voices are summed
+        audio.set_tap(Some(Position::new(1, 0)));
+        audio.try_sample(&mut grid).unwrap();
+        assert_eq!(audio.copies.len(), 3);
+        assert_eq!(audio.get_tap(), &[0.2, 0.4, 0.6, 0.8]);
+
+        grid.get_mut_entity(Position::new(0, 0))
+            .unwrap()
+            .update_setting(&Setting::new(SettingValue::Integer(1), "voices"));
+        audio.try_sample(&mut grid).unwrap();
+        assert!(audio.copies.is_empty());
+    }
+
+    #[test]
+    fn test_gate_buffers() {
+        let mut audio = Audio::new(4, 4);
+        let mut grid = Grid::new();
+
+        let trigger = audio.create_entity(EntityType::Trigger);
+        grid.add_entity(trigger, Position::new(0, 0)).unwrap();
+        audio.watch_gate(Position::new(0, 0), true);
+        audio.try_sample(&mut grid).unwrap();
+        assert!(!audio.get_gates().is_empty());
 
-        self.screech.sample(&mut sources).unwrap();
+        // the gate buffer is handed back empty for the next buffer
+        assert_eq!(audio.gate_buffers.len(), 1);
+        assert!(audio.gate_buffers[0].is_empty() && audio.gate_buffers[0].capacity() >= 1);
 
-        (
-            &self.screech.get_main_out("left_out").unwrap().samples,
-            &self.screech.get_main_out("right_out").unwrap().samples,
-        )
+        audio.try_sample(&mut grid).unwrap();
+        assert_eq!(audio.gate_buffers.len(), 1);
     }
 }
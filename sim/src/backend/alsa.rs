@@ -0,0 +1,159 @@
+use super::{Backend, StreamConfig};
+use crate::Error;
+use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void, CStr, CString};
+use std::ptr;
+
+const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+const SND_PCM_FORMAT_FLOAT_LE: c_int = 14;
+const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+#[repr(C)]
+struct SndPcm {
+    _private: [u8; 0],
+}
+
+#[link(name = "asound")]
+extern "C" {
+    fn snd_pcm_open(
+        pcm: *mut *mut SndPcm,
+        name: *const c_char,
+        stream: c_int,
+        mode: c_int,
+    ) -> c_int;
+    fn snd_pcm_set_params(
+        pcm: *mut SndPcm,
+        format: c_int,
+        access: c_int,
+        channels: c_uint,
+        rate: c_uint,
+        soft_resample: c_int,
+        latency: c_uint,
+    ) -> c_int;
+    fn snd_pcm_get_params(
+        pcm: *mut SndPcm,
+        buffer_size: *mut c_ulong,
+        period_size: *mut c_ulong,
+    ) -> c_int;
+    fn snd_pcm_writei(pcm: *mut SndPcm, buffer: *const c_void, size: c_ulong) -> c_long;
+    fn snd_pcm_recover(pcm: *mut SndPcm, err: c_int, silent: c_int) -> c_int;
+    fn snd_pcm_drain(pcm: *mut SndPcm) -> c_int;
+    fn snd_pcm_close(pcm: *mut SndPcm) -> c_int;
+    fn snd_strerror(errnum: c_int) -> *const c_char;
+}
+
+fn check(result: c_int) -> Result<c_int, Error> {
+    if result < 0 {
+        let message = unsafe { CStr::from_ptr(snd_strerror(result)) };
+        Err(Error::Backend(message.to_string_lossy().into()))
+    } else {
+        Ok(result)
+    }
+}
+
+/// Stereo playback through an ALSA device, PulseAudio and JACK
+/// are reached through their ALSA plugins, for example the "pulse" device
+pub struct AlsaBackend {
+    device: String,
+    pcm: *mut SndPcm,
+    config: Option<StreamConfig>,
+}
+
+// the pcm handle is only used by the thread running the backend
+unsafe impl Send for AlsaBackend {}
+
+impl AlsaBackend {
+    /// Use the named device, "default" picks the system default output
+    pub fn new(device: &str) -> Self {
+        AlsaBackend {
+            device: device.into(),
+            pcm: ptr::null_mut(),
+            config: None,
+        }
+    }
+}
+
+impl Backend for AlsaBackend {
+    fn negotiate(&mut self, requested: StreamConfig) -> Result<StreamConfig, Error> {
+        let name = CString::new(self.device.as_str())
+            .map_err(|_| Error::Backend(format!("invalid device {}", self.device)))?;
+
+        if self.pcm.is_null() {
+            check(unsafe {
+                snd_pcm_open(&mut self.pcm, name.as_ptr(), SND_PCM_STREAM_PLAYBACK, 0)
+            })?;
+        }
+
+        // alsa splits the latency into four periods, one period is rendered per block
+        let latency = (requested.block_size * 4) as f64 / requested.sample_rate as f64;
+
+        check(unsafe {
+            snd_pcm_set_params(
+                self.pcm,
+                SND_PCM_FORMAT_FLOAT_LE,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                2,
+                requested.sample_rate as c_uint,
+                1,
+                (latency * 1_000_000.0) as c_uint,
+            )
+        })?;
+
+        let mut buffer_size = 0;
+        let mut period_size = 0;
+        check(unsafe { snd_pcm_get_params(self.pcm, &mut buffer_size, &mut period_size) })?;
+
+        // soft resampling makes the device accept the requested rate
+        let config = StreamConfig::new(requested.sample_rate, (period_size as usize).max(1));
+        self.config = Some(config);
+
+        Ok(config)
+    }
+
+    fn run(&mut self, render: &mut dyn FnMut(&mut [f32], &mut [f32]) -> bool) -> Result<(), Error> {
+        let config = self
+            .config
+            .ok_or_else(|| Error::Backend("stream was not negotiated".into()))?;
+
+        let mut left = vec![0.0; config.block_size];
+        let mut right = vec![0.0; config.block_size];
+        let mut interleaved = vec![0.0_f32; config.block_size * 2];
+
+        while render(&mut left, &mut right) {
+            for (i, (l, r)) in left.iter().zip(right.iter()).enumerate() {
+                interleaved[i * 2] = *l;
+                interleaved[i * 2 + 1] = *r;
+            }
+
+            let mut offset = 0;
+
+            while offset < config.block_size {
+                let written = unsafe {
+                    snd_pcm_writei(
+                        self.pcm,
+                        interleaved[offset * 2..].as_ptr() as *const c_void,
+                        (config.block_size - offset) as c_ulong,
+                    )
+                };
+
+                if written < 0 {
+                    // recovers from underruns and suspends, other errors stop the stream
+                    check(unsafe { snd_pcm_recover(self.pcm, written as c_int, 1) })?;
+                } else {
+                    offset += written as usize;
+                }
+            }
+        }
+
+        check(unsafe { snd_pcm_drain(self.pcm) })?;
+
+        Ok(())
+    }
+}
+
+impl Drop for AlsaBackend {
+    fn drop(&mut self) {
+        if !self.pcm.is_null() {
+            unsafe { snd_pcm_close(self.pcm) };
+        }
+    }
+}
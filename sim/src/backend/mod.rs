@@ -0,0 +1,139 @@
+#[cfg(feature = "alsa")]
+mod alsa;
+mod null;
+mod wav;
+
+use crate::{Controller, Engine, Error};
+#[cfg(feature = "alsa")]
+pub use alsa::AlsaBackend;
+pub use null::NullBackend;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+pub use wav::WavBackend;
+
+/// Stream parameters requested from a backend and the ones it settled on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamConfig {
+    pub sample_rate: usize,
+    /// amount of frames rendered per block
+    pub block_size: usize,
+}
+
+impl StreamConfig {
+    pub fn new(sample_rate: usize, block_size: usize) -> Self {
+        StreamConfig {
+            sample_rate,
+            block_size,
+        }
+    }
+}
+
+/// Native audio output that asks for stereo blocks of a fixed size
+pub trait Backend {
+    /// Open the device, the returned config may differ from the requested one
+    /// and every block passed to `run` has the returned block size
+    fn negotiate(&mut self, requested: StreamConfig) -> Result<StreamConfig, Error>;
+    /// Render blocks until `render` returns false or the backend finishes,
+    /// blocking the calling thread
+    fn run(&mut self, render: &mut dyn FnMut(&mut [f32], &mut [f32]) -> bool) -> Result<(), Error>;
+}
+
+/// Engine running on its own thread, driven by a backend
+pub struct Stream<B> {
+    config: StreamConfig,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<B, Error>>>,
+}
+
+impl<B: Backend + Send + 'static> Stream<B> {
+    /// Negotiate with the backend and start sampling an engine on a new thread,
+    /// the returned controller edits the engine
+    pub fn start(
+        mut backend: B,
+        requested: StreamConfig,
+        queue_size: usize,
+    ) -> Result<(Self, Controller), Error> {
+        let config = backend.negotiate(requested)?;
+        let (mut engine, controller) =
+            Engine::new(config.sample_rate, config.block_size, queue_size);
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = thread::spawn({
+            let running = running.clone();
+
+            move || {
+                backend.run(&mut |left, right| {
+                    let (l, r) = engine.sample();
+                    left.copy_from_slice(l);
+                    right.copy_from_slice(r);
+
+                    running.load(Ordering::Acquire)
+                })?;
+
+                Ok(backend)
+            }
+        });
+
+        let stream = Stream {
+            config,
+            running,
+            thread: Some(thread),
+        };
+
+        Ok((stream, controller))
+    }
+
+    pub fn get_config(&self) -> StreamConfig {
+        self.config
+    }
+
+    /// Stop sampling and return the backend once its thread finished
+    pub fn stop(self) -> Result<B, Error> {
+        self.running.store(false, Ordering::Release);
+        self.wait()
+    }
+
+    /// Wait for a backend that finishes on its own, like [`WavBackend`]
+    pub fn wait(mut self) -> Result<B, Error> {
+        match self.thread.take().map(|thread| thread.join()) {
+            Some(Ok(result)) => result,
+            _ => Err(Error::Backend("audio thread panicked".into())),
+        }
+    }
+}
+
+impl<B> Drop for Stream<B> {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sample;
+
+    #[test]
+    fn test_stream_to_wav() {
+        let backend = WavBackend::new(vec![], 1000);
+        let (stream, _) = Stream::start(backend, StreamConfig::new(44100, 256), 4).unwrap();
+
+        assert_eq!(stream.get_config(), StreamConfig::new(44100, 256));
+
+        let bytes = stream.wait().unwrap().into_inner();
+        let sample = Sample::from_wav("out", &bytes).unwrap();
+
+        assert_eq!(sample.sample_rate, 44100);
+        assert_eq!(sample.channels.len(), 2);
+        assert_eq!(sample.len(), 1000);
+    }
+
+    #[test]
+    fn test_stream_stop() {
+        let (stream, _) =
+            Stream::start(NullBackend::new(), StreamConfig::new(48000, 64), 4).unwrap();
+
+        assert!(stream.stop().unwrap().get_blocks() > 0);
+    }
+}
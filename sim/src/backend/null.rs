@@ -0,0 +1,65 @@
+use super::{Backend, StreamConfig};
+use crate::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Backend without a device that discards the audio at the pace of real hardware,
+/// used on machines without sound output
+pub struct NullBackend {
+    config: Option<StreamConfig>,
+    /// amount of blocks rendered
+    blocks: usize,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        NullBackend {
+            config: None,
+            blocks: 0,
+        }
+    }
+
+    pub fn get_blocks(&self) -> usize {
+        self.blocks
+    }
+}
+
+impl Default for NullBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for NullBackend {
+    fn negotiate(&mut self, requested: StreamConfig) -> Result<StreamConfig, Error> {
+        let config = StreamConfig::new(requested.sample_rate.max(1), requested.block_size.max(1));
+        self.config = Some(config);
+        Ok(config)
+    }
+
+    fn run(&mut self, render: &mut dyn FnMut(&mut [f32], &mut [f32]) -> bool) -> Result<(), Error> {
+        let config = self
+            .config
+            .ok_or_else(|| Error::Backend("stream was not negotiated".into()))?;
+        let block_duration =
+            Duration::from_secs_f64(config.block_size as f64 / config.sample_rate as f64);
+
+        let mut left = vec![0.0; config.block_size];
+        let mut right = vec![0.0; config.block_size];
+        let start = Instant::now();
+
+        loop {
+            self.blocks += 1;
+
+            if !render(&mut left, &mut right) {
+                return Ok(());
+            }
+
+            // wait until the hardware would have played the rendered blocks
+            let played = block_duration * self.blocks as u32;
+            if let Some(wait) = played.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+    }
+}
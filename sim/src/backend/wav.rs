@@ -0,0 +1,65 @@
+use super::{Backend, StreamConfig};
+use crate::{Error, Sample};
+use std::io::Write;
+
+/// Backend rendering a fixed amount of frames as fast as possible
+/// and writing them to a 32 bit float WAVE file
+pub struct WavBackend<W> {
+    writer: W,
+    frames: usize,
+    config: Option<StreamConfig>,
+}
+
+impl<W: Write> WavBackend<W> {
+    pub fn new(writer: W, frames: usize) -> Self {
+        WavBackend {
+            writer,
+            frames,
+            config: None,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Backend for WavBackend<W> {
+    fn negotiate(&mut self, requested: StreamConfig) -> Result<StreamConfig, Error> {
+        let config = StreamConfig::new(requested.sample_rate.max(1), requested.block_size.max(1));
+        self.config = Some(config);
+        Ok(config)
+    }
+
+    fn run(&mut self, render: &mut dyn FnMut(&mut [f32], &mut [f32]) -> bool) -> Result<(), Error> {
+        let config = self
+            .config
+            .ok_or_else(|| Error::Backend("stream was not negotiated".into()))?;
+
+        let mut left = vec![0.0; config.block_size];
+        let mut right = vec![0.0; config.block_size];
+        let mut channels = vec![
+            Vec::with_capacity(self.frames),
+            Vec::with_capacity(self.frames),
+        ];
+
+        while channels[0].len() < self.frames {
+            let running = render(&mut left, &mut right);
+            let length = config.block_size.min(self.frames - channels[0].len());
+
+            channels[0].extend_from_slice(&left[..length]);
+            channels[1].extend_from_slice(&right[..length]);
+
+            if !running {
+                break;
+            }
+        }
+
+        let sample = Sample::new("output", config.sample_rate, channels);
+
+        self.writer
+            .write_all(&sample.to_wav())
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| Error::Backend(e.to_string()))
+    }
+}
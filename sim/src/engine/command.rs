@@ -0,0 +1,548 @@
+use crate::entity::{EntityType, Setting, SettingValue};
+use crate::grid::{Position, Rect};
+use crate::midi::{MidiMessage, MidiParser};
+use crate::{Error, Sample};
+use std::io::Write;
+use std::sync::Arc;
+
+const ADD_ENTITY: u8 = 0;
+const REMOVE_ENTITY: u8 = 1;
+const UPDATE_SETTING: u8 = 2;
+const SCHEDULE_SETTING: u8 = 3;
+const MIDI: u8 = 4;
+const LEARN_MIDI: u8 = 5;
+const CANCEL_LEARN: u8 = 6;
+const FORGET_MIDI: u8 = 7;
+const PLAY: u8 = 8;
+const STOP: u8 = 9;
+const WATCH_GATE: u8 = 10;
+const SET_TEMPO: u8 = 11;
+const SYNC_TEMPO: u8 = 12;
+const TAP: u8 = 13;
+const MOVE_ENTITIES: u8 = 14;
+const UNDO: u8 = 15;
+const REDO: u8 = 16;
+const BEGIN_GROUP: u8 = 17;
+const END_GROUP: u8 = 18;
+const LOAD_SAMPLE: u8 = 19;
+
+const FLOAT: u8 = 0;
+const INTEGER: u8 = 1;
+const BOOLEAN: u8 = 2;
+
+/// Entity types by their index in encoded messages
+pub(super) const ENTITY_TYPES: [EntityType; 10] = [
+    EntityType::Step,
+    EntityType::Trigger,
+    EntityType::Sampler,
+    EntityType::SampleHold,
+    EntityType::Slew,
+    EntityType::Euclid,
+    EntityType::VoiceAllocator,
+    EntityType::MidiIn,
+    EntityType::MidiOut,
+    EntityType::Voice,
+];
+
+/// Names of the settings of all entity types, decoded settings borrow them
+/// so applying a setting sent to the audio side does not allocate
+const SETTING_NAMES: [&str; 21] = [
+    "bpm", "cap", "channel", "div", "end", "fall", "gate", "glide", "loop", "oneshot", "pitch",
+    "pulses", "rise", "rotation", "start", "steal", "steps", "sync", "velocity", "voice", "voices",
+];
+
+/// Edit of the grid sent from the user interface to the audio side
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// create an entity, replacing the entity at the position
+    AddEntity(Position, EntityType),
+    RemoveEntity(Position),
+    /// change a setting right away
+    UpdateSetting(Position, Setting),
+    /// change a setting at a time in samples, see [`crate::Audio::get_time`]
+    ScheduleSetting(u64, Position, Setting),
+    /// message received from a MIDI input
+    Midi(MidiMessage),
+    /// map the next control change received onto a setting by its name
+    LearnMidi(Position, String),
+    CancelLearn,
+    /// remove the control change mapped onto a setting
+    ForgetMidi(Position, String),
+    /// start or stop sampling the grid, a stopped grid outputs silence
+    Play,
+    Stop,
+    /// report the gates of the primary output of an entity back to the controller
+    WatchGate(Position, bool),
+    /// change the tempo of the transport in beats per minute, keeping the current beat
+    SetTempo(f64),
+    /// follow a shared timeline with a tempo that is at a beat on a time in samples
+    SyncTempo(f64, f64, u64),
+    /// send the primary output of the entity at a position back to the controller
+    Tap(Position),
+    /// move the entities inside a rectangle by an offset, keeping their state
+    MoveEntities(Rect, Position),
+    /// revert the last group of edits
+    Undo,
+    /// repeat the last group of edits that was undone
+    Redo,
+    /// collect the following edits into a group that is undone in one step,
+    /// edits outside of a group are undone one by one
+    BeginGroup,
+    EndGroup,
+    /// play a sample with the sampler at a position, encoded as a WAVE file
+    LoadSample(Position, Arc<Sample>),
+}
+
+impl Command {
+    /// Encode as a little endian message that can be sent between wasm instances
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Command::AddEntity(position, entity_type) => {
+                bytes.push(ADD_ENTITY);
+                encode_position(position, bytes);
+                encode_entity_type(entity_type, bytes);
+            }
+            Command::RemoveEntity(position) => {
+                bytes.push(REMOVE_ENTITY);
+                encode_position(position, bytes);
+            }
+            Command::UpdateSetting(position, setting) => {
+                bytes.push(UPDATE_SETTING);
+                encode_position(position, bytes);
+                encode_setting(setting, bytes);
+            }
+            Command::ScheduleSetting(time, position, setting) => {
+                bytes.push(SCHEDULE_SETTING);
+                bytes.extend_from_slice(&time.to_le_bytes());
+                encode_position(position, bytes);
+                encode_setting(setting, bytes);
+            }
+            Command::Midi(message) => {
+                bytes.push(MIDI);
+                message.encode(bytes);
+            }
+            Command::LearnMidi(position, name) => {
+                bytes.push(LEARN_MIDI);
+                encode_position(position, bytes);
+                encode_name(name, bytes);
+            }
+            Command::CancelLearn => bytes.push(CANCEL_LEARN),
+            Command::ForgetMidi(position, name) => {
+                bytes.push(FORGET_MIDI);
+                encode_position(position, bytes);
+                encode_name(name, bytes);
+            }
+            Command::Play => bytes.push(PLAY),
+            Command::Stop => bytes.push(STOP),
+            Command::WatchGate(position, watch) => {
+                bytes.push(WATCH_GATE);
+                encode_position(position, bytes);
+                bytes.push(*watch as u8);
+            }
+            Command::SetTempo(bpm) => {
+                bytes.push(SET_TEMPO);
+                bytes.extend_from_slice(&bpm.to_le_bytes());
+            }
+            Command::SyncTempo(bpm, beat, time) => {
+                bytes.push(SYNC_TEMPO);
+                bytes.extend_from_slice(&bpm.to_le_bytes());
+                bytes.extend_from_slice(&beat.to_le_bytes());
+                bytes.extend_from_slice(&time.to_le_bytes());
+            }
+            Command::Tap(position) => {
+                bytes.push(TAP);
+                encode_position(position, bytes);
+            }
+            Command::MoveEntities(rect, offset) => {
+                bytes.push(MOVE_ENTITIES);
+                encode_position(&rect.position, bytes);
+                bytes.extend_from_slice(&rect.width.to_le_bytes());
+                bytes.extend_from_slice(&rect.height.to_le_bytes());
+                encode_position(offset, bytes);
+            }
+            Command::Undo => bytes.push(UNDO),
+            Command::Redo => bytes.push(REDO),
+            Command::BeginGroup => bytes.push(BEGIN_GROUP),
+            Command::EndGroup => bytes.push(END_GROUP),
+            Command::LoadSample(position, sample) => {
+                bytes.push(LOAD_SAMPLE);
+                encode_position(position, bytes);
+                encode_name(&sample.name, bytes);
+                bytes.extend_from_slice(&sample.to_wav());
+            }
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader::new(bytes);
+
+        match reader.u8()? {
+            ADD_ENTITY => Ok(Command::AddEntity(
+                reader.position()?,
+                reader.entity_type()?,
+            )),
+            REMOVE_ENTITY => Ok(Command::RemoveEntity(reader.position()?)),
+            UPDATE_SETTING => Ok(Command::UpdateSetting(
+                reader.position()?,
+                reader.setting()?,
+            )),
+            SCHEDULE_SETTING => Ok(Command::ScheduleSetting(
+                u64::from_le_bytes(reader.array()?),
+                reader.position()?,
+                reader.setting()?,
+            )),
+            MIDI => {
+                let mut parser = MidiParser::new();
+                let message = reader.bytes[reader.offset..]
+                    .iter()
+                    .find_map(|&byte| parser.push(byte))
+                    .ok_or_else(|| Error::InvalidMessage("incomplete midi message".into()))?;
+
+                Ok(Command::Midi(message))
+            }
+            LEARN_MIDI => Ok(Command::LearnMidi(reader.position()?, reader.name()?)),
+            CANCEL_LEARN => Ok(Command::CancelLearn),
+            FORGET_MIDI => Ok(Command::ForgetMidi(reader.position()?, reader.name()?)),
+            PLAY => Ok(Command::Play),
+            STOP => Ok(Command::Stop),
+            WATCH_GATE => Ok(Command::WatchGate(reader.position()?, reader.u8()? != 0)),
+            SET_TEMPO => Ok(Command::SetTempo(f64::from_le_bytes(reader.array()?))),
+            SYNC_TEMPO => Ok(Command::SyncTempo(
+                f64::from_le_bytes(reader.array()?),
+                f64::from_le_bytes(reader.array()?),
+                u64::from_le_bytes(reader.array()?),
+            )),
+            TAP => Ok(Command::Tap(reader.position()?)),
+            MOVE_ENTITIES => {
+                let position = reader.position()?;
+                let width = i32::from_le_bytes(reader.array()?);
+                let height = i32::from_le_bytes(reader.array()?);
+
+                Ok(Command::MoveEntities(
+                    Rect::new(width, height, position),
+                    reader.position()?,
+                ))
+            }
+            UNDO => Ok(Command::Undo),
+            REDO => Ok(Command::Redo),
+            BEGIN_GROUP => Ok(Command::BeginGroup),
+            END_GROUP => Ok(Command::EndGroup),
+            LOAD_SAMPLE => {
+                let position = reader.position()?;
+                let name = reader.name()?;
+                let sample = Sample::from_wav(&name, &reader.bytes[reader.offset..])?;
+
+                Ok(Command::LoadSample(position, Arc::new(sample)))
+            }
+            tag => Err(Error::InvalidMessage(format!("unknown command {}", tag))),
+        }
+    }
+}
+
+pub(super) fn encode_position(position: &Position, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&position.x.to_le_bytes());
+    bytes.extend_from_slice(&position.y.to_le_bytes());
+}
+
+pub(super) fn encode_entity_type(entity_type: &EntityType, bytes: &mut Vec<u8>) {
+    bytes.push(
+        ENTITY_TYPES
+            .iter()
+            .position(|t| t == entity_type)
+            .unwrap_or(0) as u8,
+    );
+}
+
+pub(super) fn encode_setting(setting: &Setting, bytes: &mut Vec<u8>) {
+    match setting.value {
+        SettingValue::Float(v) => {
+            bytes.push(FLOAT);
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        SettingValue::Integer(v) => {
+            bytes.push(INTEGER);
+            bytes.extend_from_slice(&(v as u64).to_le_bytes());
+        }
+        SettingValue::Boolean(v) => {
+            bytes.push(BOOLEAN);
+            bytes.push(v as u8);
+        }
+    }
+
+    encode_name(&setting.description, bytes);
+}
+
+pub(super) fn encode_name(name: &str, bytes: &mut Vec<u8>) {
+    let name = name.as_bytes();
+    let length = name.len().min(u8::MAX as usize);
+
+    bytes.push(length as u8);
+    bytes.extend_from_slice(&name[..length]);
+}
+
+pub(super) fn encode_count(count: usize, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+/// Append the text of an error, with its position when an entity failed
+pub(super) fn encode_error(error: &Error, bytes: &mut Vec<u8>) {
+    let error = match error {
+        Error::Entity(position, error) => {
+            bytes.push(1);
+            encode_position(position, bytes);
+            error
+        }
+        error => {
+            bytes.push(0);
+            error
+        }
+    };
+
+    // written in place so the worklet does not format into a new string
+    let start = bytes.len();
+    bytes.extend_from_slice(&[0; 2]);
+    let _ = write!(bytes, "{}", error);
+
+    let length = (bytes.len() - start - 2).min(u16::MAX as usize);
+    bytes.truncate(start + 2 + length);
+    bytes[start..start + 2].copy_from_slice(&(length as u16).to_le_bytes());
+}
+
+/// Cursor over an encoded message
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, offset: 0 }
+    }
+
+    pub(super) fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + length)
+            .ok_or_else(|| Error::InvalidMessage("message is too short".into()))?;
+
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    pub(super) fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(super) fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Amount of values that follow, written by [`encode_count`]
+    pub(super) fn count(&mut self) -> Result<usize, Error> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    pub(super) fn position(&mut self) -> Result<Position, Error> {
+        Ok(Position::new(
+            i32::from_le_bytes(self.array()?),
+            i32::from_le_bytes(self.array()?),
+        ))
+    }
+
+    pub(super) fn entity_type(&mut self) -> Result<EntityType, Error> {
+        let index = self.u8()? as usize;
+
+        ENTITY_TYPES
+            .get(index)
+            .copied()
+            .ok_or_else(|| Error::InvalidMessage(format!("unknown entity {}", index)))
+    }
+
+    pub(super) fn setting(&mut self) -> Result<Setting, Error> {
+        let value = match self.u8()? {
+            FLOAT => SettingValue::Float(f32::from_le_bytes(self.array()?)),
+            INTEGER => SettingValue::Integer(u64::from_le_bytes(self.array()?) as usize),
+            BOOLEAN => SettingValue::Boolean(self.u8()? != 0),
+            tag => return Err(Error::InvalidMessage(format!("unknown value {}", tag))),
+        };
+
+        let name = self.str()?;
+
+        match SETTING_NAMES.iter().find(|n| **n == name) {
+            Some(name) => Ok(Setting::new(value, *name)),
+            None => Ok(Setting::new(value, name.to_string())),
+        }
+    }
+
+    pub(super) fn name(&mut self) -> Result<String, Error> {
+        Ok(self.str()?.into())
+    }
+
+    pub(super) fn str(&mut self) -> Result<&'a str, Error> {
+        let length = self.u8()? as usize;
+
+        std::str::from_utf8(self.take(length)?)
+            .map_err(|_| Error::InvalidMessage("setting name is not utf-8".into()))
+    }
+
+    /// Error written by [`encode_error`], keeping only its text
+    pub(super) fn error(&mut self) -> Result<Error, Error> {
+        let position = match self.u8()? {
+            0 => None,
+            _ => Some(self.position()?),
+        };
+        let length = u16::from_le_bytes(self.array()?) as usize;
+        let text = String::from_utf8_lossy(self.take(length)?).into_owned();
+
+        Ok(match position {
+            Some(position) => Error::Entity(position, Box::new(Error::Worklet(text))),
+            None => Error::Worklet(text),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_setting_names() {
+        for entity_type in ENTITY_TYPES {
+            for setting in entity_type.default_settings() {
+                assert!(SETTING_NAMES.contains(&setting.description.as_ref()));
+            }
+        }
+
+        // known names are borrowed so decoding them does not allocate
+        let setting = Setting::new(SettingValue::Float(90.0), String::from("bpm"));
+        assert!(matches!(
+            round_trip(Command::UpdateSetting(Position::origin(), setting)),
+            Command::UpdateSetting(
+                _,
+                Setting {
+                    description: Cow::Borrowed("bpm"),
+                    ..
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn test_error() {
+        let error = Error::Entity(
+            Position::new(2, -1),
+            Box::new(Error::InvalidSetting("pulses".into(), "99".into())),
+        );
+        let mut bytes = vec![];
+        encode_error(&error, &mut bytes);
+
+        // only the text is kept, with the position of a failing entity
+        match Reader::new(&bytes).error().unwrap() {
+            Error::Entity(position, e) => {
+                assert_eq!(position, Position::new(2, -1));
+                assert!(matches!(*e, Error::Worklet(_)));
+                assert_eq!(e.to_string(), "invalid value 99 for pulses");
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    fn round_trip(command: Command) -> Command {
+        let mut bytes = vec![];
+        command.encode(&mut bytes);
+        Command::decode(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert!(matches!(
+            round_trip(Command::AddEntity(Position::new(-3, 4), EntityType::Euclid)),
+            Command::AddEntity(p, EntityType::Euclid) if p == Position::new(-3, 4)
+        ));
+
+        assert!(matches!(
+            round_trip(Command::RemoveEntity(Position::new(1, 2))),
+            Command::RemoveEntity(p) if p == Position::new(1, 2)
+        ));
+
+        match round_trip(Command::ScheduleSetting(
+            48000,
+            Position::new(0, 0),
+            Setting::new(SettingValue::Integer(5), "pulses"),
+        )) {
+            Command::ScheduleSetting(time, _, setting) => {
+                assert_eq!(time, 48000);
+                assert_eq!(setting.description, "pulses");
+                assert!(matches!(setting.value, SettingValue::Integer(5)));
+            }
+            _ => panic!("expected a scheduled setting"),
+        }
+
+        let message = MidiMessage::NoteOn {
+            channel: 3,
+            note: 64,
+            velocity: 80,
+        };
+        assert!(matches!(round_trip(Command::Midi(message)), Command::Midi(m) if m == message));
+
+        assert!(matches!(
+            round_trip(Command::LearnMidi(Position::new(2, 1), "pitch".into())),
+            Command::LearnMidi(p, name) if p == Position::new(2, 1) && name == "pitch"
+        ));
+
+        assert!(matches!(
+            round_trip(Command::WatchGate(Position::new(5, 6), true)),
+            Command::WatchGate(p, true) if p == Position::new(5, 6)
+        ));
+
+        assert!(matches!(
+            round_trip(Command::SyncTempo(128.0, 7.5, 96000)),
+            Command::SyncTempo(bpm, beat, 96000) if bpm == 128.0 && beat == 7.5
+        ));
+
+        assert!(matches!(
+            round_trip(Command::Tap(Position::new(-3, 2))),
+            Command::Tap(p) if p == Position::new(-3, 2)
+        ));
+
+        assert!(matches!(
+            round_trip(Command::MoveEntities(
+                Rect::new(2, 3, Position::new(-1, 4)),
+                Position::new(5, -6)
+            )),
+            Command::MoveEntities(r, o)
+                if r.position == Position::new(-1, 4)
+                    && (r.width, r.height) == (2, 3)
+                    && o == Position::new(5, -6)
+        ));
+
+        assert!(matches!(round_trip(Command::Undo), Command::Undo));
+        assert!(matches!(round_trip(Command::Redo), Command::Redo));
+        assert!(matches!(
+            round_trip(Command::BeginGroup),
+            Command::BeginGroup
+        ));
+        assert!(matches!(round_trip(Command::EndGroup), Command::EndGroup));
+
+        let sample = Arc::new(Sample::new("kick", 48000, vec![vec![0.5, -0.5]]));
+        match round_trip(Command::LoadSample(Position::new(1, 1), sample.clone())) {
+            Command::LoadSample(p, decoded) => {
+                assert_eq!(p, Position::new(1, 1));
+                assert_eq!(decoded, sample);
+            }
+            _ => panic!("expected a sample"),
+        }
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(Command::decode(&[]).is_err());
+        assert!(Command::decode(&[42]).is_err());
+        assert!(Command::decode(&[MIDI, 0x90, 60]).is_err());
+        assert!(Command::decode(&[ADD_ENTITY, 0, 0]).is_err());
+        assert!(Command::decode(&[ADD_ENTITY, 0, 0, 0, 0, 0, 0, 0, 0, 42]).is_err());
+    }
+}
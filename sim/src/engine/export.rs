@@ -0,0 +1,162 @@
+use super::Engine;
+use crate::entity::{EntityKind, EntityMutKind};
+use crate::grid::{Grid, Position};
+use crate::midi::{MidiFile, MidiMessage, MidiTrack};
+use crate::transport::Transport;
+use crate::{Audio, Error};
+
+/// Ticks per quarter note in exported files
+const DIVISION: u16 = 480;
+
+impl Engine {
+    /// Render a copy of the patch for a number of 4/4 bars and record the notes of every
+    /// [`crate::entity::MidiOut`] into its own track, ordered by row.
+    /// The copy starts from the first beat, the running patch is left untouched.
+    pub fn export_midi(&self, bars: usize, bpm: f32) -> Result<MidiFile, Error> {
+        let sample_rate = self.audio.get_sample_rate();
+        let mut audio = Audio::new(sample_rate, self.audio.get_buffer_size());
+        let mut grid = self.copy_grid(&mut audio)?;
+        let mut transport = Transport::new(bpm as f64);
+
+        let samples_per_tick = sample_rate as f64 * 60.0 / (bpm.max(1.0) as f64 * DIVISION as f64);
+        let end_tick = bars as u64 * 4 * DIVISION as u64;
+        let end = (end_tick as f64 * samples_per_tick).round() as u64;
+
+        let mut positions: Vec<Position> = grid
+            .get_entities()
+            .iter()
+            .filter(|entity| matches!(entity.as_kind(), EntityKind::MidiOut(_)))
+            .map(|entity| entity.get_position())
+            .collect();
+        positions.sort_by_key(|p| (p.y, p.x));
+
+        let mut file = MidiFile::new(DIVISION, bpm);
+        file.tracks = positions
+            .iter()
+            .map(|p| MidiTrack::new(&format!("{},{}", p.x, p.y)))
+            .collect();
+
+        while audio.get_time() < end {
+            audio.set_clock(transport.clock);
+            audio.try_sample(&mut grid)?;
+            transport.advance(audio.get_buffer_size(), sample_rate);
+
+            for (time, position, message) in audio.get_midi() {
+                let track = positions.iter().position(|p| p == position);
+
+                if let (Some(track), true) = (track, *time < end) {
+                    let tick = (*time as f64 / samples_per_tick) as u64;
+                    file.tracks[track].events.push((tick, *message));
+                }
+            }
+        }
+
+        for track in file.tracks.iter_mut() {
+            release_notes(track, end_tick);
+        }
+
+        Ok(file)
+    }
+
+    /// Entities of the grid created again in another audio with the same settings
+    fn copy_grid(&self, audio: &mut Audio) -> Result<Grid, Error> {
+        let mut grid = Grid::new();
+
+        for entity in self.grid.get_entities() {
+            let mut copy = audio.create_entity(entity.as_kind().get_type());
+
+            for setting in entity.get_settings() {
+                copy.update_setting(&setting);
+            }
+
+            if let (EntityKind::Sampler(sampler), EntityMutKind::Sampler(copy)) =
+                (entity.as_kind(), copy.as_mut_kind())
+            {
+                if let Some(sample) = sampler.get_sample() {
+                    copy.load(sample.clone(), audio.get_sample_rate());
+                }
+            }
+
+            grid.add_entity(copy, entity.get_position())?;
+        }
+
+        Ok(grid)
+    }
+}
+
+/// End the notes that are still playing at the end of the export
+fn release_notes(track: &mut MidiTrack, tick: u64) {
+    let mut playing = vec![];
+
+    for (_, message) in track.events.iter() {
+        match *message {
+            MidiMessage::NoteOn { channel, note, .. } => playing.push((channel, note)),
+            MidiMessage::NoteOff { channel, note, .. } => {
+                playing.retain(|&playing| playing != (channel, note))
+            }
+            _ => (),
+        }
+    }
+
+    track
+        .events
+        .extend(playing.into_iter().map(|(channel, note)| {
+            (
+                tick,
+                MidiMessage::NoteOff {
+                    channel,
+                    note,
+                    velocity: 0,
+                },
+            )
+        }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Command;
+    use crate::entity::EntityType;
+
+    #[test]
+    fn test_export_midi() {
+        let (mut engine, mut controller) = Engine::new(1000, 100, 8);
+
+        controller
+            .send(Command::AddEntity(Position::new(0, 0), EntityType::Trigger))
+            .unwrap();
+        controller
+            .send(Command::AddEntity(Position::new(0, 1), EntityType::MidiOut))
+            .unwrap();
+        controller
+            .send(Command::AddEntity(Position::new(3, 0), EntityType::MidiOut))
+            .unwrap();
+
+        engine.sample();
+        controller.receive_midi();
+        let time = engine.audio.get_time();
+
+        let file = engine.export_midi(1, 120.0).unwrap();
+
+        // the running patch did not move or send anything
+        assert_eq!(engine.audio.get_time(), time);
+        assert!(controller.receive_midi().is_empty());
+
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tracks[0].name, "3,0");
+        assert!(file.tracks[0].events.is_empty());
+
+        let events = &file.tracks[1].events;
+        let count = |on: bool| {
+            events
+                .iter()
+                .filter(|(_, m)| matches!(m, MidiMessage::NoteOn { .. }) == on)
+                .count()
+        };
+
+        assert!(count(true) > 0);
+        assert_eq!(count(true), count(false));
+        assert!(events.iter().all(|(tick, _)| *tick <= 1920));
+        assert_eq!(&file.to_bytes()[..4], b"MThd");
+    }
+}
@@ -0,0 +1,140 @@
+use crate::entity::{Entity, Setting};
+use crate::grid::{Position, Rect};
+use crate::midi::MidiMapping;
+
+/// Entity taken off the grid with the MIDI mappings and gate watch set up for it,
+/// kept whole so undoing a removal does not create a new entity
+pub struct Removed {
+    pub entity: Box<dyn Entity>,
+    pub mappings: Vec<MidiMapping>,
+    pub watched: bool,
+}
+
+/// Change of the grid that reverts an edit
+pub enum Edit {
+    /// put an entity back at a position, `None` clears the position
+    Place(Position, Option<Removed>),
+    /// change a setting back right away
+    Setting(Position, Setting),
+    /// move the entities inside a rectangle by an offset
+    Move(Rect, Position),
+}
+
+/// Edits of the grid that can be undone and redone, stored as the changes
+/// that revert them. Every edit is undone on its own, unless it is made
+/// between the start and the end of a group.
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// changes reverting the edits of the open group, latest edit first
+    pending: Vec<Edit>,
+    /// a group is open and collects the edits until it ends
+    grouping: bool,
+}
+
+impl History {
+    /// Keep the changes reverting an edit, returning the changes that can no
+    /// longer be redone because of the new edit
+    pub fn record(&mut self, mut revert: Vec<Edit>) -> Vec<Edit> {
+        if revert.is_empty() {
+            return vec![];
+        }
+
+        revert.append(&mut self.pending);
+        self.pending = revert;
+
+        if !self.grouping {
+            self.close();
+        }
+
+        self.redo.drain(..).flatten().collect()
+    }
+
+    /// Collect the following edits into one group
+    pub fn begin_group(&mut self) {
+        self.close();
+        self.grouping = true;
+    }
+
+    pub fn end_group(&mut self) {
+        self.grouping = false;
+        self.close();
+    }
+
+    /// Changes reverting the last group of edits, ending an open group
+    pub fn take_undo(&mut self) -> Option<Vec<Edit>> {
+        self.end_group();
+        self.undo.pop()
+    }
+
+    /// Changes repeating the last group of edits that was undone, ending an open group
+    pub fn take_redo(&mut self) -> Option<Vec<Edit>> {
+        self.end_group();
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, group: Vec<Edit>) {
+        if !group.is_empty() {
+            self.undo.push(group);
+        }
+    }
+
+    pub fn push_redo(&mut self, group: Vec<Edit>) {
+        if !group.is_empty() {
+            self.redo.push(group);
+        }
+    }
+
+    fn close(&mut self) {
+        if !self.pending.is_empty() {
+            self.undo.push(std::mem::take(&mut self.pending));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(x: i32) -> Vec<Edit> {
+        vec![Edit::Place(Position::new(x, 0), None)]
+    }
+
+    fn positions(group: Option<Vec<Edit>>) -> Vec<i32> {
+        group
+            .unwrap_or_default()
+            .iter()
+            .map(|edit| match edit {
+                Edit::Place(position, _) => position.x,
+                _ => -1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut history = History::default();
+
+        history.record(clear(0));
+        history.begin_group();
+        history.record(clear(1));
+        history.record(clear(2));
+        history.end_group();
+        history.end_group();
+        history.record(vec![]);
+        history.begin_group();
+        history.record(clear(3));
+
+        // the open group ends, later edits are reverted first
+        assert_eq!(positions(history.take_undo()), vec![3]);
+        assert_eq!(positions(history.take_undo()), vec![2, 1]);
+        assert_eq!(positions(history.take_undo()), vec![0]);
+        assert!(history.take_undo().is_none());
+
+        history.push_redo(clear(4));
+        assert!(history.record(vec![]).is_empty());
+        assert_eq!(history.record(clear(5)).len(), 1);
+        assert!(history.take_redo().is_none());
+    }
+}
@@ -0,0 +1,75 @@
+/// Level of a channel of the main output over the buffers since the previous snapshot
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Meter {
+    pub peak: f32,
+    pub rms: f32,
+    /// a sample went past full scale
+    pub clipped: bool,
+}
+
+/// Collects the samples of a channel until the next snapshot takes the meter
+#[derive(Debug, Default)]
+pub struct MeterAccumulator {
+    peak: f32,
+    squares: f64,
+    count: usize,
+    clipped: bool,
+}
+
+impl MeterAccumulator {
+    pub fn add(&mut self, samples: &[f32]) {
+        for s in samples {
+            self.peak = self.peak.max(s.abs());
+            self.squares += (*s as f64) * (*s as f64);
+            self.clipped |= s.abs() > 1.0;
+        }
+
+        self.count += samples.len();
+    }
+
+    /// Level since the last call, starting over afterwards
+    pub fn take(&mut self) -> Meter {
+        let rms = if self.count > 0 {
+            (self.squares / self.count as f64).sqrt() as f32
+        } else {
+            0.0
+        };
+        let meter = Meter {
+            peak: self.peak,
+            rms,
+            clipped: self.clipped,
+        };
+
+        *self = MeterAccumulator::default();
+        meter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter() {
+        let mut accumulator = MeterAccumulator::default();
+        accumulator.add(&[0.5, -0.5]);
+        accumulator.add(&[-0.5, 0.5]);
+
+        assert_eq!(
+            accumulator.take(),
+            Meter {
+                peak: 0.5,
+                rms: 0.5,
+                clipped: false
+            }
+        );
+
+        // full scale itself does not clip
+        accumulator.add(&[1.0, -1.0]);
+        assert!(!accumulator.take().clipped);
+
+        accumulator.add(&[0.0, -1.2]);
+        assert!(accumulator.take().clipped);
+        assert_eq!(accumulator.take(), Meter::default());
+    }
+}
@@ -0,0 +1,1014 @@
+mod command;
+mod export;
+mod history;
+mod meter;
+mod notes;
+mod queue;
+mod relay;
+mod ring;
+mod snapshot;
+mod worklet;
+
+use crate::entity::{Entity, EntityMutKind};
+use crate::grid::{Grid, Position, Rect};
+use crate::midi::{MidiMapping, MidiMessage};
+use crate::transport::Transport;
+use crate::{Audio, Color, Error, Image};
+pub use command::Command;
+use history::{Edit, History, Removed};
+pub use meter::Meter;
+use meter::MeterAccumulator;
+use notes::NoteSender;
+use queue::{channel, Receiver, Sender};
+pub use relay::Relay;
+pub use ring::{MessageRing, RingStorage, MAX_MESSAGE_SIZE};
+pub use snapshot::{EntitySnapshot, Snapshot};
+use std::collections::HashMap;
+pub use worklet::{Worklet, RENDER_QUANTUM};
+
+/// Samples of the tapped output that can be queued before the controller reads them
+const TAP_SIZE: usize = 16384;
+
+/// Audio side of the sim, owns the grid and applies the commands sent by a [`Controller`]
+pub struct Engine {
+    audio: Audio,
+    grid: Grid,
+    commands: Receiver<Command>,
+    snapshots: Sender<Snapshot>,
+    /// snapshot handed back by the controller, filled again and sent once it arrives
+    spare: Receiver<Snapshot>,
+    /// last command that could not be applied, kept until it is sent with a snapshot
+    error: Option<Error>,
+    /// setting the next control change received is mapped onto
+    learning: Option<(Position, String)>,
+    mappings: Vec<MidiMapping>,
+    /// messages sent by entities with their time in samples
+    midi_out: NoteSender,
+    /// gates of watched entities with their time in samples
+    gates: Sender<(u64, Position, bool)>,
+    /// primary output of the tapped entity
+    tap: Sender<f32>,
+    /// the grid is only sampled while playing
+    transport: Transport,
+    /// peak of the primary output of each entity since the last snapshot
+    levels: HashMap<Position, f32>,
+    /// left and right channel of the main output since the last snapshot
+    meters: [MeterAccumulator; 2],
+    /// edits of the grid that can be undone
+    history: History,
+}
+
+/// User interface side of the sim, edits the grid through an [`Engine`]
+/// and keeps the last snapshot it received for rendering
+pub struct Controller {
+    pub cursor_position: Position,
+    pub window_position: Position,
+    commands: Sender<Command>,
+    snapshots: Receiver<Snapshot>,
+    snapshot: Snapshot,
+    /// hands the replaced snapshot back to the engine to be filled again
+    spare: Sender<Snapshot>,
+    /// last command the engine could not apply, until it is taken
+    error: Option<Error>,
+    midi_out: Receiver<(u64, MidiMessage)>,
+    gates: Receiver<(u64, Position, bool)>,
+    tap: Receiver<f32>,
+}
+
+/// Engine side of the queues shared with a [`Controller`]
+struct Link {
+    commands: Receiver<Command>,
+    snapshots: Sender<Snapshot>,
+    spare: Receiver<Snapshot>,
+    midi_out: Sender<(u64, MidiMessage)>,
+    gates: Sender<(u64, Position, bool)>,
+    tap: Sender<f32>,
+}
+
+/// Create a controller and the queues an engine, or a [`Relay`] standing in
+/// for it, uses to talk to the controller
+fn link(queue_size: usize) -> (Link, Controller) {
+    let (command_sender, command_receiver) = channel(queue_size);
+    let (snapshot_sender, snapshot_receiver) = channel(1);
+    let (mut spare_sender, spare_receiver) = channel(1);
+    let (midi_sender, midi_receiver) = channel(queue_size);
+    let (gate_sender, gate_receiver) = channel(queue_size);
+    let (tap_sender, tap_receiver) = channel(TAP_SIZE);
+
+    // the engine fills one snapshot while the controller renders the other
+    let _ = spare_sender.push(Snapshot::default());
+
+    let link = Link {
+        commands: command_receiver,
+        snapshots: snapshot_sender,
+        spare: spare_receiver,
+        midi_out: midi_sender,
+        gates: gate_sender,
+        tap: tap_sender,
+    };
+
+    let controller = Controller {
+        cursor_position: Position::origin(),
+        window_position: Position::new(-8, -4),
+        commands: command_sender,
+        snapshots: snapshot_receiver,
+        snapshot: Snapshot::default(),
+        spare: spare_sender,
+        error: None,
+        midi_out: midi_receiver,
+        gates: gate_receiver,
+        tap: tap_receiver,
+    };
+
+    (link, controller)
+}
+
+impl Engine {
+    /// Create an engine and its controller, `queue_size` limits the amount
+    /// of commands that can be sent before the engine samples again
+    pub fn new(sample_rate: usize, buffer_size: usize, queue_size: usize) -> (Engine, Controller) {
+        let (link, controller) = link(queue_size);
+
+        let engine = Engine {
+            audio: Audio::new(sample_rate, buffer_size),
+            grid: Grid::new(),
+            commands: link.commands,
+            snapshots: link.snapshots,
+            spare: link.spare,
+            error: None,
+            learning: None,
+            mappings: vec![],
+            midi_out: NoteSender::new(link.midi_out),
+            gates: link.gates,
+            tap: link.tap,
+            transport: Transport::new(120.0),
+            levels: HashMap::new(),
+            meters: Default::default(),
+            history: History::default(),
+        };
+
+        (engine, controller)
+    }
+
+    /// Apply the queued commands and sample the next buffer,
+    /// a snapshot is sent back once the controller handed back the previous one
+    pub fn sample(&mut self) -> (&[f32], &[f32]) {
+        while let Some(command) = self.commands.pop() {
+            self.apply(command);
+        }
+
+        if self.transport.playing {
+            self.audio.set_clock(self.transport.clock);
+            self.grid.error = self.audio.try_sample(&mut self.grid).err();
+            if let Some(error) = self.audio.take_rejected() {
+                self.error = Some(error);
+            }
+            self.transport
+                .advance(self.audio.get_buffer_size(), self.audio.get_sample_rate());
+
+            // events are dropped while the controller is not reading them
+            for (time, _, message) in self.audio.get_midi() {
+                self.midi_out.send(*time, *message);
+            }
+
+            for gate in self.audio.get_gates() {
+                let _ = self.gates.push(*gate);
+            }
+
+            for sample in self.audio.get_tap() {
+                let _ = self.tap.push(*sample);
+            }
+
+            let (left, right) = self.audio.get_main_out(&self.grid);
+            self.meters[0].add(left);
+            self.meters[1].add(right);
+
+            // hold the peaks so gates shorter than a snapshot are not missed
+            for &(position, level) in self.audio.get_levels() {
+                let peak = self.levels.entry(position).or_insert(0.0);
+                *peak = peak.max(level);
+            }
+        }
+
+        if let Some(mut snapshot) = self.spare.pop() {
+            snapshot.update(&self.grid, self.audio.get_time(), &self.levels);
+            self.levels.clear();
+
+            snapshot.command_error = self.error.take();
+            snapshot.sample_rate = self.audio.get_sample_rate();
+            snapshot.learning.clone_from(&self.learning);
+            snapshot.mappings.clone_from(&self.mappings);
+            snapshot.playing = self.transport.playing;
+            snapshot.clock = self.transport.clock;
+            snapshot.watched.clear();
+            snapshot.watched.extend(self.audio.get_watched());
+            snapshot.meters = [self.meters[0].take(), self.meters[1].take()];
+
+            let _ = self.snapshots.push(snapshot);
+        }
+
+        if self.transport.playing {
+            self.audio.get_main_out(&self.grid)
+        } else {
+            self.audio.get_silence()
+        }
+    }
+
+    /// Limit the amount of entities on the grid, `None` for no limit
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.grid.set_capacity(capacity);
+    }
+
+    /// Apply a command right away, for hosts that receive commands
+    /// through another channel than the [`Controller`]
+    pub fn apply(&mut self, command: Command) {
+        if let Err(error) = self.try_apply(command) {
+            self.error = Some(error);
+        }
+    }
+
+    fn try_apply(&mut self, command: Command) -> Result<(), Error> {
+        match command {
+            Command::AddEntity(position, entity_type) => {
+                // a full grid is checked first so no signals are made for nothing
+                self.grid.check_capacity(position)?;
+                let removed = Removed {
+                    entity: self.audio.create_entity(entity_type),
+                    mappings: vec![],
+                    watched: false,
+                };
+                self.record(Edit::Place(position, Some(removed)))?;
+            }
+            Command::RemoveEntity(position) => self.record(Edit::Place(position, None))?,
+            Command::UpdateSetting(position, setting) => {
+                self.record(Edit::Setting(position, setting))?;
+            }
+            Command::MoveEntities(rect, offset) => self.record(Edit::Move(rect, offset))?,
+            Command::Undo => {
+                if let Some(edits) = self.history.take_undo() {
+                    let (redo, result) = self.replay(edits);
+                    self.history.push_redo(redo);
+                    result?;
+                }
+            }
+            Command::Redo => {
+                if let Some(edits) = self.history.take_redo() {
+                    let (undo, result) = self.replay(edits);
+                    self.history.push_undo(undo);
+                    result?;
+                }
+            }
+            Command::BeginGroup => self.history.begin_group(),
+            Command::EndGroup => self.history.end_group(),
+            Command::ScheduleSetting(time, position, setting) => {
+                self.grid.schedule_setting(time, position, setting)?;
+            }
+            Command::Midi(message) => self.handle_midi(message),
+            Command::LearnMidi(position, setting) => {
+                self.learning = Some((position, setting));
+            }
+            Command::CancelLearn => {
+                self.learning = None;
+            }
+            Command::ForgetMidi(position, setting) => {
+                self.mappings
+                    .retain(|m| m.position != position || m.setting != setting);
+            }
+            Command::Play => {
+                self.transport.playing = true;
+            }
+            Command::Stop => {
+                self.transport.playing = false;
+
+                for entity in self.grid.get_mut_entities() {
+                    release_note(entity.as_mut(), self.audio.get_time(), &mut self.midi_out);
+                }
+            }
+            Command::WatchGate(position, watch) => {
+                self.audio.watch_gate(position, watch);
+            }
+            Command::SetTempo(bpm) => {
+                self.transport.set_bpm(bpm);
+            }
+            Command::SyncTempo(bpm, beat, time) => {
+                let now = self.audio.get_time();
+                let sample_rate = self.audio.get_sample_rate();
+                self.transport.align(bpm, beat, time, now, sample_rate);
+            }
+            Command::Tap(position) => {
+                self.audio.set_tap(Some(position));
+            }
+            Command::LoadSample(position, sample) => {
+                let sample_rate = self.audio.get_sample_rate();
+
+                if let Some(entity) = self.grid.get_mut_entity(position) {
+                    if let EntityMutKind::Sampler(sampler) = entity.as_mut_kind() {
+                        sampler.load(sample, sample_rate);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Change the grid and keep the change reverting it in the history
+    fn record(&mut self, edit: Edit) -> Result<(), Error> {
+        let revert = self.edit(edit)?;
+        let discarded = self.history.record(revert);
+        self.release(discarded);
+        Ok(())
+    }
+
+    /// Change the grid, returning the changes that revert it
+    fn edit(&mut self, edit: Edit) -> Result<Vec<Edit>, Error> {
+        match edit {
+            Edit::Place(position, Some(removed)) => {
+                if let Err(e) = self.grid.check_capacity(position) {
+                    self.audio.release_entity(removed.entity);
+                    return Err(e);
+                }
+
+                let replaced = self.grid.add_entity(removed.entity, position)?;
+                let replaced = replaced.map(|entity| self.detach(entity));
+
+                // set up again what was set up for the entity before it was removed
+                self.mappings.extend(removed.mappings);
+                self.audio.watch_gate(position, removed.watched);
+
+                Ok(vec![Edit::Place(position, replaced)])
+            }
+            Edit::Place(position, None) => Ok(self
+                .grid
+                .remove_entity(position)
+                .map(|entity| vec![Edit::Place(position, Some(self.detach(entity)))])
+                .unwrap_or_default()),
+            Edit::Setting(position, mut setting) => {
+                let entity = match self.grid.get_mut_entity(position) {
+                    Some(entity) => entity,
+                    None => return Ok(vec![]),
+                };
+                let previous = entity
+                    .get_settings()
+                    .into_iter()
+                    .find(|s| s.description == setting.description);
+
+                // applied right away so the next edit sees the new value
+                entity.update_setting(&setting);
+
+                Ok(match previous {
+                    Some(previous) => {
+                        setting.value = previous.value;
+                        vec![Edit::Setting(position, setting)]
+                    }
+                    None => vec![],
+                })
+            }
+            Edit::Move(rect, offset) => {
+                let destination = Rect::new(rect.width, rect.height, rect.position.add(offset));
+                let replaced = self.grid.move_entities(&rect, offset);
+
+                // move the block back, then put back what it was moved onto
+                let mut revert = vec![Edit::Move(destination, offset.invert())];
+                for entity in replaced {
+                    let position = entity.get_position();
+                    revert.push(Edit::Place(position, Some(self.detach(entity))));
+                }
+
+                // mappings and watched gates follow the entities they were set up for
+                for mapping in self.mappings.iter_mut() {
+                    if rect.intersect_position(mapping.position) {
+                        mapping.position = mapping.position.add(offset);
+                    }
+                }
+                self.audio.move_watched(&rect, offset);
+
+                Ok(revert)
+            }
+        }
+    }
+
+    /// Take the MIDI mappings and the gate watch of an entity taken off the grid along with it
+    fn detach(&mut self, mut entity: Box<dyn Entity>) -> Removed {
+        let position = entity.get_position();
+        release_note(entity.as_mut(), self.audio.get_time(), &mut self.midi_out);
+        let watched = self.audio.is_watched(position);
+        self.audio.watch_gate(position, false);
+
+        Removed {
+            entity,
+            mappings: self
+                .mappings
+                .extract_if(.., |m| m.position == position)
+                .collect(),
+            watched,
+        }
+    }
+
+    /// Free the entities kept by changes that were dropped from the history
+    fn release(&mut self, edits: Vec<Edit>) {
+        for edit in edits {
+            if let Edit::Place(_, Some(removed)) = edit {
+                self.audio.release_entity(removed.entity);
+            }
+        }
+    }
+
+    /// Apply changes taken from the history, returning the changes that revert them
+    fn replay(&mut self, edits: Vec<Edit>) -> (Vec<Edit>, Result<(), Error>) {
+        let mut reverted = vec![];
+        let mut result = Ok(());
+
+        for edit in edits {
+            match self.edit(edit) {
+                Ok(mut revert) => {
+                    revert.append(&mut reverted);
+                    reverted = revert;
+                }
+                Err(e) => result = Err(e),
+            }
+        }
+
+        (reverted, result)
+    }
+
+    /// Notes go to every entity, control changes update the settings mapped onto them
+    fn handle_midi(&mut self, message: MidiMessage) {
+        let (channel, controller, value) = match message {
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (channel, controller, value),
+            _ => {
+                for entity in self.grid.get_mut_entities() {
+                    entity.handle_midi(&message);
+                }
+
+                return;
+            }
+        };
+
+        if let Some((position, setting)) = self.learning.take() {
+            // a controller and a setting are only mapped once
+            self.mappings.retain(|m| {
+                !m.matches(channel, controller) && (m.position != position || m.setting != setting)
+            });
+            self.mappings.push(MidiMapping {
+                channel,
+                controller,
+                position,
+                setting,
+            });
+        }
+
+        for mapping in self
+            .mappings
+            .iter()
+            .filter(|m| m.matches(channel, controller))
+        {
+            let setting = self
+                .grid
+                .get_entities()
+                .iter()
+                .find(|e| e.get_position() == mapping.position)
+                .and_then(|e| {
+                    e.get_settings()
+                        .into_iter()
+                        .find(|s| s.description == mapping.setting)
+                });
+
+            if let Some(setting) = setting {
+                let setting = setting.with_normalized(value as f32 / 127.0);
+                if let Err(error) = self.grid.schedule_setting(0, mapping.position, setting) {
+                    self.error = Some(error);
+                }
+            }
+        }
+    }
+}
+
+/// End the note an entity is playing, for entities that stop being sampled
+fn release_note(entity: &mut dyn Entity, time: u64, notes: &mut NoteSender) {
+    if let EntityMutKind::MidiOut(midi_out) = entity.as_mut_kind() {
+        if let Some(message) = midi_out.release_note() {
+            notes.send(time, message);
+        }
+    }
+}
+
+impl Controller {
+    /// Queue a command for the engine, fails when the engine is not keeping up
+    pub fn send(&mut self, command: Command) -> Result<(), Error> {
+        self.commands.push(command).map_err(|_| Error::QueueFull)
+    }
+
+    /// Queue commands that only make sense together,
+    /// none are queued when they do not all fit
+    pub fn send_all(&mut self, commands: Vec<Command>) -> Result<(), Error> {
+        if commands.len() > self.commands.free() {
+            return Err(Error::QueueFull);
+        }
+
+        for command in commands {
+            self.send(command)?;
+        }
+
+        Ok(())
+    }
+
+    /// Take the latest snapshot sent by the engine,
+    /// handing the previous one back so the engine can fill it again
+    pub fn update(&mut self) {
+        while let Some(mut snapshot) = self.snapshots.pop() {
+            if let Some(error) = snapshot.command_error.take() {
+                self.error = Some(error);
+            }
+
+            std::mem::swap(&mut self.snapshot, &mut snapshot);
+            let _ = self.spare.push(snapshot);
+        }
+    }
+
+    /// Take the last command the engine could not apply
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
+
+    /// Take the MIDI messages sent by entities since the last call,
+    /// with their time in samples
+    pub fn receive_midi(&mut self) -> Vec<(u64, MidiMessage)> {
+        self.drain_midi().collect()
+    }
+
+    /// Take the gates opened or closed by watched entities since the last call
+    pub fn receive_gates(&mut self) -> Vec<(u64, Position, bool)> {
+        self.drain_gates().collect()
+    }
+
+    /// Take the samples of the tapped output since the last call
+    pub fn receive_tap(&mut self) -> Vec<f32> {
+        self.drain_tap().collect()
+    }
+
+    /// Take the MIDI messages one by one, for callers that must not allocate
+    pub fn drain_midi(&mut self) -> impl Iterator<Item = (u64, MidiMessage)> + '_ {
+        std::iter::from_fn(|| self.midi_out.pop())
+    }
+
+    pub fn drain_gates(&mut self) -> impl Iterator<Item = (u64, Position, bool)> + '_ {
+        std::iter::from_fn(|| self.gates.pop())
+    }
+
+    pub fn drain_tap(&mut self) -> impl Iterator<Item = f32> + '_ {
+        std::iter::from_fn(|| self.tap.pop())
+    }
+
+    pub fn get_snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn get_image_for_pos(&self, pos: Position) -> Option<Image> {
+        if self.cursor_position == pos {
+            let mut image = Image::new(4, 4);
+            image.clear(Color::new(251, 255, 38, 255));
+            Some(image)
+        } else {
+            self.snapshot
+                .get_entity(pos)
+                .and_then(|entity| entity.grid_display.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntityType, Setting, SettingValue};
+    use crate::Sample;
+    use std::sync::Arc;
+
+    fn control_change(value: u8) -> Command {
+        Command::Midi(MidiMessage::ControlChange {
+            channel: 0,
+            controller: 74,
+            value,
+        })
+    }
+
+    #[test]
+    fn test_commands() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 2);
+
+        controller
+            .send(Command::AddEntity(Position::new(0, 0), EntityType::Trigger))
+            .unwrap();
+        controller
+            .send(Command::UpdateSetting(
+                Position::new(0, 0),
+                Setting::new(SettingValue::Float(120.0), "bpm"),
+            ))
+            .unwrap();
+
+        assert!(matches!(
+            controller.send(Command::RemoveEntity(Position::new(0, 0))),
+            Err(Error::QueueFull)
+        ));
+
+        // nothing is received until the engine has sampled
+        controller.update();
+        assert!(controller.get_snapshot().entities.is_empty());
+
+        engine.sample();
+        controller.update();
+
+        let snapshot = controller.get_snapshot();
+        let entity = snapshot.get_entity(Position::new(0, 0)).unwrap();
+
+        assert_eq!(snapshot.time, 4);
+        assert_eq!(entity.level, 1.0);
+        assert!(matches!(
+            entity.settings[0].value,
+            SettingValue::Float(bpm) if bpm == 120.0
+        ));
+    }
+
+    #[test]
+    fn test_command_error() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 2);
+
+        engine.set_capacity(Some(0));
+        controller
+            .send(Command::AddEntity(Position::new(0, 0), EntityType::Step))
+            .unwrap();
+
+        // the error is kept after the next command succeeds
+        controller.send(Command::Stop).unwrap();
+        engine.sample();
+        engine.sample();
+        controller.update();
+
+        assert!(controller.get_snapshot().error.is_none());
+        assert!(matches!(
+            controller.take_error(),
+            Some(Error::CapacityReached(0))
+        ));
+        assert!(controller.take_error().is_none());
+    }
+
+    #[test]
+    fn test_snapshot_storage() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+
+        controller
+            .send(Command::AddEntity(position, EntityType::Trigger))
+            .unwrap();
+        engine.sample();
+        controller.update();
+        engine.sample();
+        controller.update();
+
+        // both snapshots hold the entity, later ones reuse their settings
+        let settings = controller.get_snapshot().entities[0].settings.as_ptr();
+        controller
+            .send(Command::UpdateSetting(
+                position,
+                Setting::new(SettingValue::Float(90.0), "bpm"),
+            ))
+            .unwrap();
+        engine.sample();
+        controller.update();
+        engine.sample();
+        controller.update();
+
+        let entity = controller.get_snapshot().get_entity(position).unwrap();
+        assert_eq!(entity.settings.as_ptr(), settings);
+        assert!(matches!(entity.settings[0].value, SettingValue::Float(v) if v == 90.0));
+    }
+
+    #[test]
+    fn test_midi_learn() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(1, 0);
+
+        controller
+            .send(Command::AddEntity(position, EntityType::Trigger))
+            .unwrap();
+        controller.send(control_change(0)).unwrap();
+        controller
+            .send(Command::LearnMidi(position, "bpm".into()))
+            .unwrap();
+        engine.sample();
+        controller.update();
+
+        // control changes are ignored until learned
+        let snapshot = controller.get_snapshot();
+        assert_eq!(snapshot.learning, Some((position, "bpm".into())));
+        assert!(snapshot.mappings.is_empty());
+
+        controller.send(control_change(127)).unwrap();
+        engine.sample();
+        controller.update();
+
+        let snapshot = controller.get_snapshot();
+        assert_eq!(snapshot.learning, None);
+        assert_eq!(
+            snapshot.get_mapping(position, "bpm").unwrap().controller,
+            74
+        );
+        assert!(matches!(
+            snapshot.get_entity(position).unwrap().settings[0].value,
+            SettingValue::Float(bpm) if bpm == 300.0
+        ));
+
+        controller
+            .send(Command::ForgetMidi(position, "bpm".into()))
+            .unwrap();
+        controller.send(control_change(0)).unwrap();
+        engine.sample();
+        controller.update();
+
+        let snapshot = controller.get_snapshot();
+        assert!(snapshot.mappings.is_empty());
+        assert!(matches!(
+            snapshot.get_entity(position).unwrap().settings[0].value,
+            SettingValue::Float(bpm) if bpm == 300.0
+        ));
+    }
+
+    #[test]
+    fn test_midi_notes() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+
+        controller
+            .send(Command::AddEntity(Position::new(0, 0), EntityType::MidiIn))
+            .unwrap();
+        controller
+            .send(Command::Midi(MidiMessage::NoteOn {
+                channel: 0,
+                note: 72,
+                velocity: 100,
+            }))
+            .unwrap();
+        engine.sample();
+        controller.update();
+
+        let entity = controller
+            .get_snapshot()
+            .get_entity(Position::new(0, 0))
+            .unwrap();
+        assert_eq!(entity.level, 1.0);
+    }
+
+    #[test]
+    fn test_release_removed_notes() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 1);
+
+        controller
+            .send(Command::AddEntity(Position::new(0, 0), EntityType::MidiIn))
+            .unwrap();
+        controller
+            .send(Command::AddEntity(position, EntityType::MidiOut))
+            .unwrap();
+        controller
+            .send(Command::Midi(MidiMessage::NoteOn {
+                channel: 0,
+                note: 72,
+                velocity: 100,
+            }))
+            .unwrap();
+        engine.sample();
+
+        let messages = controller.receive_midi();
+        assert!(matches!(
+            messages[..],
+            [(0, MidiMessage::NoteOn { note: 60, .. })]
+        ));
+
+        controller.send(Command::RemoveEntity(position)).unwrap();
+        engine.sample();
+
+        let messages = controller.receive_midi();
+        assert!(matches!(
+            messages[..],
+            [(4, MidiMessage::NoteOff { note: 60, .. })]
+        ));
+    }
+
+    #[test]
+    fn test_load_sample() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+        let sample = Arc::new(Sample::new("kick", 4, vec![vec![1.0, 0.5]]));
+
+        controller
+            .send(Command::AddEntity(position, EntityType::Sampler))
+            .unwrap();
+        controller
+            .send(Command::LoadSample(position, sample.clone()))
+            .unwrap();
+        engine.sample();
+        controller.update();
+
+        // a sample at the engine rate is shared with the snapshot, not copied
+        let entity = controller.get_snapshot().get_entity(position).unwrap();
+        assert!(entity
+            .sample
+            .as_ref()
+            .is_some_and(|s| Arc::ptr_eq(s, &sample)));
+    }
+
+    #[test]
+    fn test_transport_and_gates() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+
+        controller
+            .send(Command::AddEntity(position, EntityType::Trigger))
+            .unwrap();
+        controller.send(Command::WatchGate(position, true)).unwrap();
+        engine.sample();
+        controller.update();
+
+        let gates = controller.receive_gates();
+        assert_eq!(gates.first(), Some(&(0, position, true)));
+
+        controller.send(Command::Stop).unwrap();
+        let (left, _) = engine.sample();
+        assert!(left.iter().all(|s| *s == 0.0));
+
+        controller.update();
+        let snapshot = controller.get_snapshot();
+        assert!(!snapshot.playing);
+        assert_eq!(snapshot.watched, vec![position]);
+        assert_eq!(snapshot.time, 4);
+    }
+
+    #[test]
+    fn test_tap() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+
+        controller.send(Command::Tap(position)).unwrap();
+        engine.sample();
+        assert!(controller.receive_tap().is_empty());
+
+        controller
+            .send(Command::AddEntity(position, EntityType::Trigger))
+            .unwrap();
+        engine.sample();
+        engine.sample();
+
+        let samples = controller.receive_tap();
+        assert_eq!(samples.len(), 8);
+        assert_eq!(samples[0], 1.0);
+    }
+
+    #[test]
+    fn test_held_levels() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+
+        let sync = Setting::new(SettingValue::Boolean(true), "sync");
+        controller
+            .send(Command::AddEntity(position, EntityType::Trigger))
+            .unwrap();
+        controller
+            .send(Command::UpdateSetting(position, sync))
+            .unwrap();
+        // every other buffer starts on a beat and opens the gate
+        controller.send(Command::SetTempo(30.0)).unwrap();
+
+        for _ in 0..4 {
+            engine.sample();
+        }
+
+        // the peak of the third buffer is kept until the next snapshot
+        controller.send(Command::Stop).unwrap();
+        controller.update();
+        engine.sample();
+        controller.update();
+
+        let entity = controller.get_snapshot().get_entity(position).unwrap();
+        assert_eq!(entity.kind, EntityType::Trigger);
+        assert_eq!(entity.level, 1.0);
+        assert!(entity.display_level.is_some());
+    }
+
+    #[test]
+    fn test_undo() {
+        let (mut engine, _controller) = Engine::new(4, 4, 8);
+        let (a, b) = (Position::new(0, 0), Position::new(2, 0));
+        let bpm = |engine: &Engine, position| {
+            let entity = engine
+                .grid
+                .get_entities()
+                .iter()
+                .find(|e| e.get_position() == position);
+            entity
+                .and_then(|e| e.get_settings().into_iter().next())
+                .map(|s| s.value)
+        };
+        let positions = |engine: &Engine| {
+            let mut positions: Vec<Position> = engine
+                .grid
+                .get_entities()
+                .iter()
+                .map(|e| e.get_position())
+                .collect();
+            positions.sort_by_key(|p| p.x);
+            positions
+        };
+
+        engine.apply(Command::BeginGroup);
+        engine.apply(Command::AddEntity(a, EntityType::Trigger));
+        engine.apply(Command::UpdateSetting(
+            a,
+            Setting::new(SettingValue::Float(90.0), "bpm"),
+        ));
+        engine.apply(Command::EndGroup);
+        engine.apply(Command::AddEntity(b, EntityType::Step));
+        engine.sample();
+
+        // moving the trigger onto the step replaces it, undo brings both back
+        engine.apply(Command::MoveEntities(
+            Rect::new(0, 0, a),
+            Position::new(2, 0),
+        ));
+        engine.apply(Command::Undo);
+        engine.sample();
+        assert_eq!(positions(&engine), vec![a, b]);
+        assert!(matches!(bpm(&engine, a), Some(SettingValue::Float(v)) if v == 90.0));
+
+        // the step and the trigger with its setting are separate groups
+        engine.apply(Command::Undo);
+        assert_eq!(positions(&engine), vec![a]);
+        engine.apply(Command::Undo);
+        assert_eq!(positions(&engine), vec![]);
+        engine.apply(Command::Undo);
+
+        engine.apply(Command::Redo);
+        engine.sample();
+        assert!(matches!(bpm(&engine, a), Some(SettingValue::Float(v)) if v == 90.0));
+        engine.apply(Command::Redo);
+        engine.apply(Command::Redo);
+        assert_eq!(positions(&engine), vec![b]);
+
+        // a new edit drops what could be redone
+        engine.apply(Command::Undo);
+        engine.apply(Command::RemoveEntity(a));
+        engine.apply(Command::Redo);
+        assert_eq!(positions(&engine), vec![b]);
+
+        // edits outside of a group are undone one by one,
+        // settings changed in the same buffer revert to the value before each change
+        engine.apply(Command::AddEntity(a, EntityType::Trigger));
+        let initial = match bpm(&engine, a) {
+            Some(SettingValue::Float(v)) => v,
+            _ => panic!("trigger without bpm"),
+        };
+        for value in [100.0, 110.0] {
+            engine.apply(Command::UpdateSetting(
+                a,
+                Setting::new(SettingValue::Float(value), "bpm"),
+            ));
+        }
+        engine.apply(Command::Undo);
+        assert!(matches!(bpm(&engine, a), Some(SettingValue::Float(v)) if v == 100.0));
+        engine.apply(Command::Undo);
+        assert!(matches!(bpm(&engine, a), Some(SettingValue::Float(v)) if v == initial));
+        engine.apply(Command::Undo);
+        assert_eq!(positions(&engine), vec![b]);
+    }
+
+    #[test]
+    fn test_undo_remove() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let position = Position::new(0, 0);
+
+        engine.apply(Command::AddEntity(position, EntityType::Trigger));
+        engine.apply(Command::WatchGate(position, true));
+        engine.apply(Command::LearnMidi(position, "bpm".into()));
+        engine.apply(control_change(0));
+        engine.apply(Command::RemoveEntity(position));
+        assert!(engine.mappings.is_empty());
+        assert!(!engine.audio.is_watched(position));
+
+        // the same entity comes back with its mapping and watched gate
+        engine.apply(Command::Undo);
+        engine.sample();
+        controller.update();
+        let snapshot = controller.get_snapshot();
+        assert_eq!(snapshot.entities.len(), 1);
+        assert!(snapshot.get_mapping(position, "bpm").is_some());
+        assert_eq!(snapshot.watched, vec![position]);
+
+        // an entity kept for redoing its add is freed once it can no longer be redone
+        engine.apply(Command::Undo);
+        assert_eq!(engine.audio.get_released_count(), 0);
+        engine.apply(Command::AddEntity(Position::new(1, 0), EntityType::Step));
+        assert_eq!(engine.audio.get_released_count(), 1);
+    }
+}
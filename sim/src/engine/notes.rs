@@ -0,0 +1,110 @@
+use super::queue::Sender;
+use crate::midi::MidiMessage;
+
+/// Sends MIDI messages to the controller, keeping a slot free for the
+/// note off of every note on that got through so no note is left hanging
+pub struct NoteSender {
+    sender: Sender<(u64, MidiMessage)>,
+    /// note ons sent without their note off for each channel and note
+    sounding: [[u8; 128]; 16],
+    /// total of `sounding`, the slots kept free for note offs
+    held: usize,
+}
+
+impl NoteSender {
+    pub fn new(sender: Sender<(u64, MidiMessage)>) -> Self {
+        NoteSender {
+            sender,
+            sounding: [[0; 128]; 16],
+            held: 0,
+        }
+    }
+
+    /// Queue a message, note ons and other messages are dropped when only
+    /// the slots kept for note offs are left, note offs of notes that were
+    /// dropped are dropped as well
+    pub fn send(&mut self, time: u64, message: MidiMessage) {
+        match message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } if velocity > 0 => {
+                let count = &mut self.sounding[channel as usize & 15][note as usize & 127];
+
+                if *count < u8::MAX
+                    && self.sender.free() > self.held + 1
+                    && self.sender.push((time, message)).is_ok()
+                {
+                    *count += 1;
+                    self.held += 1;
+                }
+            }
+            MidiMessage::NoteOn { channel, note, .. }
+            | MidiMessage::NoteOff { channel, note, .. } => {
+                let count = &mut self.sounding[channel as usize & 15][note as usize & 127];
+
+                if *count > 0 && self.sender.push((time, message)).is_ok() {
+                    *count -= 1;
+                    self.held -= 1;
+                }
+            }
+            _ => {
+                if self.sender.free() > self.held {
+                    let _ = self.sender.push((time, message));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::queue::channel;
+
+    fn note_on(note: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel: 0,
+            note,
+            velocity: 100,
+        }
+    }
+
+    fn note_off(note: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            channel: 0,
+            note,
+            velocity: 0,
+        }
+    }
+
+    #[test]
+    fn test_note_off_priority() {
+        let (sender, mut receiver) = channel(4);
+        let mut notes = NoteSender::new(sender);
+
+        for note in 60..64 {
+            notes.send(0, note_on(note));
+        }
+
+        // two note ons fill the queue with the slots kept for their note offs
+        notes.send(1, note_off(60));
+        notes.send(1, note_off(61));
+        notes.send(1, note_off(62));
+
+        let received: Vec<_> = std::iter::from_fn(|| receiver.pop()).collect();
+        assert_eq!(
+            received,
+            vec![
+                (0, note_on(60)),
+                (0, note_on(61)),
+                (1, note_off(60)),
+                (1, note_off(61)),
+            ]
+        );
+
+        notes.send(2, note_on(62));
+        assert_eq!(receiver.pop(), Some((2, note_on(62))));
+    }
+}
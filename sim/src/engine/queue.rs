@@ -0,0 +1,174 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Ring buffer shared between a single sender and a single receiver
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    /// index of the next slot to read, only written by the receiver
+    head: AtomicUsize,
+    /// index of the next slot to write, only written by the sender
+    tail: AtomicUsize,
+}
+
+// slots are only accessed by the side that owns them according to head and tail
+unsafe impl<T: Send> Send for Buffer<T> {}
+unsafe impl<T: Send> Sync for Buffer<T> {}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        let mut head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+
+        while head != tail {
+            unsafe { self.slots[head].get_mut().assume_init_drop() };
+            head = (head + 1) % self.slots.len();
+        }
+    }
+}
+
+/// Sending half of a lock-free single producer, single consumer queue
+pub struct Sender<T> {
+    buffer: Arc<Buffer<T>>,
+}
+
+/// Receiving half of a lock-free single producer, single consumer queue
+pub struct Receiver<T> {
+    buffer: Arc<Buffer<T>>,
+}
+
+/// Create a queue holding at most `capacity` values,
+/// neither side blocks or allocates when pushing or popping
+pub fn channel<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    // one slot stays empty to tell a full queue apart from an empty one
+    let slots = (0..capacity + 1)
+        .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+        .collect();
+
+    let buffer = Arc::new(Buffer {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+
+    (
+        Sender {
+            buffer: buffer.clone(),
+        },
+        Receiver { buffer },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Add a value to the queue, returning it when the queue is full
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let buffer = &self.buffer;
+        let tail = buffer.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % buffer.slots.len();
+
+        if next == buffer.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+
+        unsafe { (*buffer.slots[tail].get()).write(value) };
+        buffer.tail.store(next, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Amount of values that can be pushed before the queue is full
+    pub fn free(&self) -> usize {
+        let buffer = &self.buffer;
+        let tail = buffer.tail.load(Ordering::Relaxed);
+        let head = buffer.head.load(Ordering::Acquire);
+
+        (head + buffer.slots.len() - tail - 1) % buffer.slots.len()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Take the oldest value from the queue
+    pub fn pop(&mut self) -> Option<T> {
+        let buffer = &self.buffer;
+        let head = buffer.head.load(Ordering::Relaxed);
+
+        if head == buffer.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { (*buffer.slots[head].get()).assume_init_read() };
+        buffer
+            .head
+            .store((head + 1) % buffer.slots.len(), Ordering::Release);
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_push_pop() {
+        let (mut sender, mut receiver) = channel(2);
+
+        assert_eq!(sender.free(), 2);
+        assert_eq!(sender.push(1), Ok(()));
+        assert_eq!(sender.push(2), Ok(()));
+        assert_eq!(sender.push(3), Err(3));
+        assert_eq!(sender.free(), 0);
+
+        assert_eq!(receiver.pop(), Some(1));
+        assert_eq!(sender.free(), 1);
+        assert_eq!(sender.push(3), Ok(()));
+        assert_eq!(receiver.pop(), Some(2));
+        assert_eq!(receiver.pop(), Some(3));
+        assert_eq!(receiver.pop(), None);
+    }
+
+    #[test]
+    fn test_drop_remaining() {
+        let value = Arc::new(());
+        let (mut sender, receiver) = channel(4);
+
+        sender.push(value.clone()).unwrap();
+        sender.push(value.clone()).unwrap();
+        drop(sender);
+        drop(receiver);
+
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn test_threads() {
+        let (mut sender, mut receiver) = channel(16);
+
+        let producer = thread::spawn(move || {
+            for i in 0..10000 {
+                let mut value = i;
+
+                while let Err(v) = sender.push(value) {
+                    value = v;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 0;
+
+        while expected < 10000 {
+            match receiver.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+
+        producer.join().unwrap();
+    }
+}
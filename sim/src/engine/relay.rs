@@ -0,0 +1,200 @@
+use super::command::{encode_error, encode_position, Reader};
+use super::{
+    link, Command, Controller, Link, MessageRing, RingStorage, Snapshot, MAX_MESSAGE_SIZE,
+};
+use crate::midi::MidiParser;
+use crate::{Error, Sample};
+use std::sync::Arc;
+
+/// Main thread side of a [`Worklet`](super::Worklet), standing in for the engine
+/// of a [`Controller`]: commands are sent to the worklet through one ring and the
+/// state it sends back through the other is handed to the controller
+pub struct Relay<S> {
+    link: Link,
+    commands: MessageRing<S>,
+    state: MessageRing<S>,
+    /// scratch buffer for reading parts of messages
+    buffer: [u8; MAX_MESSAGE_SIZE],
+    /// encoded command the ring had no room for yet, with the bytes already written
+    outgoing: Vec<u8>,
+    sent: usize,
+    /// parts of a state message that is still arriving
+    incoming: Vec<u8>,
+    /// snapshot handed back by the controller that could not be filled yet
+    spare: Option<Snapshot>,
+    /// samples loaded through the relay, the worklet only sends their names back
+    samples: Vec<Arc<Sample>>,
+    /// last error, kept until it is sent with a snapshot
+    error: Option<Error>,
+}
+
+impl<S: RingStorage> Relay<S> {
+    /// Create a relay and its controller, `queue_size` limits the amount
+    /// of commands that can be sent before the relay is updated again
+    pub fn new(
+        commands: MessageRing<S>,
+        state: MessageRing<S>,
+        queue_size: usize,
+    ) -> (Relay<S>, Controller) {
+        let (link, controller) = link(queue_size);
+
+        let relay = Relay {
+            link,
+            commands,
+            state,
+            buffer: [0; MAX_MESSAGE_SIZE],
+            outgoing: vec![],
+            sent: 0,
+            incoming: vec![],
+            spare: None,
+            samples: vec![],
+            error: None,
+        };
+
+        (relay, controller)
+    }
+
+    /// Send the queued commands to the worklet for as long as the ring has room
+    /// and hand the state the worklet sent back to the controller
+    pub fn update(&mut self) {
+        loop {
+            if self.sent == self.outgoing.len() {
+                let command = match self.link.commands.pop() {
+                    Some(command) => command,
+                    None => break,
+                };
+
+                if let Command::LoadSample(_, sample) = &command {
+                    self.samples.retain(|s| s.name != sample.name);
+                    self.samples.push(sample.clone());
+                }
+
+                self.outgoing.clear();
+                command.encode(&mut self.outgoing);
+                self.sent = 0;
+            }
+
+            self.sent = self.commands.resume_parts(&self.outgoing, self.sent);
+
+            if self.sent < self.outgoing.len() {
+                break;
+            }
+        }
+
+        while let Some(message) = self.state.pop_parts(&mut self.buffer, &mut self.incoming) {
+            if let Err(e) = message.and_then(|_| self.receive()) {
+                self.error = Some(e);
+            }
+
+            self.incoming.clear();
+        }
+    }
+
+    /// Pass on a state message written by [`encode_state`], the snapshot in it
+    /// is dropped when the controller has not handed back the previous one
+    fn receive(&mut self) -> Result<(), Error> {
+        let mut reader = Reader::new(&self.incoming);
+
+        if reader.u8()? != 0 {
+            self.error = Some(reader.error()?);
+        }
+
+        for _ in 0..reader.count()? {
+            let time = u64::from_le_bytes(reader.array()?);
+            let length = reader.u8()? as usize;
+            let mut parser = MidiParser::new();
+            let message = reader
+                .take(length)?
+                .iter()
+                .find_map(|&byte| parser.push(byte))
+                .ok_or_else(|| Error::InvalidMessage("incomplete midi message".into()))?;
+
+            let _ = self.link.midi_out.push((time, message));
+        }
+
+        for _ in 0..reader.count()? {
+            let gate = (
+                u64::from_le_bytes(reader.array()?),
+                reader.position()?,
+                reader.u8()? != 0,
+            );
+
+            let _ = self.link.gates.push(gate);
+        }
+
+        for _ in 0..reader.count()? {
+            let _ = self.link.tap.push(f32::from_le_bytes(reader.array()?));
+        }
+
+        let mut snapshot = match self.spare.take().or_else(|| self.link.spare.pop()) {
+            Some(snapshot) => snapshot,
+            None => return Ok(()),
+        };
+
+        match snapshot.decode(&mut reader, &self.samples) {
+            Ok(()) => {
+                snapshot.command_error = self.error.take();
+                let _ = self.link.snapshots.push(snapshot);
+                Ok(())
+            }
+            Err(e) => {
+                self.spare = Some(snapshot);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Encode the latest snapshot of a worklet's controller together with the
+/// errors, MIDI messages, gates and tapped samples it received since the last call
+pub(super) fn encode_state(controller: &mut Controller, bytes: &mut Vec<u8>) {
+    match controller.take_error() {
+        Some(error) => {
+            bytes.push(1);
+            encode_error(&error, bytes);
+        }
+        None => bytes.push(0),
+    }
+
+    let start = begin_count(bytes);
+    let mut count = 0;
+    for (time, message) in controller.drain_midi() {
+        bytes.extend_from_slice(&time.to_le_bytes());
+        let length = bytes.len();
+        bytes.push(0);
+        message.encode(bytes);
+        bytes[length] = (bytes.len() - length - 1) as u8;
+        count += 1;
+    }
+    end_count(bytes, start, count);
+
+    let start = begin_count(bytes);
+    let mut count = 0;
+    for (time, position, open) in controller.drain_gates() {
+        bytes.extend_from_slice(&time.to_le_bytes());
+        encode_position(&position, bytes);
+        bytes.push(open as u8);
+        count += 1;
+    }
+    end_count(bytes, start, count);
+
+    let start = begin_count(bytes);
+    let mut count = 0;
+    for sample in controller.drain_tap() {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+        count += 1;
+    }
+    end_count(bytes, start, count);
+
+    controller.get_snapshot().encode(bytes);
+}
+
+/// Leave room for the amount of values that follow, returning where it goes
+fn begin_count(bytes: &mut Vec<u8>) -> usize {
+    bytes.extend_from_slice(&[0; 4]);
+    bytes.len() - 4
+}
+
+fn end_count(bytes: &mut [u8], start: usize, count: u32) {
+    bytes[start..start + 4].copy_from_slice(&count.to_le_bytes());
+}
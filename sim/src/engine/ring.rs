@@ -0,0 +1,367 @@
+use crate::Error;
+#[cfg(test)]
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// Largest message that fits in a [`MessageRing`]
+pub const MAX_MESSAGE_SIZE: usize = 256;
+
+/// Memory shared between the two sides of a [`MessageRing`], for example a
+/// SharedArrayBuffer holding the two indices followed by the data bytes
+pub trait RingStorage {
+    /// amount of data bytes
+    fn capacity(&self) -> usize;
+    /// load the read (0) or write (1) index with acquire ordering
+    fn load_index(&self, index: usize) -> usize;
+    /// store the read (0) or write (1) index with release ordering
+    fn store_index(&self, index: usize, value: usize);
+    fn get(&self, offset: usize) -> u8;
+    fn set(&self, offset: usize, value: u8);
+}
+
+const READ: usize = 0;
+const WRITE: usize = 1;
+
+/// First byte of a part that is followed by more parts of the same message
+const PART: u8 = 0;
+/// First byte of the part that ends a message
+const LAST: u8 = 1;
+/// Bytes of a longer message carried by each part, after the part header
+const PART_SIZE: usize = MAX_MESSAGE_SIZE - 1;
+
+/// Single producer, single consumer queue of length prefixed byte messages,
+/// both sides can live in different wasm instances as long as they share the storage
+pub struct MessageRing<S> {
+    storage: S,
+}
+
+impl<S: RingStorage> MessageRing<S> {
+    pub fn new(storage: S) -> Self {
+        MessageRing { storage }
+    }
+
+    /// Write a message, fails when there is not enough room
+    pub fn push(&self, message: &[u8]) -> Result<(), Error> {
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::InvalidMessage(format!(
+                "message of {} bytes exceeds {} bytes",
+                message.len(),
+                MAX_MESSAGE_SIZE
+            )));
+        }
+
+        if message.len() + 2 > self.free() {
+            return Err(Error::QueueFull);
+        }
+
+        self.write(&[], message);
+
+        Ok(())
+    }
+
+    /// Write a message of any length split into parts, only when all of them fit
+    pub fn push_parts(&self, message: &[u8]) -> Result<(), Error> {
+        let parts = message.len().div_ceil(PART_SIZE).max(1);
+        let length = message.len() + parts * 3;
+
+        if length >= self.storage.capacity() {
+            return Err(Error::InvalidMessage(format!(
+                "message of {} bytes exceeds the ring of {} bytes",
+                message.len(),
+                self.storage.capacity()
+            )));
+        }
+
+        if length > self.free() {
+            return Err(Error::QueueFull);
+        }
+
+        self.resume_parts(message, 0);
+
+        Ok(())
+    }
+
+    /// Write the parts of a message starting at `sent` bytes into it for as long
+    /// as they fit, returning how many bytes of the message are written so the
+    /// rest can follow once the other side has read some
+    pub fn resume_parts(&self, message: &[u8], mut sent: usize) -> usize {
+        loop {
+            let end = message.len().min(sent + PART_SIZE);
+
+            if end - sent + 3 > self.free() {
+                return sent;
+            }
+
+            let header = if end == message.len() { LAST } else { PART };
+            self.write(&[header], &message[sent..end]);
+            sent = end;
+
+            if header == LAST {
+                return sent;
+            }
+        }
+    }
+
+    /// Bytes that can be written before the ring is full, length prefixes included
+    fn free(&self) -> usize {
+        let capacity = self.storage.capacity();
+        let read = self.storage.load_index(READ);
+        let write = self.storage.load_index(WRITE);
+
+        // one byte stays empty to tell a full ring apart from an empty one
+        (read + capacity - write - 1) % capacity
+    }
+
+    /// Write `header` and `message` as one length prefixed message, the caller
+    /// checked that it fits
+    fn write(&self, header: &[u8], message: &[u8]) {
+        let capacity = self.storage.capacity();
+        let write = self.storage.load_index(WRITE);
+        let length = header.len() + message.len();
+
+        for (i, &byte) in (length as u16)
+            .to_le_bytes()
+            .iter()
+            .chain(header)
+            .chain(message)
+            .enumerate()
+        {
+            self.storage.set((write + i) % capacity, byte);
+        }
+
+        self.storage
+            .store_index(WRITE, (write + length + 2) % capacity);
+    }
+
+    /// Read the oldest message into `buffer`, returning its length. A length
+    /// larger than `MAX_MESSAGE_SIZE` or than the data in the ring means the ring
+    /// was corrupted, everything in it is dropped and an error returned.
+    pub fn pop(&self, buffer: &mut [u8; MAX_MESSAGE_SIZE]) -> Option<Result<usize, Error>> {
+        let capacity = self.storage.capacity();
+        let read = self.storage.load_index(READ);
+        let write = self.storage.load_index(WRITE);
+
+        if read == write {
+            return None;
+        }
+
+        let available = (write + capacity - read) % capacity;
+        let length = u16::from_le_bytes([
+            self.storage.get(read),
+            self.storage.get((read + 1) % capacity),
+        ]) as usize;
+
+        if length > MAX_MESSAGE_SIZE || length + 2 > available {
+            self.storage.store_index(READ, write);
+
+            return Some(Err(Error::InvalidMessage(format!(
+                "message of {} bytes in a ring holding {} bytes",
+                length, available
+            ))));
+        }
+
+        for (i, byte) in buffer.iter_mut().take(length).enumerate() {
+            *byte = self.storage.get((read + 2 + i) % capacity);
+        }
+
+        self.storage
+            .store_index(READ, (read + length + 2) % capacity);
+
+        Some(Ok(length))
+    }
+
+    /// Read parts and append them to `message` until a message is complete,
+    /// returning `None` when the ring runs empty first. The parts read so far
+    /// stay in `message` for the next call, on errors it is cleared.
+    pub fn pop_parts(
+        &self,
+        buffer: &mut [u8; MAX_MESSAGE_SIZE],
+        message: &mut Vec<u8>,
+    ) -> Option<Result<(), Error>> {
+        loop {
+            let length = match self.pop(buffer)? {
+                Ok(length) => length,
+                Err(e) => {
+                    message.clear();
+                    return Some(Err(e));
+                }
+            };
+
+            match buffer[..length].split_first() {
+                Some((&PART, part)) => message.extend_from_slice(part),
+                Some((&LAST, part)) => {
+                    message.extend_from_slice(part);
+                    return Some(Ok(()));
+                }
+                _ => {
+                    message.clear();
+                    return Some(Err(Error::InvalidMessage(
+                        "message part without a header".into(),
+                    )));
+                }
+            }
+        }
+    }
+}
+
+/// Storage in local memory, used for testing the ring and the worklet
+#[cfg(test)]
+pub struct TestStorage {
+    indices: [AtomicUsize; 2],
+    data: Vec<AtomicU8>,
+}
+
+#[cfg(test)]
+impl TestStorage {
+    pub fn new(capacity: usize) -> Self {
+        TestStorage {
+            indices: Default::default(),
+            data: (0..capacity).map(|_| Default::default()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl RingStorage for &TestStorage {
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    fn load_index(&self, index: usize) -> usize {
+        self.indices[index].load(Ordering::Acquire)
+    }
+
+    fn store_index(&self, index: usize, value: usize) {
+        self.indices[index].store(value, Ordering::Release)
+    }
+
+    fn get(&self, offset: usize) -> u8 {
+        self.data[offset].load(Ordering::Relaxed)
+    }
+
+    fn set(&self, offset: usize, value: u8) {
+        self.data[offset].store(value, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_pop() {
+        let storage = TestStorage::new(16);
+        let ring = MessageRing::new(&storage);
+        let mut buffer = [0; MAX_MESSAGE_SIZE];
+
+        ring.push(&[1, 2, 3]).unwrap();
+        ring.push(&[4, 5, 6, 7]).unwrap();
+
+        // 15 usable bytes, both messages take 5 and 6 bytes
+        assert!(matches!(ring.push(&[8, 9, 10]), Err(Error::QueueFull)));
+
+        assert!(matches!(ring.pop(&mut buffer), Some(Ok(3))));
+        assert_eq!(&buffer[..3], &[1, 2, 3]);
+
+        // wraps around the end of the storage
+        ring.push(&[8, 9, 10]).unwrap();
+
+        assert!(matches!(ring.pop(&mut buffer), Some(Ok(4))));
+        assert_eq!(&buffer[..4], &[4, 5, 6, 7]);
+        assert!(matches!(ring.pop(&mut buffer), Some(Ok(3))));
+        assert_eq!(&buffer[..3], &[8, 9, 10]);
+        assert!(ring.pop(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_length() {
+        let storage = TestStorage::new(16);
+        let ring = MessageRing::new(&storage);
+        let mut buffer = [0; MAX_MESSAGE_SIZE];
+
+        // a length prefix pointing past the data written
+        ring.push(&[1, 2, 3]).unwrap();
+        (&storage).set(0, 9);
+
+        assert!(matches!(
+            ring.pop(&mut buffer),
+            Some(Err(Error::InvalidMessage(_)))
+        ));
+        assert!(ring.pop(&mut buffer).is_none());
+
+        ring.push(&[4]).unwrap();
+        assert!(matches!(ring.pop(&mut buffer), Some(Ok(1))));
+        assert_eq!(buffer[0], 4);
+    }
+
+    #[test]
+    fn test_parts() {
+        let storage = TestStorage::new(1024);
+        let ring = MessageRing::new(&storage);
+        let mut buffer = [0; MAX_MESSAGE_SIZE];
+        let mut message = vec![];
+
+        let long: Vec<u8> = (0..1500).map(|i| i as u8).collect();
+
+        assert!(matches!(
+            ring.push_parts(&long),
+            Err(Error::InvalidMessage(_))
+        ));
+
+        // written as far as it fits and continued after reading
+        let sent = ring.resume_parts(&long, 0);
+        assert_eq!(sent, PART_SIZE * 3);
+        assert!(ring.pop_parts(&mut buffer, &mut message).is_none());
+        assert_eq!(message, &long[..sent]);
+
+        assert_eq!(ring.resume_parts(&long, sent), long.len());
+        assert!(matches!(
+            ring.pop_parts(&mut buffer, &mut message),
+            Some(Ok(()))
+        ));
+        assert_eq!(message, long);
+
+        message.clear();
+        ring.push_parts(&long[..1000]).unwrap();
+        assert!(matches!(
+            ring.push_parts(&long[..20]),
+            Err(Error::QueueFull)
+        ));
+        assert!(matches!(
+            ring.pop_parts(&mut buffer, &mut message),
+            Some(Ok(()))
+        ));
+        assert_eq!(message, &long[..1000]);
+
+        message.clear();
+        ring.push_parts(&[]).unwrap();
+        ring.push_parts(&[1, 2]).unwrap();
+        assert!(matches!(
+            ring.pop_parts(&mut buffer, &mut message),
+            Some(Ok(()))
+        ));
+        assert!(message.is_empty());
+        assert!(matches!(
+            ring.pop_parts(&mut buffer, &mut message),
+            Some(Ok(()))
+        ));
+        assert_eq!(message, [1, 2]);
+
+        ring.push(&[42, 1]).unwrap();
+        assert!(matches!(
+            ring.pop_parts(&mut buffer, &mut message),
+            Some(Err(Error::InvalidMessage(_)))
+        ));
+        assert!(message.is_empty());
+    }
+
+    #[test]
+    fn test_message_too_large() {
+        let storage = TestStorage::new(1024);
+        let ring = MessageRing::new(&storage);
+
+        assert!(matches!(
+            ring.push(&[0; MAX_MESSAGE_SIZE + 1]),
+            Err(Error::InvalidMessage(_))
+        ));
+    }
+}
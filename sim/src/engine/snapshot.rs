@@ -0,0 +1,473 @@
+use super::command::{
+    encode_count, encode_entity_type, encode_error, encode_name, encode_position, encode_setting,
+    Reader,
+};
+use super::Meter;
+use crate::entity::{EntityType, Setting};
+use crate::grid::{Connection, Grid, Position, Rect};
+use crate::midi::MidiMapping;
+use crate::transport::Clock;
+use crate::{Color, Error, Image, Sample};
+use screech::Output;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Names of the inputs and outputs of all entity types, decoded connections
+/// borrow them like the signals of the tracker do
+const SIGNAL_NAMES: [&str; 24] = [
+    "accent", "clock", "gate", "gate_0", "gate_1", "gate_2", "gate_3", "gate_4", "gate_5",
+    "gate_6", "gate_7", "input", "left", "output", "pitch", "pitch_0", "pitch_1", "pitch_2",
+    "pitch_3", "pitch_4", "pitch_5", "pitch_6", "pitch_7", "right",
+];
+
+/// State of an entity after sampling a buffer
+#[derive(Clone)]
+pub struct EntitySnapshot {
+    pub position: Position,
+    pub kind: EntityType,
+    pub grid_display: Option<Image>,
+    pub detail_display: Option<Image>,
+    pub settings: Vec<Setting>,
+    /// peak of the primary output since the previous snapshot
+    pub level: f32,
+    /// state shown with the glyph of the entity, between 0.0 and 1.0
+    pub display_level: Option<f32>,
+    /// sample played by the entity, shared with the audio side
+    pub sample: Option<Arc<Sample>>,
+}
+
+/// Copy of the audio state sent back to the user interface after sampling
+#[derive(Clone)]
+pub struct Snapshot {
+    /// time in samples at the end of the sampled buffer
+    pub time: u64,
+    pub sample_rate: usize,
+    /// error of the last buffer that was sampled
+    pub error: Option<Error>,
+    /// last command that could not be applied since the previous snapshot
+    pub command_error: Option<Error>,
+    pub cycles: Vec<Position>,
+    pub connections: Vec<Connection>,
+    pub entities: Vec<EntitySnapshot>,
+    /// setting waiting for a control change to be mapped onto it
+    pub learning: Option<(Position, String)>,
+    pub mappings: Vec<MidiMapping>,
+    pub playing: bool,
+    /// tempo and beat of the transport at `time`
+    pub clock: Clock,
+    /// entities with their gates reported to the controller
+    pub watched: Vec<Position>,
+    /// left and right channel of the main output
+    pub meters: [Meter; 2],
+}
+
+impl Default for Snapshot {
+    /// State of an empty grid that is playing
+    fn default() -> Self {
+        Snapshot::new(&Grid::new(), 0, &HashMap::new())
+    }
+}
+
+impl Snapshot {
+    pub fn new(grid: &Grid, time: u64, levels: &HashMap<Position, f32>) -> Self {
+        let mut snapshot = Snapshot {
+            time,
+            sample_rate: 0,
+            error: None,
+            command_error: None,
+            cycles: vec![],
+            connections: vec![],
+            entities: vec![],
+            learning: None,
+            mappings: vec![],
+            playing: true,
+            clock: Clock::new(120.0),
+            watched: vec![],
+            meters: [Meter::default(); 2],
+        };
+
+        snapshot.update(grid, time, levels);
+        snapshot
+    }
+
+    /// Copy the state of the grid into the snapshot, reusing the storage of the
+    /// previous state so nothing is allocated while the grid is not edited
+    pub fn update(&mut self, grid: &Grid, time: u64, levels: &HashMap<Position, f32>) {
+        self.time = time;
+        self.error.clone_from(&grid.error);
+        self.cycles.clear();
+        self.cycles.extend_from_slice(grid.get_cycles());
+        self.connections.clear();
+        self.connections.extend_from_slice(grid.get_connections());
+
+        let entities = grid.get_entities();
+        self.entities.truncate(entities.len());
+
+        for (i, entity) in entities.iter().enumerate() {
+            let position = entity.get_position();
+            let kind = entity.as_kind().get_type();
+            let level = levels.get(&position).copied().unwrap_or(0.0);
+
+            match self.entities.get_mut(i) {
+                // the same kind of entity in the same place has the same settings
+                Some(snapshot) if snapshot.position == position && snapshot.kind == kind => {
+                    entity.write_settings(&mut snapshot.settings);
+                    entity.render_grid_display(&mut snapshot.grid_display);
+                    snapshot.detail_display = entity.get_detail_display();
+                    snapshot.level = level;
+                    snapshot.display_level = entity.get_display_level();
+                    snapshot.sample = entity.as_kind().get_sample().cloned();
+                }
+                snapshot => {
+                    let fresh = EntitySnapshot {
+                        position,
+                        kind,
+                        grid_display: entity.get_grid_display(),
+                        detail_display: entity.get_detail_display(),
+                        settings: entity.get_settings(),
+                        level,
+                        display_level: entity.get_display_level(),
+                        sample: entity.as_kind().get_sample().cloned(),
+                    };
+
+                    match snapshot {
+                        Some(snapshot) => *snapshot = fresh,
+                        None => self.entities.push(fresh),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get_entity(&self, position: Position) -> Option<&EntitySnapshot> {
+        self.entities.iter().find(|e| e.position == position)
+    }
+
+    /// Smallest rectangle holding every entity, `None` for an empty grid
+    pub fn get_bounds(&self) -> Option<Rect> {
+        let first = self.entities.first()?.position;
+        let (min, max) = self
+            .entities
+            .iter()
+            .fold((first, first), |(min, max), entity| {
+                let p = entity.position;
+                (
+                    Position::new(min.x.min(p.x), min.y.min(p.y)),
+                    Position::new(max.x.max(p.x), max.y.max(p.y)),
+                )
+            });
+
+        Some(Rect::from_corners(min, max))
+    }
+
+    /// Position of the entity that caused the last sampling error
+    pub fn get_error_position(&self) -> Option<Position> {
+        match &self.error {
+            Some(Error::Entity(position, _)) => Some(*position),
+            _ => None,
+        }
+    }
+
+    /// Control change mapped onto a setting of the entity at a position
+    pub fn get_mapping(&self, position: Position, setting: &str) -> Option<&MidiMapping> {
+        self.mappings
+            .iter()
+            .find(|m| m.position == position && m.setting == setting)
+    }
+
+    pub fn is_in_cycle(&self, position: Position) -> bool {
+        self.cycles.contains(&position)
+    }
+
+    /// Encode as a little endian message, for a worklet sending its state to the
+    /// main thread. Errors only keep their text and samples only their name.
+    pub(super) fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.time.to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate as u64).to_le_bytes());
+
+        for error in [&self.error, &self.command_error] {
+            match error {
+                Some(error) => {
+                    bytes.push(1);
+                    encode_error(error, bytes);
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        encode_count(self.cycles.len(), bytes);
+        for position in &self.cycles {
+            encode_position(position, bytes);
+        }
+
+        encode_count(self.connections.len(), bytes);
+        for connection in &self.connections {
+            encode_position(&connection.from, bytes);
+            encode_position(&connection.to, bytes);
+            encode_signal(&connection.output, bytes);
+            encode_signal(&connection.input, bytes);
+            bytes.push(connection.delayed as u8);
+        }
+
+        encode_count(self.entities.len(), bytes);
+        for entity in &self.entities {
+            encode_position(&entity.position, bytes);
+            encode_entity_type(&entity.kind, bytes);
+            bytes.extend_from_slice(&entity.level.to_le_bytes());
+
+            match entity.display_level {
+                Some(level) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&level.to_le_bytes());
+                }
+                None => bytes.push(0),
+            }
+
+            let settings = &entity.settings[..entity.settings.len().min(u8::MAX as usize)];
+            bytes.push(settings.len() as u8);
+
+            for setting in settings {
+                encode_setting(setting, bytes);
+
+                match setting.range {
+                    Some((min, max)) => {
+                        bytes.push(1);
+                        bytes.extend_from_slice(&min.to_le_bytes());
+                        bytes.extend_from_slice(&max.to_le_bytes());
+                    }
+                    None => bytes.push(0),
+                }
+            }
+
+            encode_image(&entity.grid_display, bytes);
+            encode_image(&entity.detail_display, bytes);
+
+            match &entity.sample {
+                Some(sample) => {
+                    bytes.push(1);
+                    encode_name(&sample.name, bytes);
+                }
+                None => bytes.push(0),
+            }
+        }
+
+        match &self.learning {
+            Some((position, setting)) => {
+                bytes.push(1);
+                encode_position(position, bytes);
+                encode_name(setting, bytes);
+            }
+            None => bytes.push(0),
+        }
+
+        encode_count(self.mappings.len(), bytes);
+        for mapping in &self.mappings {
+            bytes.push(mapping.channel);
+            bytes.push(mapping.controller);
+            encode_position(&mapping.position, bytes);
+            encode_name(&mapping.setting, bytes);
+        }
+
+        bytes.push(self.playing as u8);
+        bytes.extend_from_slice(&self.clock.bpm.to_le_bytes());
+        bytes.extend_from_slice(&self.clock.beat.to_le_bytes());
+
+        encode_count(self.watched.len(), bytes);
+        for position in &self.watched {
+            encode_position(position, bytes);
+        }
+
+        for meter in &self.meters {
+            bytes.extend_from_slice(&meter.peak.to_le_bytes());
+            bytes.extend_from_slice(&meter.rms.to_le_bytes());
+            bytes.push(meter.clipped as u8);
+        }
+    }
+
+    /// Replace the state with a snapshot written by [`Snapshot::encode`],
+    /// entities get the sample of the same name from `samples`
+    pub(super) fn decode(
+        &mut self,
+        reader: &mut Reader,
+        samples: &[Arc<Sample>],
+    ) -> Result<(), Error> {
+        self.time = u64::from_le_bytes(reader.array()?);
+        self.sample_rate = u64::from_le_bytes(reader.array()?) as usize;
+
+        for error in [&mut self.error, &mut self.command_error] {
+            *error = match reader.u8()? {
+                0 => None,
+                _ => Some(reader.error()?),
+            };
+        }
+
+        self.cycles.clear();
+        for _ in 0..reader.count()? {
+            self.cycles.push(reader.position()?);
+        }
+
+        self.connections.clear();
+        for _ in 0..reader.count()? {
+            let from = reader.position()?;
+            let to = reader.position()?;
+            let mut connection =
+                Connection::new(from, to, decode_signal(reader)?, decode_signal(reader)?);
+            connection.delayed = reader.u8()? != 0;
+
+            self.connections.push(connection);
+        }
+
+        self.entities.clear();
+        for _ in 0..reader.count()? {
+            let position = reader.position()?;
+            let kind = reader.entity_type()?;
+            let level = f32::from_le_bytes(reader.array()?);
+            let display_level = match reader.u8()? {
+                0 => None,
+                _ => Some(f32::from_le_bytes(reader.array()?)),
+            };
+
+            let mut settings = vec![];
+            for _ in 0..reader.u8()? {
+                let mut setting = reader.setting()?;
+
+                if reader.u8()? != 0 {
+                    setting.range = Some((
+                        f32::from_le_bytes(reader.array()?),
+                        f32::from_le_bytes(reader.array()?),
+                    ));
+                }
+
+                settings.push(setting);
+            }
+
+            let grid_display = decode_image(reader)?;
+            let detail_display = decode_image(reader)?;
+            let sample = match reader.u8()? {
+                0 => None,
+                _ => {
+                    let name = reader.str()?;
+                    samples.iter().find(|s| s.name == name).cloned()
+                }
+            };
+
+            self.entities.push(EntitySnapshot {
+                position,
+                kind,
+                grid_display,
+                detail_display,
+                settings,
+                level,
+                display_level,
+                sample,
+            });
+        }
+
+        self.learning = match reader.u8()? {
+            0 => None,
+            _ => Some((reader.position()?, reader.name()?)),
+        };
+
+        self.mappings.clear();
+        for _ in 0..reader.count()? {
+            self.mappings.push(MidiMapping {
+                channel: reader.u8()?,
+                controller: reader.u8()?,
+                position: reader.position()?,
+                setting: reader.name()?,
+            });
+        }
+
+        self.playing = reader.u8()? != 0;
+        self.clock.bpm = f64::from_le_bytes(reader.array()?);
+        self.clock.beat = f64::from_le_bytes(reader.array()?);
+
+        self.watched.clear();
+        for _ in 0..reader.count()? {
+            self.watched.push(reader.position()?);
+        }
+
+        for meter in &mut self.meters {
+            meter.peak = f32::from_le_bytes(reader.array()?);
+            meter.rms = f32::from_le_bytes(reader.array()?);
+            meter.clipped = reader.u8()? != 0;
+        }
+
+        Ok(())
+    }
+}
+
+fn encode_signal(signal: &Output, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(*signal.get_source_id() as u64).to_le_bytes());
+    encode_name(signal.get_signal_id(), bytes);
+}
+
+fn decode_signal(reader: &mut Reader) -> Result<Output, Error> {
+    let source = u64::from_le_bytes(reader.array()?) as usize;
+    let name = reader.str()?;
+
+    SIGNAL_NAMES
+        .iter()
+        .find(|n| **n == name)
+        .map(|name| Output::new(source, name))
+        .ok_or_else(|| Error::InvalidMessage(format!("unknown signal {}", name)))
+}
+
+fn encode_image(image: &Option<Image>, bytes: &mut Vec<u8>) {
+    let image = match image {
+        Some(image) => image,
+        None => return bytes.push(0),
+    };
+
+    bytes.push(1);
+    bytes.extend_from_slice(&image.width.to_le_bytes());
+    bytes.extend_from_slice(&image.height.to_le_bytes());
+
+    for color in &image.data {
+        bytes.extend_from_slice(&[color.red, color.green, color.blue, color.alpha]);
+    }
+}
+
+fn decode_image(reader: &mut Reader) -> Result<Option<Image>, Error> {
+    if reader.u8()? == 0 {
+        return Ok(None);
+    }
+
+    let width = i32::from_le_bytes(reader.array()?);
+    let height = i32::from_le_bytes(reader.array()?);
+    let length = usize::try_from(width)
+        .ok()
+        .zip(usize::try_from(height).ok())
+        .and_then(|(width, height)| width.checked_mul(height)?.checked_mul(4))
+        .ok_or_else(|| Error::InvalidMessage(format!("image of {}x{}", width, height)))?;
+
+    let data = reader
+        .take(length)?
+        .chunks_exact(4)
+        .map(|c| Color::new(c[0], c[1], c[2], c[3]))
+        .collect();
+
+    Ok(Some(Image {
+        width,
+        height,
+        data,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::command::ENTITY_TYPES;
+    use super::*;
+    use screech::Screech;
+
+    #[test]
+    fn test_signal_names() {
+        for entity_type in ENTITY_TYPES {
+            let entity = entity_type.create(&mut Screech::new(1, 1));
+
+            for output in entity.as_kind().get_outputs() {
+                assert!(SIGNAL_NAMES.contains(&output.get_signal_id()));
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use super::relay::encode_state;
+use super::{Command, Controller, Engine, MessageRing, RingStorage, MAX_MESSAGE_SIZE};
+use crate::Error;
+
+/// Amount of frames an AudioWorkletProcessor renders per call
+pub const RENDER_QUANTUM: usize = 128;
+
+/// Quanta rendered between two states sent to the main thread, about 90 a second
+const STATE_INTERVAL: usize = 4;
+
+/// Commands the engine can receive from one quantum to the next
+const QUEUE_SIZE: usize = 256;
+
+/// Audio side of the sim inside an AudioWorkletProcessor, rendering one quantum
+/// at a time. Edits arrive from a [`Relay`](super::Relay) on the main thread
+/// through one [`MessageRing`] and the state of the engine is sent back through the other.
+pub struct Worklet<S> {
+    engine: Engine,
+    /// receives the snapshots, errors and outputs of the engine to send them on
+    controller: Controller,
+    commands: MessageRing<S>,
+    state: MessageRing<S>,
+    /// scratch buffer for reading parts of messages without allocating
+    message: [u8; MAX_MESSAGE_SIZE],
+    /// parts of a command that is still arriving
+    command: Vec<u8>,
+    /// encoded state waiting for room in the ring, its storage is reused
+    outgoing: Vec<u8>,
+    /// quanta rendered since the last state was sent
+    quanta: usize,
+}
+
+impl<S: RingStorage> Worklet<S> {
+    pub fn new(sample_rate: usize, commands: MessageRing<S>, state: MessageRing<S>) -> Self {
+        let (engine, controller) = Engine::new(sample_rate, RENDER_QUANTUM, QUEUE_SIZE);
+
+        Worklet {
+            engine,
+            controller,
+            commands,
+            state,
+            message: [0; MAX_MESSAGE_SIZE],
+            command: Vec::with_capacity(MAX_MESSAGE_SIZE),
+            outgoing: vec![],
+            quanta: 0,
+        }
+    }
+
+    /// Limit the amount of entities on the grid, `None` for no limit
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.engine.set_capacity(capacity);
+    }
+
+    /// Apply the pending messages and render the next quantum,
+    /// outputs shorter than a quantum are truncated and the rest is left silent
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32]) {
+        while let Some(message) = self
+            .commands
+            .pop_parts(&mut self.message, &mut self.command)
+        {
+            match message.and_then(|_| Command::decode(&self.command)) {
+                Ok(command) => self.engine.apply(command),
+                Err(e) => self.engine.error = Some(e),
+            }
+
+            self.command.clear();
+        }
+
+        let (l, r) = self.engine.sample();
+
+        for (output, input) in [(left, l), (right, r)] {
+            output.fill(0.0);
+
+            for (o, i) in output.iter_mut().zip(input.iter()) {
+                *o = *i;
+            }
+        }
+
+        self.quanta += 1;
+
+        if self.quanta >= STATE_INTERVAL {
+            self.send_state();
+        }
+    }
+
+    /// Send the latest state to the main thread, a state that does not fit is
+    /// kept and sent again after the next quantum
+    fn send_state(&mut self) {
+        if self.outgoing.is_empty() {
+            self.controller.update();
+            encode_state(&mut self.controller, &mut self.outgoing);
+        }
+
+        match self.state.push_parts(&self.outgoing) {
+            Err(Error::QueueFull) => return,
+            Err(e) => self.engine.error = Some(e),
+            Ok(()) => (),
+        }
+
+        self.outgoing.clear();
+        self.quanta = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ring::TestStorage;
+    use crate::engine::Relay;
+    use crate::entity::EntityType;
+    use crate::grid::Position;
+    use crate::Sample;
+    use std::sync::Arc;
+
+    fn process(worklet: &mut Worklet<&TestStorage>, quanta: usize) {
+        let mut left = [1.0; RENDER_QUANTUM];
+        let mut right = [1.0; RENDER_QUANTUM];
+
+        for _ in 0..quanta {
+            worklet.process(&mut left, &mut right);
+        }
+    }
+
+    #[test]
+    fn test_process() {
+        let storage = TestStorage::new(1024);
+        let state = TestStorage::new(1024);
+        let mut worklet = Worklet::new(48000, MessageRing::new(&storage), MessageRing::new(&state));
+        let main_thread = MessageRing::new(&storage);
+        let mut left = [1.0; RENDER_QUANTUM];
+        let mut right = [1.0; RENDER_QUANTUM];
+
+        worklet.process(&mut left, &mut right);
+        assert!(left.iter().chain(right.iter()).all(|s| *s == 0.0));
+
+        for command in [
+            Command::AddEntity(Position::new(0, 0), EntityType::Trigger),
+            Command::AddEntity(Position::new(0, 1), EntityType::Step),
+        ] {
+            let mut bytes = vec![];
+            command.encode(&mut bytes);
+            main_thread.push_parts(&bytes).unwrap();
+        }
+        worklet.process(&mut left, &mut right);
+
+        assert_eq!(worklet.engine.grid.get_entities().len(), 2);
+        assert_eq!(worklet.engine.audio.get_time(), 2 * RENDER_QUANTUM as u64);
+    }
+
+    #[test]
+    fn test_relay() {
+        let commands = TestStorage::new(4096);
+        let state = TestStorage::new(1 << 16);
+        let mut worklet =
+            Worklet::new(48000, MessageRing::new(&commands), MessageRing::new(&state));
+        let (mut relay, mut controller) =
+            Relay::new(MessageRing::new(&commands), MessageRing::new(&state), 16);
+
+        worklet.set_capacity(Some(3));
+
+        let sample = Arc::new(Sample::new("kick", 48000, vec![vec![0.5; 2000]]));
+        controller
+            .send_all(vec![
+                Command::AddEntity(Position::new(0, 0), EntityType::Trigger),
+                Command::AddEntity(Position::new(1, 0), EntityType::Euclid),
+                Command::AddEntity(Position::new(0, 1), EntityType::Sampler),
+                Command::LoadSample(Position::new(0, 1), sample.clone()),
+                Command::WatchGate(Position::new(0, 0), true),
+                Command::Tap(Position::new(0, 0)),
+                Command::AddEntity(Position::new(1, 1), EntityType::Step),
+            ])
+            .unwrap();
+
+        // the sample is sent in parts over several updates
+        for _ in 0..32 {
+            relay.update();
+            controller.update();
+            process(&mut worklet, STATE_INTERVAL);
+        }
+        relay.update();
+        controller.update();
+
+        let snapshot = controller.get_snapshot();
+        assert_eq!(snapshot.entities.len(), 3);
+        assert_eq!(snapshot.sample_rate, 48000);
+        assert_eq!(snapshot.watched, [Position::new(0, 0)]);
+        assert!(!snapshot.connections.is_empty());
+        assert_eq!(snapshot.connections, worklet.engine.grid.get_connections());
+        assert_eq!(snapshot.time, worklet.controller.get_snapshot().time);
+
+        let euclid = snapshot.get_entity(Position::new(1, 0)).unwrap();
+        assert_eq!(euclid.kind, EntityType::Euclid);
+        assert!(euclid.grid_display.is_some());
+        assert_eq!(
+            euclid.settings.len(),
+            EntityType::Euclid.default_settings().len()
+        );
+
+        // samples are matched by name to the ones sent through the relay
+        let sampler = snapshot.get_entity(Position::new(0, 1)).unwrap();
+        assert!(sampler
+            .sample
+            .as_ref()
+            .is_some_and(|s| Arc::ptr_eq(s, &sample)));
+
+        // errors keep their text
+        assert_eq!(
+            controller.take_error().map(|e| e.to_string()),
+            Some(Error::CapacityReached(3).to_string())
+        );
+        assert!(!controller.receive_gates().is_empty());
+        assert!(!controller.receive_tap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_message() {
+        let storage = TestStorage::new(64);
+        let state = TestStorage::new(1024);
+        let mut worklet = Worklet::new(48000, MessageRing::new(&storage), MessageRing::new(&state));
+        let (mut relay, mut controller) =
+            Relay::new(MessageRing::new(&storage), MessageRing::new(&state), 1);
+
+        MessageRing::new(&storage).push(&[42]).unwrap();
+        process(&mut worklet, STATE_INTERVAL * 2);
+        relay.update();
+        controller.update();
+
+        assert!(matches!(controller.take_error(), Some(Error::Worklet(_))));
+    }
+}
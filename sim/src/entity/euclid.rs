@@ -0,0 +1,329 @@
+use super::{
+    merge_inputs, write_values, zeroed, Entity, EntityKind, EntityMutKind, Setting, SettingValue,
+};
+use crate::grid::Position;
+use crate::{Color, Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+use std::f32::consts::PI;
+
+/// Longest pattern, pulses and rotation are limited to it as well
+const MAX_STEPS: usize = 32;
+
+pub struct Euclid {
+    id: usize,
+    grid_position: Position,
+    steps: usize,
+    pulses: usize,
+    rotation: usize,
+    /// current step in the pattern, `None` before the first clock
+    step: Option<usize>,
+    clock: bool,
+    pub output: Output,
+    pub accent: Output,
+    input: Input,
+    /// buffers kept between calls to `sample` so sampling does not allocate
+    input_buffer: Vec<f32>,
+    gate_buffer: Vec<f32>,
+    accent_buffer: Vec<f32>,
+}
+
+impl Euclid {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        Euclid {
+            id,
+            output: screech.init_output(&id, "output"),
+            accent: screech.init_output(&id, "accent"),
+            input: screech.init_input(&id, "clock"),
+            grid_position: Position::origin(),
+            steps: 8,
+            pulses: 3,
+            rotation: 0,
+            step: None,
+            clock: false,
+            input_buffer: vec![],
+            gate_buffer: vec![],
+            accent_buffer: vec![],
+        }
+    }
+
+    /// Returns if there is a pulse for the given step of the rotated pattern
+    pub fn is_pulse(&self, step: usize) -> bool {
+        if self.steps == 0 {
+            return false;
+        }
+
+        let pulses = self.pulses.min(self.steps);
+        let step = (step + self.rotation) % self.steps;
+
+        (step * pulses) % self.steps < pulses
+    }
+
+    /// Returns if the step holds the first pulse of the pattern
+    pub fn is_accent(&self, step: usize) -> bool {
+        self.is_pulse(step) && (0..step).all(|s| !self.is_pulse(s))
+    }
+}
+
+impl Entity for Euclid {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        // the buffers are taken out so the pattern can be read while they are filled
+        let mut input_buffer = std::mem::take(&mut self.input_buffer);
+        let mut gate_buffer = std::mem::take(&mut self.gate_buffer);
+        let mut accent_buffer = std::mem::take(&mut self.accent_buffer);
+        let clock_in = merge_inputs(tracker, &self.input, &mut input_buffer)?;
+        let gate = zeroed(&mut gate_buffer, clock_in.len());
+        let accent = zeroed(&mut accent_buffer, clock_in.len());
+
+        for (i, &c) in clock_in.iter().enumerate() {
+            let clock = c >= 0.5;
+
+            // advance the pattern on the rising edge of the clock
+            if clock && !self.clock {
+                self.step = match self.step {
+                    Some(step) if self.steps > 0 => Some((step + 1) % self.steps),
+                    _ => Some(0),
+                };
+            }
+
+            self.clock = clock;
+
+            if let Some(step) = self.step.filter(|_| clock) {
+                gate[i] = if self.is_pulse(step) { 1.0 } else { 0.0 };
+                accent[i] = if self.is_accent(step) { 1.0 } else { 0.0 };
+            }
+        }
+
+        tracker
+            .get_mut_output(&self.output)
+            .ok_or(Error::MissingOutput(self.output))?
+            .samples
+            .copy_from_slice(gate);
+
+        tracker
+            .get_mut_output(&self.accent)
+            .ok_or(Error::MissingOutput(self.accent))?
+            .samples
+            .copy_from_slice(accent);
+
+        self.input_buffer = input_buffer;
+        self.gate_buffer = gate_buffer;
+        self.accent_buffer = accent_buffer;
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        let mut image = None;
+        self.render_grid_display(&mut image);
+        image
+    }
+
+    fn render_grid_display(&self, image: &mut Option<Image>) {
+        let image = match image {
+            Some(image) if image.width == 16 && image.height == 16 => image,
+            _ => image.insert(Image::new(16, 16)),
+        };
+        image.clear(Color::empty());
+
+        // render the pattern as a ring of dots, starting at the top
+        for step in 0..self.steps {
+            let angle = step as f32 / self.steps as f32 * PI * 2.0;
+            let x = 7.0 + angle.sin() * 6.0;
+            let y = 7.0 - angle.cos() * 6.0;
+
+            let color = match (self.step == Some(step), self.is_pulse(step)) {
+                (true, _) => Color::new(251, 255, 38, 255),
+                (false, true) => Color::full(),
+                (false, false) => Color::new(255, 255, 255, 64),
+            };
+
+            image.add_rect(x.round() as i32, y.round() as i32, 2, 2, color);
+        }
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_display_level(&self) -> Option<f32> {
+        self.step
+            .map(|step| (step + 1) as f32 / self.steps.max(1) as f32)
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Integer(self.steps), "steps")
+                .with_range(1.0, MAX_STEPS as f32),
+            Setting::new(SettingValue::Integer(self.pulses), "pulses")
+                .with_range(0.0, MAX_STEPS as f32),
+            Setting::new(SettingValue::Integer(self.rotation), "rotation")
+                .with_range(0.0, MAX_STEPS as f32),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Integer(self.steps),
+                SettingValue::Integer(self.pulses),
+                SettingValue::Integer(self.rotation),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Integer(v), "steps") => {
+                self.steps = (*v).clamp(1, MAX_STEPS);
+                // a shorter pattern continues from the same place in its own length
+                self.step = self.step.map(|step| step % self.steps);
+            }
+            (SettingValue::Integer(v), "pulses") => self.pulses = (*v).min(MAX_STEPS),
+            (SettingValue::Integer(v), "rotation") => self.rotation = (*v).min(MAX_STEPS),
+            _ => (),
+        }
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::UP) | (Some(output), Position::LEFT) => {
+                vec![(output, self.input)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::Euclid(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::Euclid(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    fn pattern(euclid: &Euclid) -> String {
+        (0..euclid.steps)
+            .map(|s| if euclid.is_pulse(s) { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut screech = Screech::new(4, 4);
+        let mut euclid = Euclid::new(&mut screech);
+
+        assert_eq!(pattern(&euclid), "x..x..x.");
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(5), "pulses"));
+        assert_eq!(pattern(&euclid), "x.x.xx.x");
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(1), "rotation"));
+        assert_eq!(pattern(&euclid), ".x.xx.xx");
+        assert!(euclid.is_accent(1));
+        assert!(!euclid.is_accent(3));
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(12), "pulses"));
+        assert_eq!(pattern(&euclid), "xxxxxxxx");
+    }
+
+    #[test]
+    fn test_setting_bounds() {
+        let mut screech = Screech::new(4, 4);
+        let mut euclid = Euclid::new(&mut screech);
+
+        for name in ["steps", "pulses", "rotation"] {
+            euclid.update_setting(&Setting::new(SettingValue::Integer(usize::MAX), name));
+        }
+        assert_eq!(
+            (euclid.steps, euclid.pulses, euclid.rotation),
+            (MAX_STEPS, MAX_STEPS, MAX_STEPS)
+        );
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(0), "steps"));
+        assert_eq!(euclid.steps, 1);
+
+        // the current step wraps into a pattern that got shorter
+        euclid.update_setting(&Setting::new(SettingValue::Integer(8), "steps"));
+        euclid.step = Some(6);
+        euclid.update_setting(&Setting::new(SettingValue::Integer(4), "steps"));
+        assert_eq!(euclid.step, Some(2));
+        assert_eq!(euclid.get_display_level(), Some(0.75));
+    }
+
+    #[test]
+    fn test_render_grid_display() {
+        let mut screech = Screech::new(4, 4);
+        let mut euclid = Euclid::new(&mut screech);
+        let mut image = euclid.get_grid_display();
+        let storage = image.as_ref().map(|image| image.data.as_ptr());
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(5), "pulses"));
+        euclid.step = Some(3);
+        euclid.render_grid_display(&mut image);
+
+        // the pattern is drawn over the previous one in the same image
+        assert_eq!(image.as_ref().map(|image| image.data.as_ptr()), storage);
+        assert!(image.map(|image| image.data) == euclid.get_grid_display().map(|image| image.data));
+    }
+
+    #[test]
+    fn test_clock() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(8)), 4);
+        screech.create_main_out("gate");
+        screech.create_main_out("accent");
+
+        let mut clock = TestSource::new(&mut screech, &[1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 1.0]);
+        let mut euclid = Euclid::new(&mut screech);
+
+        euclid.update_setting(&Setting::new(SettingValue::Integer(3), "steps"));
+        euclid.update_setting(&Setting::new(SettingValue::Integer(2), "pulses"));
+
+        screech.connect_signal(&clock.output, &euclid.input);
+        screech.connect_signal_to_main_out(&euclid.output, "gate");
+        screech.connect_signal_to_main_out(&euclid.accent, "accent");
+
+        screech
+            .sample(&mut [
+                &mut clock as &mut dyn Source,
+                &mut EntitySource::new(&mut euclid),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("gate").unwrap().samples,
+            vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0]
+        );
+        assert_eq!(
+            screech.get_main_out("accent").unwrap().samples,
+            vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0]
+        );
+    }
+}
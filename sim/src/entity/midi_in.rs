@@ -0,0 +1,259 @@
+use super::{write_values, Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use crate::grid::Position;
+use crate::midi::MidiMessage;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+/// Turn a MIDI note number into a pitch in semitones relative to middle C
+pub fn note_to_pitch(note: u8) -> f32 {
+    note as f32 - 60.0
+}
+
+/// Outputs a pitch/gate pair for the notes received over MIDI,
+/// the most recently pressed note that is still held sets the pitch
+pub struct MidiIn {
+    id: usize,
+    grid_position: Position,
+    /// listen to a single channel from 1 to 16, 0 listens to all channels
+    channel: usize,
+    notes: Vec<u8>,
+    pitch: f32,
+    /// drop the gate for a single sample so a new note retriggers
+    retrigger: bool,
+    pub pitch_output: Output,
+    pub gate_output: Output,
+}
+
+impl MidiIn {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        MidiIn {
+            id,
+            pitch_output: screech.init_output(&id, "pitch"),
+            gate_output: screech.init_output(&id, "gate"),
+            grid_position: Position::origin(),
+            channel: 0,
+            notes: vec![],
+            pitch: 0.0,
+            retrigger: false,
+        }
+    }
+
+    fn accepts(&self, channel: u8) -> bool {
+        self.channel == 0 || self.channel == channel as usize + 1
+    }
+}
+
+impl Entity for MidiIn {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        let gate = !self.notes.is_empty();
+
+        if let Some(&note) = self.notes.last() {
+            self.pitch = note_to_pitch(note);
+        }
+
+        let pitch = tracker
+            .get_mut_output(&self.pitch_output)
+            .ok_or(Error::MissingOutput(self.pitch_output))?;
+
+        for s in pitch.samples.iter_mut() {
+            *s = self.pitch;
+        }
+
+        let signal = tracker
+            .get_mut_output(&self.gate_output)
+            .ok_or(Error::MissingOutput(self.gate_output))?;
+
+        for (i, s) in signal.samples.iter_mut().enumerate() {
+            *s = if gate && !(i == 0 && self.retrigger) {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        self.retrigger = false;
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![Setting::new(SettingValue::Integer(self.channel), "channel").with_range(0.0, 16.0)]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(settings, [SettingValue::Integer(self.channel)]);
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        if let (SettingValue::Integer(v), "channel") =
+            (&setting.value, setting.description.as_ref())
+        {
+            self.channel = (*v).min(16);
+            self.notes.clear();
+        }
+    }
+
+    fn handle_midi(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn { channel, note, .. } if self.accepts(channel) => {
+                self.retrigger = !self.notes.is_empty();
+                self.notes.retain(|&n| n != note);
+                self.notes.push(note);
+            }
+            MidiMessage::NoteOff { channel, note, .. } if self.accepts(channel) => {
+                self.notes.retain(|&n| n != note);
+            }
+            _ => (),
+        }
+    }
+
+    fn find_connections(
+        &self,
+        _entity: &EntityKind,
+        _relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        vec![]
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::MidiIn(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::MidiIn(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, Voice};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    fn note_on(channel: u8, note: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            channel,
+            note,
+            velocity: 100,
+        }
+    }
+
+    fn note_off(channel: u8, note: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            channel,
+            note,
+            velocity: 0,
+        }
+    }
+
+    fn sample(screech: &mut Screech, midi_in: &mut MidiIn) -> (Vec<f32>, Vec<f32>) {
+        screech
+            .sample(&mut [&mut EntitySource::new(midi_in) as &mut dyn Source])
+            .unwrap();
+
+        (
+            screech.get_main_out("pitch").unwrap().samples.clone(),
+            screech.get_main_out("gate").unwrap().samples.clone(),
+        )
+    }
+
+    #[test]
+    fn test_last_note_priority() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(3)), 4);
+        screech.create_main_out("pitch");
+        screech.create_main_out("gate");
+
+        let mut midi_in = MidiIn::new(&mut screech);
+        screech.connect_signal_to_main_out(&midi_in.pitch_output, "pitch");
+        screech.connect_signal_to_main_out(&midi_in.gate_output, "gate");
+
+        midi_in.handle_midi(&note_on(0, 60));
+        assert_eq!(
+            sample(&mut screech, &mut midi_in),
+            (vec![0.0; 3], vec![1.0; 3])
+        );
+
+        // a second note retriggers the gate and takes over the pitch
+        midi_in.handle_midi(&note_on(0, 67));
+        assert_eq!(
+            sample(&mut screech, &mut midi_in),
+            (vec![7.0; 3], vec![0.0, 1.0, 1.0])
+        );
+
+        // releasing it falls back to the note still held
+        midi_in.handle_midi(&note_off(0, 67));
+        assert_eq!(
+            sample(&mut screech, &mut midi_in),
+            (vec![0.0; 3], vec![1.0; 3])
+        );
+
+        // the pitch holds after the last note is released
+        midi_in.handle_midi(&note_off(0, 60));
+        assert_eq!(
+            sample(&mut screech, &mut midi_in),
+            (vec![0.0; 3], vec![0.0; 3])
+        );
+    }
+
+    #[test]
+    fn test_channel_filter() {
+        let mut screech = Screech::new(4, 48000);
+        let mut midi_in = MidiIn::new(&mut screech);
+
+        midi_in.update_setting(&Setting::new(SettingValue::Integer(2), "channel"));
+        midi_in.handle_midi(&note_on(0, 60));
+        assert!(midi_in.notes.is_empty());
+
+        midi_in.handle_midi(&note_on(1, 60));
+        assert_eq!(midi_in.notes, vec![60]);
+    }
+
+    #[test]
+    fn test_pitch_through_voice() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(2)), 4);
+        screech.create_main_out("pitch");
+
+        let mut midi_in = MidiIn::new(&mut screech);
+        let mut voice = Voice::new(&mut screech);
+        voice.update_setting(&Setting::new(SettingValue::Boolean(false), "gate"));
+
+        for (output, input) in voice.find_connections(&midi_in.as_kind(), Position::LEFT) {
+            screech.connect_signal(&output, &input);
+        }
+        screech.connect_signal_to_main_out(&voice.output, "pitch");
+
+        midi_in.handle_midi(&note_on(0, 67));
+        screech
+            .sample(&mut [
+                &mut EntitySource::new(&mut midi_in) as &mut dyn Source,
+                &mut EntitySource::new(&mut voice),
+            ])
+            .unwrap();
+
+        assert_eq!(screech.get_main_out("pitch").unwrap().samples, vec![7.0; 2]);
+    }
+}
@@ -0,0 +1,247 @@
+use super::{merge_inputs, write_values, Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use crate::grid::Position;
+use crate::midi::MidiMessage;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+/// Turn a pitch in semitones relative to middle C into the nearest MIDI note number
+pub fn pitch_to_note(pitch: f32) -> u8 {
+    (pitch.round() + 60.0).clamp(0.0, 127.0) as u8
+}
+
+/// Sends a note for every gate received, using the pitch input at the rising edge
+pub struct MidiOut {
+    id: usize,
+    grid_position: Position,
+    /// channel from 1 to 16
+    channel: usize,
+    velocity: usize,
+    gate: bool,
+    /// channel and note of the note currently playing
+    note: Option<(u8, u8)>,
+    /// messages sent in the last buffer with their sample offset
+    events: Vec<(usize, MidiMessage)>,
+    pitch_input: Input,
+    gate_input: Input,
+    /// buffers kept between calls to `sample` so sampling does not allocate
+    pitch_buffer: Vec<f32>,
+    gate_buffer: Vec<f32>,
+}
+
+impl MidiOut {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        MidiOut {
+            id,
+            pitch_input: screech.init_input(&id, "pitch"),
+            gate_input: screech.init_input(&id, "gate"),
+            grid_position: Position::origin(),
+            channel: 1,
+            velocity: 100,
+            gate: false,
+            note: None,
+            events: vec![],
+            pitch_buffer: vec![],
+            gate_buffer: vec![],
+        }
+    }
+
+    fn note_off(&mut self, offset: usize) {
+        if let Some((channel, note)) = self.note.take() {
+            self.events.push((
+                offset,
+                MidiMessage::NoteOff {
+                    channel,
+                    note,
+                    velocity: 0,
+                },
+            ));
+        }
+    }
+
+    /// Note off for the note currently playing, the next gate starts a new note
+    pub fn release_note(&mut self) -> Option<MidiMessage> {
+        self.gate = false;
+        self.note
+            .take()
+            .map(|(channel, note)| MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity: 0,
+            })
+    }
+}
+
+impl Entity for MidiOut {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        // the buffers are taken out so notes can be sent while they are read
+        let mut pitch_buffer = std::mem::take(&mut self.pitch_buffer);
+        let mut gate_buffer = std::mem::take(&mut self.gate_buffer);
+        let pitch_in = merge_inputs(tracker, &self.pitch_input, &mut pitch_buffer)?;
+        let gate_in = merge_inputs(tracker, &self.gate_input, &mut gate_buffer)?;
+
+        self.events.clear();
+
+        for (i, (&pitch, &gate)) in pitch_in.iter().zip(gate_in.iter()).enumerate() {
+            let gate = gate >= 0.5;
+
+            if gate && !self.gate {
+                let channel = (self.channel.clamp(1, 16) - 1) as u8;
+                let note = pitch_to_note(pitch);
+
+                self.note_off(i);
+                self.note = Some((channel, note));
+                self.events.push((
+                    i,
+                    MidiMessage::NoteOn {
+                        channel,
+                        note,
+                        velocity: self.velocity.clamp(1, 127) as u8,
+                    },
+                ));
+            } else if !gate && self.gate {
+                self.note_off(i);
+            }
+
+            self.gate = gate;
+        }
+
+        self.pitch_buffer = pitch_buffer;
+        self.gate_buffer = gate_buffer;
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Integer(self.channel), "channel").with_range(1.0, 16.0),
+            Setting::new(SettingValue::Integer(self.velocity), "velocity").with_range(1.0, 127.0),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Integer(self.channel),
+                SettingValue::Integer(self.velocity),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Integer(v), "channel") => self.channel = (*v).clamp(1, 16),
+            (SettingValue::Integer(v), "velocity") => self.velocity = (*v).clamp(1, 127),
+            _ => (),
+        }
+    }
+
+    fn drain_midi(&mut self, events: &mut Vec<(usize, MidiMessage)>) {
+        events.append(&mut self.events);
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::LEFT) => vec![(output, self.pitch_input)],
+            (Some(output), Position::UP) => vec![(output, self.gate_input)],
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::MidiOut(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::MidiOut(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_gate_to_notes() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(5)), 4);
+
+        let mut pitch = TestSource::new(&mut screech, &[0.0, 0.0, 7.2, 7.2, 7.2]);
+        let mut gate = TestSource::new(&mut screech, &[1.0, 0.0, 1.0, 1.0, 1.0]);
+        let mut midi_out = MidiOut::new(&mut screech);
+
+        midi_out.update_setting(&Setting::new(SettingValue::Integer(3), "channel"));
+        screech.connect_signal(&pitch.output, &midi_out.pitch_input);
+        screech.connect_signal(&gate.output, &midi_out.gate_input);
+
+        screech
+            .sample(&mut [
+                &mut pitch as &mut dyn Source,
+                &mut gate,
+                &mut EntitySource::new(&mut midi_out),
+            ])
+            .unwrap();
+
+        let mut events = vec![];
+        midi_out.drain_midi(&mut events);
+
+        assert_eq!(
+            events,
+            vec![
+                (
+                    0,
+                    MidiMessage::NoteOn {
+                        channel: 2,
+                        note: 60,
+                        velocity: 100
+                    }
+                ),
+                (
+                    1,
+                    MidiMessage::NoteOff {
+                        channel: 2,
+                        note: 60,
+                        velocity: 0
+                    }
+                ),
+                (
+                    2,
+                    MidiMessage::NoteOn {
+                        channel: 2,
+                        note: 67,
+                        velocity: 100
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -1,50 +1,445 @@
+mod euclid;
+mod midi_in;
+mod midi_out;
+mod parameter;
+mod sample_hold;
+mod sampler;
 mod setting;
+mod slew;
 mod step;
 mod trigger;
+mod voice;
+mod voice_allocator;
 
 use crate::grid::Position;
-use crate::Image;
-use screech::traits::Source;
-// use screech::{Input, Output};
-pub use setting::{Setting, SettingValue};
-use step::Step;
-use trigger::Trigger;
-
-pub trait UpcastSource {
-    fn as_mut_source(&mut self) -> &mut dyn Source;
-}
-
-impl<T: Source + Entity> UpcastSource for T {
-    fn as_mut_source(&mut self) -> &mut dyn Source {
-        self
-    }
-}
+use crate::midi::MidiMessage;
+use crate::transport::Clock;
+use crate::{Error, Image, Sample};
+pub use euclid::Euclid;
+pub use midi_in::{note_to_pitch, MidiIn};
+pub use midi_out::MidiOut;
+pub use parameter::{Parameter, Smoothing};
+pub use sample_hold::SampleHold;
+pub use sampler::Sampler;
+use screech::traits::{Source, Tracker};
+use screech::{Input, Output, Screech};
+pub use setting::{write_values, Setting, SettingValue};
+pub use slew::Slew;
+use std::sync::Arc;
+pub use step::Step;
+pub use trigger::Trigger;
+pub use voice::Voice;
+pub use voice_allocator::VoiceAllocator;
 
 pub enum EntityKind<'a> {
     Step(&'a Step),
     Trigger(&'a Trigger),
+    Sampler(&'a Sampler),
+    SampleHold(&'a SampleHold),
+    Slew(&'a Slew),
+    Euclid(&'a Euclid),
+    VoiceAllocator(&'a VoiceAllocator),
+    MidiIn(&'a MidiIn),
+    MidiOut(&'a MidiOut),
+    Voice(&'a Voice),
 }
 
 pub enum EntityMutKind<'a> {
     Step(&'a mut Step),
     Trigger(&'a mut Trigger),
+    Sampler(&'a mut Sampler),
+    SampleHold(&'a mut SampleHold),
+    Slew(&'a mut Slew),
+    Euclid(&'a mut Euclid),
+    VoiceAllocator(&'a mut VoiceAllocator),
+    MidiIn(&'a mut MidiIn),
+    MidiOut(&'a mut MidiOut),
+    Voice(&'a mut Voice),
 }
 
-pub trait Entity: Source + UpcastSource {
+/// Kinds of entities that can be placed on the grid by name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntityType {
+    Step,
+    Trigger,
+    Sampler,
+    SampleHold,
+    Slew,
+    Euclid,
+    VoiceAllocator,
+    MidiIn,
+    MidiOut,
+    Voice,
+}
+
+impl EntityType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "step" => Some(EntityType::Step),
+            "trigger" => Some(EntityType::Trigger),
+            "sampler" => Some(EntityType::Sampler),
+            "samplehold" => Some(EntityType::SampleHold),
+            "slew" => Some(EntityType::Slew),
+            "euclid" => Some(EntityType::Euclid),
+            "voices" => Some(EntityType::VoiceAllocator),
+            "midiin" => Some(EntityType::MidiIn),
+            "midiout" => Some(EntityType::MidiOut),
+            "voice" => Some(EntityType::Voice),
+            _ => None,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            EntityType::Step => "step",
+            EntityType::Trigger => "trigger",
+            EntityType::Sampler => "sampler",
+            EntityType::SampleHold => "samplehold",
+            EntityType::Slew => "slew",
+            EntityType::Euclid => "euclid",
+            EntityType::VoiceAllocator => "voices",
+            EntityType::MidiIn => "midiin",
+            EntityType::MidiOut => "midiout",
+            EntityType::Voice => "voice",
+        }
+    }
+
+    pub fn create(&self, screech: &mut Screech) -> Box<dyn Entity> {
+        match self {
+            EntityType::Step => Box::new(Step::new(screech)),
+            EntityType::Trigger => Box::new(Trigger::new(screech)),
+            EntityType::Sampler => Box::new(Sampler::new(screech)),
+            EntityType::SampleHold => Box::new(SampleHold::new(screech)),
+            EntityType::Slew => Box::new(Slew::new(screech)),
+            EntityType::Euclid => Box::new(Euclid::new(screech)),
+            EntityType::VoiceAllocator => Box::new(VoiceAllocator::new(screech)),
+            EntityType::MidiIn => Box::new(MidiIn::new(screech)),
+            EntityType::MidiOut => Box::new(MidiOut::new(screech)),
+            EntityType::Voice => Box::new(Voice::new(screech)),
+        }
+    }
+
+    /// Settings of a newly created entity, used to parse values for it before it is placed
+    pub fn default_settings(&self) -> Vec<Setting> {
+        self.create(&mut Screech::new(1, 1)).get_settings()
+    }
+
+    /// Character drawn for the entity on the grid
+    pub fn get_glyph(&self) -> char {
+        match self {
+            EntityType::Step => 'S',
+            EntityType::Trigger => 'T',
+            EntityType::Sampler => 'P',
+            EntityType::SampleHold => 'H',
+            EntityType::Slew => 'W',
+            EntityType::Euclid => 'E',
+            EntityType::VoiceAllocator => 'V',
+            EntityType::MidiIn => 'I',
+            EntityType::MidiOut => 'O',
+            EntityType::Voice => 'N',
+        }
+    }
+}
+
+impl EntityKind<'_> {
+    pub fn get_type(&self) -> EntityType {
+        match self {
+            EntityKind::Step(_) => EntityType::Step,
+            EntityKind::Trigger(_) => EntityType::Trigger,
+            EntityKind::Sampler(_) => EntityType::Sampler,
+            EntityKind::SampleHold(_) => EntityType::SampleHold,
+            EntityKind::Slew(_) => EntityType::Slew,
+            EntityKind::Euclid(_) => EntityType::Euclid,
+            EntityKind::VoiceAllocator(_) => EntityType::VoiceAllocator,
+            EntityKind::MidiIn(_) => EntityType::MidiIn,
+            EntityKind::MidiOut(_) => EntityType::MidiOut,
+            EntityKind::Voice(_) => EntityType::Voice,
+        }
+    }
+
+    /// The output used when connecting the entity to its neighbours
+    pub fn get_output(&self) -> Option<Output> {
+        match self {
+            EntityKind::Step(step) => Some(step.output),
+            EntityKind::Trigger(trigger) => Some(trigger.output),
+            EntityKind::Sampler(sampler) => Some(sampler.output_left),
+            EntityKind::SampleHold(sample_hold) => Some(sample_hold.output),
+            EntityKind::Slew(slew) => Some(slew.output),
+            EntityKind::Euclid(euclid) => Some(euclid.output),
+            EntityKind::VoiceAllocator(voices) => voices.gate_outputs.first().copied(),
+            EntityKind::MidiIn(midi_in) => Some(midi_in.gate_output),
+            EntityKind::MidiOut(_) => None,
+            EntityKind::Voice(voice) => Some(voice.output),
+        }
+    }
+
+    /// Pitch and gate outputs of every voice, for entities playing more than one note
+    pub fn get_voices(&self) -> Option<(&[Output], &[Output])> {
+        match self {
+            EntityKind::VoiceAllocator(voices) => {
+                Some((&voices.pitch_outputs, &voices.gate_outputs))
+            }
+            EntityKind::Voice(voice) => Some((&voice.pitch_outputs, &voice.gate_outputs)),
+            EntityKind::MidiIn(midi_in) => Some((
+                std::slice::from_ref(&midi_in.pitch_output),
+                std::slice::from_ref(&midi_in.gate_output),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Every output of the entity
+    pub fn get_outputs(&self) -> Vec<Output> {
+        match self {
+            EntityKind::Step(step) => vec![step.output],
+            EntityKind::Trigger(trigger) => vec![trigger.output],
+            EntityKind::Sampler(sampler) => vec![sampler.output_left, sampler.output_right],
+            EntityKind::SampleHold(sample_hold) => vec![sample_hold.output],
+            EntityKind::Slew(slew) => vec![slew.output],
+            EntityKind::Euclid(euclid) => vec![euclid.output, euclid.accent],
+            EntityKind::VoiceAllocator(voices) => {
+                [&voices.pitch_outputs[..], &voices.gate_outputs[..]].concat()
+            }
+            EntityKind::MidiIn(midi_in) => vec![midi_in.pitch_output, midi_in.gate_output],
+            EntityKind::MidiOut(_) => vec![],
+            EntityKind::Voice(voice) => [
+                &[voice.output][..],
+                &voice.pitch_outputs,
+                &voice.gate_outputs,
+            ]
+            .concat(),
+        }
+    }
+
+    /// The sample played by the entity, shared with copies of the entity
+    pub fn get_sample(&self) -> Option<&Arc<Sample>> {
+        match self {
+            EntityKind::Sampler(sampler) => sampler.get_sample(),
+            _ => None,
+        }
+    }
+}
+
+pub trait Entity: Send {
+    /// fill the output buffers of the entity, called by [`EntitySource`] during sampling
+    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) -> Result<(), Error>;
+    fn get_source_id(&self) -> &usize;
+
     fn set_position(&mut self, position: Position);
     fn get_position(&self) -> Position;
 
     fn get_grid_display(&self) -> Option<Image>;
+    /// draw the grid display into the image of a previous snapshot, entities with
+    /// a display draw into the image they are given so snapshots do not allocate
+    fn render_grid_display(&self, image: &mut Option<Image>) {
+        *image = self.get_grid_display();
+    }
     fn get_detail_display(&self) -> Option<Image>;
+    /// state shown with the glyph on the grid between 0.0 and 1.0,
+    /// like the charge of a step or the phase of a trigger
+    fn get_display_level(&self) -> Option<f32> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting>;
+    /// copy the current values into settings returned by `get_settings` before,
+    /// so snapshots are refreshed without allocating
+    fn write_settings(&self, settings: &mut [Setting]);
+    fn update_setting(&mut self, setting: &Setting);
+    /// apply a setting at a sample offset within the next buffer,
+    /// entities without automated parameters apply it at the start of the buffer,
+    /// changes past the limit of a `Parameter` in one buffer are rejected
+    fn schedule_setting(&mut self, _offset: usize, setting: &Setting) -> Result<(), Error> {
+        self.update_setting(setting);
+        Ok(())
+    }
+    /// respond to notes received over MIDI, called before the next buffer is sampled
+    fn handle_midi(&mut self, _message: &MidiMessage) {}
+    /// move the messages sent during the last buffer into `events`,
+    /// together with their sample offset in the buffer
+    fn drain_midi(&mut self, _events: &mut Vec<(usize, MidiMessage)>) {}
+    /// position of the transport at the start of the next buffer
+    fn set_clock(&mut self, _clock: &Clock) {}
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)>;
+
+    fn as_kind(&self) -> EntityKind<'_>;
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_>;
+}
+
+/// Wraps an entity so it can be sampled by screech, keeping the result of sampling
+pub struct EntitySource<'a> {
+    pub entity: &'a mut dyn Entity,
+    pub result: Result<(), Error>,
+    /// peak of the primary output in the last buffer
+    pub level: f32,
+    /// state of the gate on the primary output, only tracked when set
+    pub gate: Option<bool>,
+    /// sample offsets where the gate opened or closed
+    pub gates: Vec<(usize, bool)>,
+    /// copy of the primary output in the last buffer, only filled when set
+    pub tap: Option<Vec<f32>>,
+    /// outputs of other voices added to the outputs of the entity after sampling,
+    /// as pairs of the output of a voice and the output of the entity
+    pub mix: &'a [(Output, Output)],
+    /// the entity plays another voice of an entity on the grid and is left out of the levels
+    pub copy: bool,
+}
+
+impl<'a> EntitySource<'a> {
+    pub fn new(entity: &'a mut dyn Entity) -> Self {
+        EntitySource {
+            entity,
+            result: Ok(()),
+            level: 0.0,
+            gate: None,
+            gates: vec![],
+            tap: None,
+            mix: &[],
+            copy: false,
+        }
+    }
+}
+
+impl Source for EntitySource<'_> {
+    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) {
+        self.result = self
+            .entity
+            .sample(tracker, sample_rate)
+            .and_then(|_| mix_outputs(tracker, self.mix));
 
-    // fn get_settings(&self) -> Vec<Setting>;
-    // fn update_setting(&mut self, setting: &Setting);
-    // fn find_connections(
-    //     &self,
-    //     entity: &EntityKind,
-    //     relative_position: Position,
-    // ) -> Vec<(Output, Input)>;
+        let signal = self
+            .entity
+            .as_kind()
+            .get_output()
+            .and_then(|output| tracker.get_output(&output));
+
+        self.level = signal
+            .map(|signal| signal.samples.iter().fold(0.0, |peak, s| s.abs().max(peak)))
+            .unwrap_or(0.0);
+
+        if let Some(tap) = self.tap.as_mut() {
+            tap.clear();
+            tap.extend(signal.iter().flat_map(|signal| signal.samples.iter()));
+        }
+
+        if let (Some(mut gate), Some(signal)) = (self.gate, signal) {
+            for (i, s) in signal.samples.iter().enumerate() {
+                if (*s >= 0.5) != gate {
+                    gate = !gate;
+                    self.gates.push((i, gate));
+                }
+            }
+
+            self.gate = Some(gate);
+        }
+    }
+
+    fn get_source_id(&self) -> &usize {
+        self.entity.get_source_id()
+    }
+}
+
+/// Merge all outputs connected to an input into a buffer of the entity,
+/// taking the highest value of the connected outputs for every sample.
+/// Starts from the first output so negative signals pass through,
+/// an input without connections is silent.
+pub fn merge_inputs<'a>(
+    tracker: &dyn Tracker,
+    input: &Input,
+    buffer: &'a mut Vec<f32>,
+) -> Result<&'a [f32], Error> {
+    let signal_in = zeroed(buffer, *tracker.get_buffer_size());
+    let outputs = tracker
+        .get_input(input)
+        .ok_or(Error::MissingInput(*input))?;
+
+    for (i, output) in outputs.iter().enumerate() {
+        let buffer = tracker
+            .get_output(output)
+            .ok_or(Error::MissingOutput(*output))?;
+
+        for (s, &b) in signal_in.iter_mut().zip(buffer.samples.iter()) {
+            if i == 0 || b > *s {
+                *s = b;
+            }
+        }
+    }
+
+    Ok(signal_in)
+}
 
-    fn as_kind(&self) -> EntityKind;
-    fn as_mut_kind(&mut self) -> EntityMutKind;
+/// Add outputs to other outputs, going through a chunk on the stack
+/// since the tracker lends out one signal at a time
+fn mix_outputs(tracker: &mut dyn Tracker, mix: &[(Output, Output)]) -> Result<(), Error> {
+    let mut chunk = [0.0; 64];
+    let length = *tracker.get_buffer_size();
+
+    for (from, to) in mix {
+        for start in (0..length).step_by(chunk.len()) {
+            let end = (start + chunk.len()).min(length);
+            let chunk = &mut chunk[..end - start];
+
+            chunk.copy_from_slice(
+                &tracker
+                    .get_output(from)
+                    .ok_or(Error::MissingOutput(*from))?
+                    .samples[start..end],
+            );
+
+            let samples = &mut tracker
+                .get_mut_output(to)
+                .ok_or(Error::MissingOutput(*to))?
+                .samples[start..end];
+            for (s, c) in samples.iter_mut().zip(chunk.iter()) {
+                *s += c;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Clear a buffer kept by an entity between calls to `sample` and fill it with
+/// silence, it only allocates when the buffer size grows
+pub fn zeroed(buffer: &mut Vec<f32>, length: usize) -> &mut [f32] {
+    buffer.clear();
+    buffer.resize(length, 0.0);
+    buffer
+}
+
+/// Source playing back a fixed buffer, used for driving entities in tests
+#[cfg(test)]
+pub struct TestSource {
+    id: usize,
+    pub output: screech::Output,
+    samples: Vec<f32>,
+}
+
+#[cfg(test)]
+impl TestSource {
+    pub fn new(screech: &mut screech::Screech, samples: &[f32]) -> Self {
+        let id = screech.create_source_id();
+
+        TestSource {
+            id,
+            output: screech.init_output(&id, "output"),
+            samples: samples.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Source for TestSource {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) {
+        let signal = tracker.get_mut_output(&self.output).unwrap();
+        signal.samples.copy_from_slice(&self.samples);
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
 }
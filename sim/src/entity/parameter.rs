@@ -0,0 +1,186 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    None,
+    /// reach the new value in a fixed time in seconds
+    Linear(f32),
+    /// move towards the new value with a time constant in seconds
+    Exponential(f32),
+}
+
+/// Changes a parameter keeps for one buffer, later changes are rejected
+pub const MAX_PENDING: usize = 32;
+
+/// Continuous value of an entity that can be changed at a sample offset within a buffer
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    value: f32,
+    target: f32,
+    increment: f32,
+    /// samples left until a linear ramp reaches the target
+    remaining: Option<usize>,
+    smoothing: Smoothing,
+    /// changes for the current buffer as sample offset and value, sorted by offset
+    pending: [(usize, f32); MAX_PENDING],
+    /// amount of changes in `pending`
+    pending_count: usize,
+}
+
+impl Parameter {
+    pub fn new(value: f32, smoothing: Smoothing) -> Self {
+        Parameter {
+            value,
+            target: value,
+            increment: 0.0,
+            remaining: None,
+            smoothing,
+            pending: [(0, 0.0); MAX_PENDING],
+            pending_count: 0,
+        }
+    }
+
+    /// Value the parameter is moving towards
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = smoothing;
+    }
+
+    /// Move towards a new value starting at the next sample
+    pub fn set(&mut self, value: f32) {
+        self.target = value;
+        self.remaining = None;
+    }
+
+    /// Change the value at a sample offset of the next buffer, returning false
+    /// when the buffer already has `MAX_PENDING` changes
+    pub fn schedule(&mut self, offset: usize, value: f32) -> bool {
+        if self.pending_count == MAX_PENDING {
+            return false;
+        }
+
+        let count = self.pending_count;
+        let index = self.pending[..count].partition_point(|(o, _)| *o <= offset);
+        self.pending.copy_within(index..count, index + 1);
+        self.pending[index] = (offset, value);
+        self.pending_count += 1;
+        true
+    }
+
+    /// Advance to the sample at `offset` in the current buffer and return the value
+    pub fn next(&mut self, offset: usize, sample_rate: usize) -> f32 {
+        let due = self.pending[..self.pending_count].partition_point(|(o, _)| *o <= offset);
+
+        if due > 0 {
+            self.set(self.pending[due - 1].1);
+            self.pending.copy_within(due..self.pending_count, 0);
+            self.pending_count -= due;
+        }
+
+        match self.smoothing {
+            Smoothing::Linear(time) if self.value != self.target => {
+                let remaining = match self.remaining {
+                    Some(remaining) => remaining,
+                    None => {
+                        let samples = ((time * sample_rate as f32) as usize).max(1);
+                        self.increment = (self.target - self.value) / samples as f32;
+                        samples
+                    }
+                };
+
+                if remaining <= 1 {
+                    self.value = self.target;
+                    self.remaining = None;
+                } else {
+                    self.value += self.increment;
+                    self.remaining = Some(remaining - 1);
+                }
+            }
+            Smoothing::Exponential(time) if self.value != self.target => {
+                let coefficient = 1.0 - (-1.0 / (time * sample_rate as f32)).exp();
+                self.value += (self.target - self.value) * coefficient;
+
+                if (self.target - self.value).abs() < 1e-6 {
+                    self.value = self.target;
+                }
+            }
+            _ => self.value = self.target,
+        }
+
+        self.value
+    }
+
+    /// Apply changes that were scheduled past the end of the buffer
+    pub fn end_buffer(&mut self) {
+        if self.pending_count > 0 {
+            self.set(self.pending[self.pending_count - 1].1);
+        }
+
+        self.pending_count = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(parameter: &mut Parameter, length: usize) -> Vec<f32> {
+        let values = (0..length).map(|i| parameter.next(i, 4)).collect();
+        parameter.end_buffer();
+        values
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut parameter = Parameter::new(0.0, Smoothing::None);
+
+        parameter.schedule(3, 2.0);
+        parameter.schedule(1, 1.0);
+
+        assert_eq!(render(&mut parameter, 4), vec![0.0, 1.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_schedule_full() {
+        let mut parameter = Parameter::new(0.0, Smoothing::None);
+
+        for i in 0..MAX_PENDING {
+            assert!(parameter.schedule(1, i as f32));
+        }
+        assert!(!parameter.schedule(0, -1.0));
+
+        // the rejected change is not applied, the latest kept change wins
+        assert_eq!(
+            render(&mut parameter, 2),
+            vec![0.0, (MAX_PENDING - 1) as f32]
+        );
+        assert!(parameter.schedule(0, 5.0));
+    }
+
+    #[test]
+    fn test_linear() {
+        let mut parameter = Parameter::new(0.0, Smoothing::Linear(1.0));
+
+        parameter.schedule(1, 1.0);
+        assert_eq!(
+            render(&mut parameter, 6),
+            vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0]
+        );
+
+        parameter.set(0.0);
+        assert_eq!(render(&mut parameter, 2), vec![0.75, 0.5]);
+    }
+
+    #[test]
+    fn test_exponential() {
+        let mut parameter = Parameter::new(0.0, Smoothing::Exponential(0.25));
+
+        parameter.set(1.0);
+        let values = render(&mut parameter, 3);
+
+        assert!((values[0] - 0.63212055).abs() < 1e-6);
+        assert!((values[1] - 0.86466473).abs() < 1e-6);
+        assert!(values[2] < 1.0);
+    }
+}
@@ -0,0 +1,174 @@
+use super::{merge_inputs, Entity, EntityKind, EntityMutKind, Setting};
+use crate::grid::Position;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+pub struct SampleHold {
+    id: usize,
+    grid_position: Position,
+    gate: bool,
+    pub value: f32,
+    pub output: Output,
+    input: Input,
+    gate_input: Input,
+    /// buffers kept between calls to `sample` so sampling does not allocate
+    input_buffer: Vec<f32>,
+    gate_buffer: Vec<f32>,
+}
+
+impl SampleHold {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        SampleHold {
+            id,
+            output: screech.init_output(&id, "output"),
+            input: screech.init_input(&id, "input"),
+            gate_input: screech.init_input(&id, "gate"),
+            grid_position: Position::origin(),
+            gate: false,
+            value: 0.0,
+            input_buffer: vec![],
+            gate_buffer: vec![],
+        }
+    }
+}
+
+impl Entity for SampleHold {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        let signal_in = merge_inputs(tracker, &self.input, &mut self.input_buffer)?;
+        let gate_in = merge_inputs(tracker, &self.gate_input, &mut self.gate_buffer)?;
+        let signal = tracker
+            .get_mut_output(&self.output)
+            .ok_or(Error::MissingOutput(self.output))?;
+
+        for (i, s) in signal.samples.iter_mut().enumerate() {
+            let gate = gate_in[i] >= 0.5;
+
+            // latch the input on the rising edge of the gate
+            if gate && !self.gate {
+                self.value = signal_in[i];
+            }
+
+            self.gate = gate;
+            *s = self.value;
+        }
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![]
+    }
+
+    fn write_settings(&self, _settings: &mut [Setting]) {}
+
+    fn update_setting(&mut self, _setting: &Setting) {}
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::LEFT) => vec![(output, self.input)],
+            (Some(output), Position::UP) => vec![(output, self.gate_input)],
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::SampleHold(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::SampleHold(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_latch_on_gate() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(5)), 4);
+        screech.create_main_out("out");
+
+        let mut input = TestSource::new(&mut screech, &[0.1, 0.2, 0.3, 0.4, 0.5]);
+        let mut gate = TestSource::new(&mut screech, &[1.0, 1.0, 0.0, 1.0, 0.0]);
+        let mut sample_hold = SampleHold::new(&mut screech);
+
+        screech.connect_signal(&input.output, &sample_hold.input);
+        screech.connect_signal(&gate.output, &sample_hold.gate_input);
+        screech.connect_signal_to_main_out(&sample_hold.output, "out");
+
+        screech
+            .sample(&mut [
+                &mut input as &mut dyn Source,
+                &mut gate,
+                &mut EntitySource::new(&mut sample_hold),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![0.1, 0.1, 0.1, 0.4, 0.4]
+        );
+    }
+
+    #[test]
+    fn test_negative_input() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<8>::new(3)), 4);
+        screech.create_main_out("out");
+
+        let mut low = TestSource::new(&mut screech, &[-0.8, -0.6, -0.2]);
+        let mut high = TestSource::new(&mut screech, &[-0.5, -0.7, -0.4]);
+        let mut gate = TestSource::new(&mut screech, &[1.0, 0.0, 1.0]);
+        let mut sample_hold = SampleHold::new(&mut screech);
+
+        // the highest of the connected outputs is held, below zero as well
+        screech.connect_signal(&low.output, &sample_hold.input);
+        screech.connect_signal(&high.output, &sample_hold.input);
+        screech.connect_signal(&gate.output, &sample_hold.gate_input);
+        screech.connect_signal_to_main_out(&sample_hold.output, "out");
+
+        screech
+            .sample(&mut [
+                &mut low as &mut dyn Source,
+                &mut high,
+                &mut gate,
+                &mut EntitySource::new(&mut sample_hold),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![-0.5, -0.5, -0.2]
+        );
+    }
+}
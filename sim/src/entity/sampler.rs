@@ -0,0 +1,305 @@
+use super::{
+    merge_inputs, write_values, zeroed, Entity, EntityKind, EntityMutKind, Parameter, Setting,
+    SettingValue, Smoothing,
+};
+use crate::grid::Position;
+use crate::{Error, Image, Sample};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+use std::sync::Arc;
+
+pub struct Sampler {
+    id: usize,
+    grid_position: Position,
+    sample: Option<Arc<Sample>>,
+    /// playback position in frames, `None` when not playing
+    position: Option<f32>,
+    gate: bool,
+    /// start of playback relative to the sample length, between 0.0 and 1.0
+    start: f32,
+    /// end of playback relative to the sample length, between 0.0 and 1.0
+    end: f32,
+    /// playback pitch in semitones
+    pitch: Parameter,
+    /// time constant of pitch changes in seconds, 0.0 changes pitch instantly
+    glide: f32,
+    looping: bool,
+    one_shot: bool,
+    pub output_left: Output,
+    pub output_right: Output,
+    input: Input,
+    /// buffers kept between calls to `sample` so sampling does not allocate
+    input_buffer: Vec<f32>,
+    left: Vec<f32>,
+    right: Vec<f32>,
+}
+
+impl Sampler {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        Sampler {
+            id,
+            output_left: screech.init_output(&id, "left"),
+            output_right: screech.init_output(&id, "right"),
+            input: screech.init_input(&id, "gate"),
+            grid_position: Position::origin(),
+            sample: None,
+            position: None,
+            gate: false,
+            start: 0.0,
+            end: 1.0,
+            pitch: Parameter::new(0.0, Smoothing::None),
+            glide: 0.0,
+            looping: false,
+            one_shot: true,
+            input_buffer: vec![],
+            left: vec![],
+            right: vec![],
+        }
+    }
+
+    /// Load a sample, resampling it to the engine sample rate if needed,
+    /// a sample at that rate already is shared instead of copied
+    pub fn load(&mut self, sample: Arc<Sample>, sample_rate: usize) -> &mut Self {
+        self.sample = Some(if sample.sample_rate == sample_rate {
+            sample
+        } else {
+            Arc::new(sample.resample(sample_rate))
+        });
+        self.position = None;
+        self
+    }
+
+    pub fn get_sample(&self) -> Option<&Arc<Sample>> {
+        self.sample.as_ref()
+    }
+}
+
+impl Entity for Sampler {
+    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) -> Result<(), Error> {
+        let signal_in = merge_inputs(tracker, &self.input, &mut self.input_buffer)?;
+        let left = zeroed(&mut self.left, signal_in.len());
+        let right = zeroed(&mut self.right, signal_in.len());
+
+        if let Some(sample) = &self.sample {
+            let length = sample.len() as f32;
+            let start = self.start.clamp(0.0, 1.0) * length;
+            let end = self.end.clamp(0.0, 1.0) * length;
+            for (i, &s) in signal_in.iter().enumerate() {
+                let rate = 2.0_f32.powf(self.pitch.next(i, sample_rate) / 12.0);
+                let gate = s >= 0.5;
+
+                if gate && !self.gate {
+                    self.position = Some(start);
+                } else if !gate && !self.one_shot {
+                    self.position = None;
+                }
+
+                self.gate = gate;
+
+                if let Some(position) = self.position.filter(|p| *p < end) {
+                    left[i] = sample.get(0, position);
+                    right[i] = sample.get(1, position);
+
+                    let next = position + rate;
+
+                    self.position = match (next >= end, self.looping) {
+                        (true, true) => Some(start + (next - end) % (end - start)),
+                        (true, false) => None,
+                        _ => Some(next),
+                    };
+                }
+            }
+        }
+
+        tracker
+            .get_mut_output(&self.output_left)
+            .ok_or(Error::MissingOutput(self.output_left))?
+            .samples
+            .copy_from_slice(left);
+
+        tracker
+            .get_mut_output(&self.output_right)
+            .ok_or(Error::MissingOutput(self.output_right))?
+            .samples
+            .copy_from_slice(right);
+
+        self.pitch.end_buffer();
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_display_level(&self) -> Option<f32> {
+        let length = self.sample.as_ref()?.len() as f32;
+        let position = self.position?;
+
+        Some((position / length.max(1.0)).clamp(0.0, 1.0))
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Float(self.start), "start").with_range(0.0, 1.0),
+            Setting::new(SettingValue::Float(self.end), "end").with_range(0.0, 1.0),
+            Setting::new(SettingValue::Float(self.pitch.target()), "pitch").with_range(-24.0, 24.0),
+            Setting::new(SettingValue::Float(self.glide), "glide").with_range(0.0, 1.0),
+            Setting::new(SettingValue::Boolean(self.looping), "loop"),
+            Setting::new(SettingValue::Boolean(self.one_shot), "oneshot"),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Float(self.start),
+                SettingValue::Float(self.end),
+                SettingValue::Float(self.pitch.target()),
+                SettingValue::Float(self.glide),
+                SettingValue::Boolean(self.looping),
+                SettingValue::Boolean(self.one_shot),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Float(v), "start") => self.start = *v,
+            (SettingValue::Float(v), "end") => self.end = *v,
+            (SettingValue::Float(v), "pitch") => self.pitch.set(*v),
+            (SettingValue::Float(v), "glide") => {
+                self.glide = v.max(0.0);
+                self.pitch.set_smoothing(if self.glide > 0.0 {
+                    Smoothing::Exponential(self.glide)
+                } else {
+                    Smoothing::None
+                });
+            }
+            (SettingValue::Boolean(v), "loop") => self.looping = *v,
+            (SettingValue::Boolean(v), "oneshot") => self.one_shot = *v,
+            _ => (),
+        }
+    }
+
+    fn schedule_setting(&mut self, offset: usize, setting: &Setting) -> Result<(), Error> {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Float(v), "pitch") if !self.pitch.schedule(offset, *v) => {
+                Err(Error::ScheduleFull)
+            }
+            (SettingValue::Float(_), "pitch") => Ok(()),
+            _ => {
+                self.update_setting(setting);
+                Ok(())
+            }
+        }
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::UP) | (Some(output), Position::LEFT) => {
+                vec![(output, self.input)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::Sampler(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::Sampler(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    fn render(sampler: &mut Sampler, screech: &mut Screech, gate: &mut TestSource) -> Vec<f32> {
+        screech
+            .sample(&mut [gate as &mut dyn Source, &mut EntitySource::new(sampler)])
+            .unwrap();
+        screech.get_main_out("out").unwrap().samples.clone()
+    }
+
+    fn setup(gate: &[f32]) -> (Screech, Sampler, TestSource) {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(gate.len())), 4);
+        screech.create_main_out("out");
+
+        let gate = TestSource::new(&mut screech, gate);
+        let mut sampler = Sampler::new(&mut screech);
+        sampler.load(
+            Arc::new(Sample::new("s", 4, vec![vec![0.1, 0.2, 0.3, 0.4]])),
+            4,
+        );
+
+        screech.connect_signal(&gate.output, &sampler.input);
+        screech.connect_signal_to_main_out(&sampler.output_left, "out");
+
+        (screech, sampler, gate)
+    }
+
+    #[test]
+    fn test_one_shot() {
+        let (mut screech, mut sampler, mut gate) = setup(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+
+        assert_eq!(
+            render(&mut sampler, &mut screech, &mut gate),
+            vec![0.1, 0.2, 0.3, 0.4, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_gated_loop() {
+        let (mut screech, mut sampler, mut gate) = setup(&[1.0, 1.0, 1.0, 1.0, 1.0, 0.0]);
+
+        sampler.update_setting(&Setting::new(SettingValue::Boolean(false), "oneshot"));
+        sampler.update_setting(&Setting::new(SettingValue::Boolean(true), "loop"));
+        sampler.update_setting(&Setting::new(SettingValue::Float(0.5), "start"));
+
+        assert_eq!(
+            render(&mut sampler, &mut screech, &mut gate),
+            vec![0.3, 0.4, 0.3, 0.4, 0.3, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_pitch() {
+        let (mut screech, mut sampler, mut gate) = setup(&[1.0, 0.0, 0.0, 0.0]);
+
+        sampler.update_setting(&Setting::new(SettingValue::Float(12.0), "pitch"));
+
+        assert_eq!(
+            render(&mut sampler, &mut screech, &mut gate),
+            vec![0.1, 0.3, 0.0, 0.0]
+        );
+    }
+}
@@ -1,16 +1,20 @@
-use std::error::Error;
+use crate::Error;
+use std::borrow::Cow;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SettingValue {
     Float(f32),
     Integer(usize),
+    Boolean(bool),
 }
 
-impl SettingValue {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for SettingValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SettingValue::Float(s) => s.to_string(),
-            SettingValue::Integer(s) => s.to_string(),
+            SettingValue::Float(s) => write!(f, "{}", s),
+            SettingValue::Integer(s) => write!(f, "{}", s),
+            SettingValue::Boolean(s) => write!(f, "{}", s),
         }
     }
 }
@@ -18,23 +22,145 @@ impl SettingValue {
 #[derive(Debug, Clone)]
 pub struct Setting {
     pub value: SettingValue,
-    pub description: String,
+    /// name of the setting, borrowed for the names entities use so making
+    /// or decoding a setting does not allocate
+    pub description: Cow<'static, str>,
+    /// range of values a controller sweeps through, see [`Setting::with_normalized`]
+    pub range: Option<(f32, f32)>,
 }
 
 impl Setting {
-    pub fn new(value: SettingValue, description: &str) -> Self {
+    pub fn new(value: SettingValue, description: impl Into<Cow<'static, str>>) -> Self {
         Setting {
             value,
             description: description.into(),
+            range: None,
         }
     }
 
-    pub fn try_update_value(&mut self, value: &str) -> Result<(), Box<dyn Error>> {
-        match self.value {
-            SettingValue::Float(_) => self.value = SettingValue::Float(value.parse::<f32>()?),
-            SettingValue::Integer(_) => self.value = SettingValue::Integer(value.parse::<usize>()?),
+    pub fn with_range(mut self, min: f32, max: f32) -> Self {
+        self.range = Some((min, max));
+        self
+    }
+
+    /// Copy of the setting with a value between 0.0 and 1.0 mapped onto its range,
+    /// without a range floats use 0.0 to 1.0 and integers 0 to 127
+    pub fn with_normalized(&self, value: f32) -> Self {
+        let value = value.clamp(0.0, 1.0);
+        let scale = |(min, max): (f32, f32)| min + (max - min) * value;
+
+        let value = match self.value {
+            SettingValue::Float(_) => SettingValue::Float(scale(self.range.unwrap_or((0.0, 1.0)))),
+            SettingValue::Integer(_) => SettingValue::Integer(
+                scale(self.range.unwrap_or((0.0, 127.0))).round().max(0.0) as usize,
+            ),
+            SettingValue::Boolean(_) => SettingValue::Boolean(value >= 0.5),
+        };
+
+        Setting {
+            value,
+            ..self.clone()
         }
+    }
+
+    /// Copy of the setting moved by a number of steps, a step is a hundredth of the
+    /// range for floats, one for integers and toggles booleans
+    pub fn with_steps(&self, steps: i32) -> Self {
+        let value = match self.value {
+            SettingValue::Float(v) => {
+                let (min, max) = self.range.unwrap_or((0.0, 1.0));
+                let v = v + (max - min) / 100.0 * steps as f32;
+
+                SettingValue::Float(match self.range {
+                    Some(_) => v.clamp(min, max),
+                    None => v,
+                })
+            }
+            SettingValue::Integer(v) => {
+                let v = (v as i64 + steps as i64).max(0) as usize;
+
+                SettingValue::Integer(match self.range {
+                    Some((min, max)) => v.clamp(min as usize, max as usize),
+                    None => v,
+                })
+            }
+            SettingValue::Boolean(v) => SettingValue::Boolean(v ^ (steps % 2 != 0)),
+        };
+
+        Setting {
+            value,
+            ..self.clone()
+        }
+    }
+
+    pub fn try_update_value(&mut self, value: &str) -> Result<(), Error> {
+        let invalid = || Error::InvalidSetting(self.description.to_string(), value.into());
+
+        self.value = match self.value {
+            SettingValue::Float(_) => SettingValue::Float(value.parse().map_err(|_| invalid())?),
+            SettingValue::Integer(_) => {
+                SettingValue::Integer(value.parse().map_err(|_| invalid())?)
+            }
+            SettingValue::Boolean(_) => {
+                SettingValue::Boolean(value.parse().map_err(|_| invalid())?)
+            }
+        };
 
         Ok(())
     }
 }
+
+/// Copy values into settings returned by `get_settings` before, in the same order
+pub fn write_values<const N: usize>(settings: &mut [Setting], values: [SettingValue; N]) {
+    for (setting, value) in settings.iter_mut().zip(values) {
+        setting.value = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_normalized() {
+        let bpm = Setting::new(SettingValue::Float(120.0), "bpm").with_range(20.0, 300.0);
+        let steps = Setting::new(SettingValue::Integer(8), "steps").with_range(1.0, 32.0);
+        let looping = Setting::new(SettingValue::Boolean(false), "loop");
+
+        assert!(matches!(bpm.with_normalized(0.5).value, SettingValue::Float(v) if v == 160.0));
+        assert!(matches!(
+            steps.with_normalized(1.0).value,
+            SettingValue::Integer(32)
+        ));
+        assert!(matches!(
+            looping.with_normalized(0.75).value,
+            SettingValue::Boolean(true)
+        ));
+    }
+
+    #[test]
+    fn test_with_steps() {
+        let bpm = Setting::new(SettingValue::Float(120.0), "bpm").with_range(20.0, 300.0);
+        let steps = Setting::new(SettingValue::Integer(2), "steps").with_range(1.0, 32.0);
+        let looping = Setting::new(SettingValue::Boolean(false), "loop");
+
+        assert!(matches!(bpm.with_steps(5).value, SettingValue::Float(v) if v == 134.0));
+        assert!(matches!(bpm.with_steps(-100).value, SettingValue::Float(v) if v == 20.0));
+        assert!(matches!(
+            steps.with_steps(-3).value,
+            SettingValue::Integer(1)
+        ));
+        assert!(matches!(
+            steps.with_steps(3).value,
+            SettingValue::Integer(5)
+        ));
+        assert!(matches!(
+            looping.with_steps(-1).value,
+            SettingValue::Boolean(true)
+        ));
+        assert!(matches!(
+            looping.with_steps(2).value,
+            SettingValue::Boolean(false)
+        ));
+    }
+}
@@ -0,0 +1,191 @@
+use super::{merge_inputs, write_values, Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use crate::grid::Position;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+pub struct Slew {
+    id: usize,
+    grid_position: Position,
+    /// time in seconds to rise from 0.0 to 1.0
+    rise: f32,
+    /// time in seconds to fall from 1.0 to 0.0
+    fall: f32,
+    pub value: f32,
+    pub output: Output,
+    input: Input,
+    /// buffer kept between calls to `sample` so sampling does not allocate
+    input_buffer: Vec<f32>,
+}
+
+impl Slew {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        Slew {
+            id,
+            output: screech.init_output(&id, "output"),
+            input: screech.init_input(&id, "input"),
+            grid_position: Position::origin(),
+            rise: 0.1,
+            fall: 0.1,
+            value: 0.0,
+            input_buffer: vec![],
+        }
+    }
+}
+
+/// Maximum change per sample for a slew time in seconds, zero time means no limit
+fn max_step(time: f32, sample_rate: usize) -> f32 {
+    if time > 0.0 {
+        1.0 / (time * sample_rate as f32)
+    } else {
+        f32::INFINITY
+    }
+}
+
+impl Entity for Slew {
+    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) -> Result<(), Error> {
+        let signal_in = merge_inputs(tracker, &self.input, &mut self.input_buffer)?;
+        let signal = tracker
+            .get_mut_output(&self.output)
+            .ok_or(Error::MissingOutput(self.output))?;
+        let rise = max_step(self.rise, sample_rate);
+        let fall = max_step(self.fall, sample_rate);
+
+        for (s, &target) in signal.samples.iter_mut().zip(signal_in.iter()) {
+            self.value += (target - self.value).clamp(-fall, rise);
+            *s = self.value;
+        }
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Float(self.rise), "rise").with_range(0.0, 2.0),
+            Setting::new(SettingValue::Float(self.fall), "fall").with_range(0.0, 2.0),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Float(self.rise),
+                SettingValue::Float(self.fall),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Float(v), "rise") => self.rise = *v,
+            (SettingValue::Float(v), "fall") => self.fall = *v,
+            _ => (),
+        }
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::UP) | (Some(output), Position::LEFT) => {
+                vec![(output, self.input)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::Slew(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::Slew(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_rise_and_fall() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(6)), 4);
+        screech.create_main_out("out");
+
+        let mut input = TestSource::new(&mut screech, &[1.0, 1.0, 1.0, 0.0, 0.0, 0.0]);
+        let mut slew = Slew::new(&mut screech);
+
+        slew.update_setting(&Setting::new(SettingValue::Float(0.5), "rise"));
+        slew.update_setting(&Setting::new(SettingValue::Float(0.25), "fall"));
+
+        screech.connect_signal(&input.output, &slew.input);
+        screech.connect_signal_to_main_out(&slew.output, "out");
+
+        screech
+            .sample(&mut [
+                &mut input as &mut dyn Source,
+                &mut EntitySource::new(&mut slew),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![0.5, 1.0, 1.0, 0.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_negative_input() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(4)), 4);
+        screech.create_main_out("out");
+
+        let mut input = TestSource::new(&mut screech, &[-1.0, -1.0, -1.0, -0.5]);
+        let mut slew = Slew::new(&mut screech);
+
+        slew.update_setting(&Setting::new(SettingValue::Float(0.5), "rise"));
+        slew.update_setting(&Setting::new(SettingValue::Float(0.5), "fall"));
+
+        screech.connect_signal(&input.output, &slew.input);
+        screech.connect_signal_to_main_out(&slew.output, "out");
+
+        screech
+            .sample(&mut [
+                &mut input as &mut dyn Source,
+                &mut EntitySource::new(&mut slew),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![-0.5, -1.0, -1.0, -0.5]
+        );
+    }
+}
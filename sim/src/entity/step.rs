@@ -1,7 +1,7 @@
-use super::{Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use super::{merge_inputs, write_values, Entity, EntityKind, EntityMutKind, Setting, SettingValue};
 use crate::grid::Position;
-use crate::Image;
-use screech::traits::{Source, Tracker};
+use crate::{Error, Image};
+use screech::traits::Tracker;
 use screech::{Input, Output, Screech};
 use std::cmp;
 
@@ -22,6 +22,8 @@ pub struct Step {
     pub level: f32,
     pub output: Output,
     input: Input,
+    /// buffer kept between calls to `sample` so sampling does not allocate
+    input_buffer: Vec<f32>,
 }
 
 impl Step {
@@ -37,6 +39,7 @@ impl Step {
             level: 1.0,
             max_charge: 0,
             charge: 0,
+            input_buffer: vec![],
         }
     }
 
@@ -46,24 +49,13 @@ impl Step {
     }
 }
 
-impl Source for Step {
-    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) {
-        let buffer_size = *tracker.get_buffer_size();
-        let mut signal_in = vec![0.0; buffer_size];
-
-        for input in tracker.get_input(&self.input).unwrap().into_iter() {
-            let buffer = tracker.get_output(&input).unwrap();
-
-            for i in 0..*tracker.get_buffer_size() {
-                signal_in[i] = if signal_in[i] >= buffer.samples[i] {
-                    signal_in[i]
-                } else {
-                    buffer.samples[i]
-                };
-            }
-        }
+impl Entity for Step {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        let signal_in = merge_inputs(tracker, &self.input, &mut self.input_buffer)?;
 
-        let signal = tracker.get_mut_output(&self.output).unwrap();
+        let signal = tracker
+            .get_mut_output(&self.output)
+            .ok_or(Error::MissingOutput(self.output))?;
 
         for (i, s) in signal.samples.iter_mut().enumerate() {
             self.state = match (signal_in[i], self.charge) {
@@ -85,14 +77,14 @@ impl Source for Step {
 
             self.max_charge = cmp::max(self.charge, self.max_charge);
         }
+
+        Ok(())
     }
 
     fn get_source_id(&self) -> &usize {
         &self.id
     }
-}
 
-impl Entity for Step {
     fn set_position(&mut self, position: Position) {
         self.grid_position = self.grid_position.move_to(position);
     }
@@ -109,39 +101,42 @@ impl Entity for Step {
         None
     }
 
-    // fn get_settings(&self) -> Vec<Setting> {
-    //     vec![Setting::new(SettingValue::Integer(self.charge), "cap")]
-    // }
-
-    //fn update_setting(&mut self, _setting: &Setting) {}
-
-    //fn find_connections(
-    //    &self,
-    //    entity: &EntityKind,
-    //    relative_position: Position,
-    //) -> Vec<(Output, Input)> {
-    //    let mut conns = vec![];
-
-    //    match (entity, relative_position) {
-    //        (EntityKind::Trigger(trigger), Position { x: 0, y: -1 })
-    //        | (EntityKind::Trigger(trigger), Position { x: -1, y: 0 }) => {
-    //            conns.push((trigger.output, self.input));
-    //        }
-    //        (EntityKind::Step(step), Position { x: 0, y: -1 })
-    //        | (EntityKind::Step(step), Position { x: -1, y: 0 }) => {
-    //            conns.push((step.output, self.input));
-    //        }
-    //        _ => (),
-    //    }
-
-    //    conns
-    //}
-
-    fn as_kind(&self) -> EntityKind {
+    fn get_display_level(&self) -> Option<f32> {
+        if self.max_charge == 0 {
+            return Some(0.0);
+        }
+
+        Some(self.charge as f32 / self.max_charge as f32)
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![Setting::new(SettingValue::Integer(self.charge), "cap")]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(settings, [SettingValue::Integer(self.charge)]);
+    }
+
+    fn update_setting(&mut self, _setting: &Setting) {}
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::UP) | (Some(output), Position::LEFT) => {
+                vec![(output, self.input)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
         EntityKind::Step(self)
     }
 
-    fn as_mut_kind(&mut self) -> EntityMutKind {
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
         EntityMutKind::Step(self)
     }
 }
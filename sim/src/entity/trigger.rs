@@ -1,15 +1,21 @@
-use super::{Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use super::{
+    write_values, Entity, EntityKind, EntityMutKind, Parameter, Setting, SettingValue, Smoothing,
+};
 use crate::grid::Position;
-use crate::Image;
-use screech::traits::{Source, Tracker};
+use crate::transport::Clock;
+use crate::{Error, Image};
+use screech::traits::Tracker;
 use screech::{Input, Output, Screech};
 
 pub struct Trigger {
     // id: usize,
     grid_position: Position,
-    bpm: f32,
-    subdivision: f32,
+    bpm: Parameter,
+    subdivision: Parameter,
     counter: f32,
+    /// follow the beat of the transport instead of the own tempo
+    sync: bool,
+    clock: Option<Clock>,
     pub output: Output,
 }
 
@@ -21,39 +27,51 @@ impl Trigger {
             // id,
             grid_position: Position::origin(),
             output: screech.init_output(&id, "output"),
-            bpm: 480.0,
-            subdivision: 0.25,
+            // tempo changes ramp so the counter does not jump
+            bpm: Parameter::new(480.0, Smoothing::Linear(0.05)),
+            subdivision: Parameter::new(0.25, Smoothing::None),
             counter: 0.0,
+            sync: false,
+            clock: None,
         }
     }
 }
 
-impl Source for Trigger {
-    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) {
-        let signal = tracker.get_mut_output(&self.output).unwrap();
-        let increase_per_sample = 1.0 / sample_rate as f32 * (self.bpm / 60.0);
+impl Entity for Trigger {
+    fn sample(&mut self, tracker: &mut dyn Tracker, sample_rate: usize) -> Result<(), Error> {
+        let signal = tracker
+            .get_mut_output(&self.output)
+            .ok_or(Error::MissingOutput(self.output))?;
 
-        for s in signal.samples.iter_mut() {
-            self.counter += increase_per_sample;
+        let clock = self.clock.filter(|_| self.sync);
 
-            if self.counter >= 1.0 {
-                self.counter = 0.0;
-            }
+        for (i, s) in signal.samples.iter_mut().enumerate() {
+            let bpm = self.bpm.next(i, sample_rate);
+            let subdivision = self.subdivision.next(i, sample_rate);
 
-            *s = if self.counter < self.subdivision {
-                1.0
+            if let Some(clock) = clock {
+                self.counter = clock.beat_at(i, sample_rate).rem_euclid(1.0) as f32;
             } else {
-                0.0
-            };
+                self.counter += 1.0 / sample_rate as f32 * (bpm / 60.0);
+
+                if self.counter >= 1.0 {
+                    self.counter = 0.0;
+                }
+            }
+
+            *s = if self.counter < subdivision { 1.0 } else { 0.0 };
         }
+
+        self.bpm.end_buffer();
+        self.subdivision.end_buffer();
+
+        Ok(())
     }
 
     fn get_source_id(&self) -> &usize {
         self.output.get_source_id()
     }
-}
 
-impl Entity for Trigger {
     fn set_position(&mut self, position: Position) {
         self.grid_position = self.grid_position.move_to(position);
     }
@@ -70,34 +88,130 @@ impl Entity for Trigger {
         None
     }
 
-    // fn get_settings(&self) -> Vec<Setting> {
-    //     vec![
-    //         Setting::new(SettingValue::Float(self.bpm), "bpm"),
-    //         Setting::new(SettingValue::Float(self.subdivision), "div"),
-    //     ]
-    // }
-
-    // fn update_setting(&mut self, setting: &Setting) {
-    //     match (&setting.value, setting.description.as_str()) {
-    //         (SettingValue::Float(v), "bpm") => self.bpm = *v,
-    //         (SettingValue::Float(v), "div") => self.subdivision = *v,
-    //         _ => (),
-    //     }
-    // }
-
-    // fn find_connections(
-    //     &self,
-    //     _entity: &EntityKind,
-    //     _relative_position: Position,
-    // ) -> Vec<(Output, Input)> {
-    //     vec![]
-    // }
-
-    fn as_kind(&self) -> EntityKind {
+    fn get_display_level(&self) -> Option<f32> {
+        Some(self.counter)
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Float(self.bpm.target()), "bpm").with_range(20.0, 300.0),
+            Setting::new(SettingValue::Float(self.subdivision.target()), "div")
+                .with_range(0.0, 1.0),
+            Setting::new(SettingValue::Boolean(self.sync), "sync"),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Float(self.bpm.target()),
+                SettingValue::Float(self.subdivision.target()),
+                SettingValue::Boolean(self.sync),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Float(v), "bpm") => self.bpm.set(*v),
+            (SettingValue::Float(v), "div") => self.subdivision.set(*v),
+            (SettingValue::Boolean(v), "sync") => self.sync = *v,
+            _ => (),
+        }
+    }
+
+    fn schedule_setting(&mut self, offset: usize, setting: &Setting) -> Result<(), Error> {
+        let scheduled = match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Float(v), "bpm") => self.bpm.schedule(offset, *v),
+            (SettingValue::Float(v), "div") => self.subdivision.schedule(offset, *v),
+            _ => {
+                self.update_setting(setting);
+                true
+            }
+        };
+
+        if scheduled {
+            Ok(())
+        } else {
+            Err(Error::ScheduleFull)
+        }
+    }
+
+    fn set_clock(&mut self, clock: &Clock) {
+        self.clock = Some(*clock);
+    }
+
+    fn find_connections(
+        &self,
+        _entity: &EntityKind,
+        _relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        vec![]
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
         EntityKind::Trigger(self)
     }
 
-    fn as_mut_kind(&mut self) -> EntityMutKind {
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
         EntityMutKind::Trigger(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::EntitySource;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_scheduled_setting() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<2>::new(8)), 8);
+        screech.create_main_out("out");
+
+        let mut trigger = Trigger::new(&mut screech);
+
+        trigger.update_setting(&Setting::new(SettingValue::Float(60.0), "bpm"));
+        trigger.update_setting(&Setting::new(SettingValue::Float(0.0), "div"));
+        trigger
+            .schedule_setting(5, &Setting::new(SettingValue::Float(1.0), "div"))
+            .unwrap();
+
+        screech.connect_signal_to_main_out(&trigger.output, "out");
+        screech
+            .sample(&mut [&mut EntitySource::new(&mut trigger)])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn test_sync_to_clock() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<2>::new(8)), 8);
+        screech.create_main_out("out");
+
+        let mut trigger = Trigger::new(&mut screech);
+
+        trigger.update_setting(&Setting::new(SettingValue::Boolean(true), "sync"));
+        trigger.update_setting(&Setting::new(SettingValue::Float(0.5), "div"));
+        // two beats per buffer, starting half way through a beat
+        trigger.set_clock(&Clock {
+            bpm: 120.0,
+            beat: 2.5,
+        });
+
+        screech.connect_signal_to_main_out(&trigger.output, "out");
+        screech
+            .sample(&mut [&mut EntitySource::new(&mut trigger)])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("out").unwrap().samples,
+            vec![0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0]
+        );
+    }
+}
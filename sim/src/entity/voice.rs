@@ -0,0 +1,218 @@
+use super::voice_allocator::MAX_VOICES;
+use super::{merge_inputs, write_values, Entity, EntityKind, EntityMutKind, Setting, SettingValue};
+use crate::grid::Position;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+const PITCH_SIGNALS: [&str; MAX_VOICES] = [
+    "pitch_0", "pitch_1", "pitch_2", "pitch_3", "pitch_4", "pitch_5", "pitch_6", "pitch_7",
+];
+
+const GATE_SIGNALS: [&str; MAX_VOICES] = [
+    "gate_0", "gate_1", "gate_2", "gate_3", "gate_4", "gate_5", "gate_6", "gate_7",
+];
+
+/// Outputs the pitch or the gate of one voice of the entity to its left or above,
+/// a [`super::VoiceAllocator`], a [`super::MidiIn`] or another voice passing all voices on,
+/// so a row or column of voices reaches every voice of an allocator
+pub struct Voice {
+    id: usize,
+    grid_position: Position,
+    /// voice from 1 to `MAX_VOICES`
+    voice: usize,
+    /// output the gate of the voice instead of its pitch
+    gate: bool,
+    pitch_inputs: [Input; MAX_VOICES],
+    gate_inputs: [Input; MAX_VOICES],
+    /// all voices received, passed on to the voices to the right and below
+    pub pitch_outputs: [Output; MAX_VOICES],
+    pub gate_outputs: [Output; MAX_VOICES],
+    pub output: Output,
+    /// buffer kept between calls to `sample` so sampling does not allocate
+    buffer: Vec<f32>,
+}
+
+impl Voice {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        Voice {
+            id,
+            pitch_inputs: PITCH_SIGNALS.map(|name| screech.init_input(&id, name)),
+            gate_inputs: GATE_SIGNALS.map(|name| screech.init_input(&id, name)),
+            pitch_outputs: PITCH_SIGNALS.map(|name| screech.init_output(&id, name)),
+            gate_outputs: GATE_SIGNALS.map(|name| screech.init_output(&id, name)),
+            output: screech.init_output(&id, "output"),
+            grid_position: Position::origin(),
+            voice: 1,
+            gate: true,
+            buffer: vec![],
+        }
+    }
+}
+
+impl Entity for Voice {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        let signals = self
+            .pitch_inputs
+            .iter()
+            .zip(self.pitch_outputs.iter())
+            .chain(self.gate_inputs.iter().zip(self.gate_outputs.iter()));
+        let selected = if self.gate {
+            self.gate_inputs[self.voice - 1]
+        } else {
+            self.pitch_inputs[self.voice - 1]
+        };
+
+        for (input, output) in signals {
+            let samples = merge_inputs(tracker, input, &mut self.buffer)?;
+
+            tracker
+                .get_mut_output(output)
+                .ok_or(Error::MissingOutput(*output))?
+                .samples
+                .copy_from_slice(samples);
+
+            if *input == selected {
+                tracker
+                    .get_mut_output(&self.output)
+                    .ok_or(Error::MissingOutput(self.output))?
+                    .samples
+                    .copy_from_slice(samples);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Integer(self.voice), "voice")
+                .with_range(1.0, MAX_VOICES as f32),
+            Setting::new(SettingValue::Boolean(self.gate), "gate"),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Integer(self.voice),
+                SettingValue::Boolean(self.gate),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Integer(v), "voice") => self.voice = (*v).clamp(1, MAX_VOICES),
+            (SettingValue::Boolean(v), "gate") => self.gate = *v,
+            _ => (),
+        }
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_voices(), relative_position) {
+            (Some((pitches, gates)), Position::LEFT | Position::UP) => pitches
+                .iter()
+                .zip(self.pitch_inputs.iter())
+                .chain(gates.iter().zip(self.gate_inputs.iter()))
+                .map(|(output, input)| (*output, *input))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::Voice(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::Voice(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, VoiceAllocator};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_voice_outputs() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(2)), 4);
+        screech.create_main_out("pitch");
+        screech.create_main_out("gate");
+
+        let mut voices = VoiceAllocator::new(&mut screech);
+        let mut pitch = Voice::new(&mut screech);
+        let mut gate = Voice::new(&mut screech);
+
+        pitch.update_setting(&Setting::new(SettingValue::Integer(2), "voice"));
+        pitch.update_setting(&Setting::new(SettingValue::Boolean(false), "gate"));
+        gate.update_setting(&Setting::new(SettingValue::Integer(3), "voice"));
+
+        // the second voice reads the voices passed on by the first
+        let connections = pitch
+            .find_connections(&voices.as_kind(), Position::LEFT)
+            .into_iter()
+            .chain(gate.find_connections(&pitch.as_kind(), Position::UP));
+        for (output, input) in connections {
+            screech.connect_signal(&output, &input);
+        }
+        screech.connect_signal_to_main_out(&pitch.output, "pitch");
+        screech.connect_signal_to_main_out(&gate.output, "gate");
+
+        voices.note_on(0.1);
+        voices.note_on(0.2);
+
+        screech
+            .sample(&mut [
+                &mut EntitySource::new(&mut voices) as &mut dyn Source,
+                &mut EntitySource::new(&mut pitch),
+                &mut EntitySource::new(&mut gate),
+            ])
+            .unwrap();
+
+        assert_eq!(screech.get_main_out("pitch").unwrap().samples, vec![0.2; 2]);
+        assert_eq!(screech.get_main_out("gate").unwrap().samples, vec![0.0; 2]);
+
+        voices.note_on(0.3);
+
+        screech
+            .sample(&mut [
+                &mut EntitySource::new(&mut voices) as &mut dyn Source,
+                &mut EntitySource::new(&mut pitch),
+                &mut EntitySource::new(&mut gate),
+            ])
+            .unwrap();
+
+        assert_eq!(screech.get_main_out("gate").unwrap().samples, vec![1.0; 2]);
+    }
+}
@@ -0,0 +1,430 @@
+use super::{
+    merge_inputs, note_to_pitch, write_values, zeroed, Entity, EntityKind, EntityMutKind, Setting,
+    SettingValue,
+};
+use crate::grid::Position;
+use crate::midi::MidiMessage;
+use crate::{Error, Image};
+use screech::traits::Tracker;
+use screech::{Input, Output, Screech};
+
+pub const MAX_VOICES: usize = 8;
+
+const PITCH_OUTPUTS: [&str; MAX_VOICES] = [
+    "pitch_0", "pitch_1", "pitch_2", "pitch_3", "pitch_4", "pitch_5", "pitch_6", "pitch_7",
+];
+
+const GATE_OUTPUTS: [&str; MAX_VOICES] = [
+    "gate_0", "gate_1", "gate_2", "gate_3", "gate_4", "gate_5", "gate_6", "gate_7",
+];
+
+/// Strategy for picking a voice when all voices are in use
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stealing {
+    RoundRobin,
+    LastNote,
+    LowestNote,
+}
+
+impl Stealing {
+    fn from_index(index: usize) -> Self {
+        match index {
+            1 => Stealing::LastNote,
+            2 => Stealing::LowestNote,
+            _ => Stealing::RoundRobin,
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Stealing::RoundRobin => 0,
+            Stealing::LastNote => 1,
+            Stealing::LowestNote => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Voice {
+    pitch: f32,
+    gate: bool,
+    /// drop the gate for a single sample so a stolen voice retriggers
+    retrigger: bool,
+    started: usize,
+}
+
+/// Keeps track of which notes are playing on which voice
+#[derive(Debug)]
+pub struct Allocator {
+    voices: [Voice; MAX_VOICES],
+    voice_count: usize,
+    stealing: Stealing,
+    next: usize,
+    counter: usize,
+}
+
+impl Allocator {
+    pub fn new(voice_count: usize, stealing: Stealing) -> Self {
+        Allocator {
+            voices: [Voice::default(); MAX_VOICES],
+            voice_count: voice_count.clamp(1, MAX_VOICES),
+            stealing,
+            next: 0,
+            counter: 0,
+        }
+    }
+
+    /// Start a note, returning the index of the voice playing it
+    pub fn note_on(&mut self, pitch: f32) -> usize {
+        let count = self.voice_count;
+        let free = (0..count)
+            .map(|i| (self.next + i) % count)
+            .find(|&i| !self.voices[i].gate);
+
+        let index = free.unwrap_or_else(|| self.steal());
+        let voice = &mut self.voices[index];
+
+        voice.retrigger = voice.gate;
+        voice.gate = true;
+        voice.pitch = pitch;
+        voice.started = self.counter;
+
+        self.counter += 1;
+        self.next = (index + 1) % count;
+
+        index
+    }
+
+    /// Release all voices playing the given pitch
+    pub fn note_off(&mut self, pitch: f32) {
+        for voice in self.voices.iter_mut().filter(|v| v.pitch == pitch) {
+            voice.gate = false;
+        }
+    }
+
+    pub fn set_voice_count(&mut self, voice_count: usize) {
+        self.voice_count = voice_count.clamp(1, MAX_VOICES);
+        self.next %= self.voice_count;
+
+        for voice in self.voices[self.voice_count..].iter_mut() {
+            voice.gate = false;
+        }
+    }
+
+    /// Current pitch and gate for a voice, clearing a pending retrigger
+    pub fn read_voice(&mut self, index: usize) -> (f32, bool) {
+        let voice = &mut self.voices[index];
+        let gate = voice.gate && !voice.retrigger;
+        voice.retrigger = false;
+
+        (voice.pitch, gate)
+    }
+
+    fn steal(&self) -> usize {
+        let voices = self.voices[..self.voice_count].iter().enumerate();
+
+        match self.stealing {
+            Stealing::RoundRobin => Some(self.next),
+            Stealing::LastNote => voices.max_by_key(|(_, v)| v.started).map(|(i, _)| i),
+            Stealing::LowestNote => voices
+                .min_by(|(_, a), (_, b)| a.pitch.total_cmp(&b.pitch))
+                .map(|(i, _)| i),
+        }
+        .unwrap_or(0)
+    }
+}
+
+/// Distributes notes over multiple pitch/gate output pairs,
+/// notes come from the pitch/gate inputs or from note events sent to the entity
+pub struct VoiceAllocator {
+    id: usize,
+    grid_position: Position,
+    allocator: Allocator,
+    events: Vec<(f32, bool)>,
+    gate: bool,
+    pitch: f32,
+    pub pitch_outputs: Vec<Output>,
+    pub gate_outputs: Vec<Output>,
+    pitch_input: Input,
+    gate_input: Input,
+    /// buffers kept between calls to `sample` so sampling does not allocate
+    pitch_buffer: Vec<f32>,
+    gate_buffer: Vec<f32>,
+    voice_pitches: [Vec<f32>; MAX_VOICES],
+    voice_gates: [Vec<f32>; MAX_VOICES],
+}
+
+impl VoiceAllocator {
+    pub fn new(screech: &mut Screech) -> Self {
+        let id = screech.create_source_id();
+
+        VoiceAllocator {
+            id,
+            pitch_outputs: PITCH_OUTPUTS
+                .iter()
+                .map(|name| screech.init_output(&id, name))
+                .collect(),
+            gate_outputs: GATE_OUTPUTS
+                .iter()
+                .map(|name| screech.init_output(&id, name))
+                .collect(),
+            pitch_input: screech.init_input(&id, "pitch"),
+            gate_input: screech.init_input(&id, "gate"),
+            grid_position: Position::origin(),
+            allocator: Allocator::new(4, Stealing::RoundRobin),
+            events: vec![],
+            gate: false,
+            pitch: 0.0,
+            pitch_buffer: vec![],
+            gate_buffer: vec![],
+            voice_pitches: Default::default(),
+            voice_gates: Default::default(),
+        }
+    }
+
+    /// Queue a note on event, applied at the start of the next buffer
+    pub fn note_on(&mut self, pitch: f32) {
+        self.events.push((pitch, true));
+    }
+
+    /// Queue a note off event, applied at the start of the next buffer
+    pub fn note_off(&mut self, pitch: f32) {
+        self.events.push((pitch, false));
+    }
+
+    pub fn get_voice_count(&self) -> usize {
+        self.allocator.voice_count
+    }
+
+    /// The output of another voice matching an output of the first voice,
+    /// other outputs are returned unchanged
+    pub fn voice_output(&self, output: Output, voice: usize) -> Output {
+        if output == self.pitch_outputs[0] {
+            self.pitch_outputs[voice]
+        } else if output == self.gate_outputs[0] {
+            self.gate_outputs[voice]
+        } else {
+            output
+        }
+    }
+}
+
+impl Entity for VoiceAllocator {
+    fn sample(&mut self, tracker: &mut dyn Tracker, _sample_rate: usize) -> Result<(), Error> {
+        let pitch_in = merge_inputs(tracker, &self.pitch_input, &mut self.pitch_buffer)?;
+        let gate_in = merge_inputs(tracker, &self.gate_input, &mut self.gate_buffer)?;
+        let buffer_size = pitch_in.len();
+
+        for buffer in self
+            .voice_pitches
+            .iter_mut()
+            .chain(self.voice_gates.iter_mut())
+        {
+            zeroed(buffer, buffer_size);
+        }
+
+        for (pitch, on) in self.events.drain(..) {
+            if on {
+                self.allocator.note_on(pitch);
+            } else {
+                self.allocator.note_off(pitch);
+            }
+        }
+
+        for i in 0..buffer_size {
+            let gate = gate_in[i] >= 0.5;
+
+            if gate && !self.gate {
+                self.pitch = pitch_in[i];
+                self.allocator.note_on(self.pitch);
+            } else if !gate && self.gate {
+                self.allocator.note_off(self.pitch);
+            }
+
+            self.gate = gate;
+
+            for voice in 0..MAX_VOICES {
+                let (pitch, gate) = self.allocator.read_voice(voice);
+                self.voice_pitches[voice][i] = pitch;
+                self.voice_gates[voice][i] = if gate { 1.0 } else { 0.0 };
+            }
+        }
+
+        for (output, samples) in self.pitch_outputs.iter().zip(self.voice_pitches.iter()) {
+            tracker
+                .get_mut_output(output)
+                .ok_or(Error::MissingOutput(*output))?
+                .samples
+                .copy_from_slice(samples);
+        }
+
+        for (output, samples) in self.gate_outputs.iter().zip(self.voice_gates.iter()) {
+            tracker
+                .get_mut_output(output)
+                .ok_or(Error::MissingOutput(*output))?
+                .samples
+                .copy_from_slice(samples);
+        }
+
+        Ok(())
+    }
+
+    fn get_source_id(&self) -> &usize {
+        &self.id
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.grid_position = self.grid_position.move_to(position);
+    }
+
+    fn get_position(&self) -> Position {
+        self.grid_position
+    }
+
+    fn get_grid_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_detail_display(&self) -> Option<Image> {
+        None
+    }
+
+    fn get_settings(&self) -> Vec<Setting> {
+        vec![
+            Setting::new(SettingValue::Integer(self.allocator.voice_count), "voices")
+                .with_range(1.0, 8.0),
+            Setting::new(
+                SettingValue::Integer(self.allocator.stealing.index()),
+                "steal",
+            )
+            .with_range(0.0, 2.0),
+        ]
+    }
+
+    fn write_settings(&self, settings: &mut [Setting]) {
+        write_values(
+            settings,
+            [
+                SettingValue::Integer(self.allocator.voice_count),
+                SettingValue::Integer(self.allocator.stealing.index()),
+            ],
+        );
+    }
+
+    fn update_setting(&mut self, setting: &Setting) {
+        match (&setting.value, setting.description.as_ref()) {
+            (SettingValue::Integer(v), "voices") => self.allocator.set_voice_count(*v),
+            (SettingValue::Integer(v), "steal") => {
+                self.allocator.stealing = Stealing::from_index(*v)
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_midi(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn { note, .. } => self.note_on(note_to_pitch(note)),
+            MidiMessage::NoteOff { note, .. } => self.note_off(note_to_pitch(note)),
+            _ => (),
+        }
+    }
+
+    fn find_connections(
+        &self,
+        entity: &EntityKind,
+        relative_position: Position,
+    ) -> Vec<(Output, Input)> {
+        match (entity.get_output(), relative_position) {
+            (Some(output), Position::LEFT) => vec![(output, self.pitch_input)],
+            (Some(output), Position::UP) => vec![(output, self.gate_input)],
+            _ => vec![],
+        }
+    }
+
+    fn as_kind(&self) -> EntityKind<'_> {
+        EntityKind::VoiceAllocator(self)
+    }
+
+    fn as_mut_kind(&mut self) -> EntityMutKind<'_> {
+        EntityMutKind::VoiceAllocator(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{EntitySource, TestSource};
+    use screech::traits::Source;
+    use screech::BasicTracker;
+
+    #[test]
+    fn test_round_robin() {
+        let mut allocator = Allocator::new(2, Stealing::RoundRobin);
+
+        assert_eq!(allocator.note_on(1.0), 0);
+        assert_eq!(allocator.note_on(2.0), 1);
+        assert_eq!(allocator.note_on(3.0), 0);
+
+        allocator.note_off(2.0);
+        assert_eq!(allocator.note_on(4.0), 1);
+        assert_eq!(allocator.read_voice(0), (3.0, false));
+        assert_eq!(allocator.read_voice(0), (3.0, true));
+    }
+
+    #[test]
+    fn test_last_note() {
+        let mut allocator = Allocator::new(3, Stealing::LastNote);
+
+        allocator.note_on(1.0);
+        allocator.note_on(2.0);
+        allocator.note_on(3.0);
+
+        assert_eq!(allocator.note_on(4.0), 2);
+        assert_eq!(allocator.note_on(5.0), 2);
+    }
+
+    #[test]
+    fn test_lowest_note() {
+        let mut allocator = Allocator::new(3, Stealing::LowestNote);
+
+        allocator.note_on(2.0);
+        allocator.note_on(1.0);
+        allocator.note_on(3.0);
+
+        assert_eq!(allocator.note_on(4.0), 1);
+        assert_eq!(allocator.note_on(5.0), 0);
+    }
+
+    #[test]
+    fn test_gate_input() {
+        let mut screech = Screech::with_tracker(Box::new(BasicTracker::<4>::new(4)), 4);
+        screech.create_main_out("first");
+        screech.create_main_out("second");
+
+        let mut pitch = TestSource::new(&mut screech, &[0.1, 0.1, 0.2, 0.2]);
+        let mut gate = TestSource::new(&mut screech, &[1.0, 0.0, 1.0, 1.0]);
+        let mut voices = VoiceAllocator::new(&mut screech);
+
+        screech.connect_signal(&pitch.output, &voices.pitch_input);
+        screech.connect_signal(&gate.output, &voices.gate_input);
+        screech.connect_signal_to_main_out(&voices.gate_outputs[0], "first");
+        screech.connect_signal_to_main_out(&voices.pitch_outputs[1], "second");
+
+        screech
+            .sample(&mut [
+                &mut pitch as &mut dyn Source,
+                &mut gate,
+                &mut EntitySource::new(&mut voices),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            screech.get_main_out("first").unwrap().samples,
+            vec![1.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            screech.get_main_out("second").unwrap().samples,
+            vec![0.0, 0.0, 0.2, 0.2]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use crate::grid::Position;
+use screech::{Input, Output};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// input has not been initialized in the tracker
+    MissingInput(Input),
+    /// output has not been initialized in the tracker
+    MissingOutput(Output),
+    /// sampling failed inside of screech
+    Sampling(String),
+    /// an entity failed while sampling
+    Entity(Position, Box<Error>),
+    /// a setting could not be updated with the given value
+    InvalidSetting(String, String),
+    /// a sample could not be decoded
+    Wav(String),
+    /// the grid holds the maximum amount of entities
+    CapacityReached(usize),
+    /// the audio side has not caught up with the commands sent to it
+    QueueFull,
+    /// too many setting changes are scheduled, the change was dropped
+    ScheduleFull,
+    /// a message sent to the audio side could not be decoded
+    InvalidMessage(String),
+    /// an error of the audio worklet, only its text is sent to the main thread
+    Worklet(String),
+    /// the audio backend failed to open or play
+    Backend(String),
+    /// an OSC packet could not be decoded, sent or mapped onto the grid
+    Osc(String),
+    /// tempo sync could not reach the network
+    Sync(String),
+    /// a font file could not be decoded
+    Font(String),
+    /// a patch could not be parsed from its text
+    Patch(String),
+    /// a line typed into the prompt could not be turned into commands
+    Prompt(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::MissingInput(input) => write!(f, "missing input {}", input.get_signal_id()),
+            Error::MissingOutput(output) => {
+                write!(f, "missing output {}", output.get_signal_id())
+            }
+            Error::Sampling(e) => write!(f, "sampling failed: {}", e),
+            Error::Entity(position, e) => write!(f, "{} at {},{}", e, position.x, position.y),
+            Error::InvalidSetting(setting, value) => {
+                write!(f, "invalid value {} for {}", value, setting)
+            }
+            Error::Wav(e) => write!(f, "invalid wav: {}", e),
+            Error::CapacityReached(capacity) => {
+                write!(f, "grid is full, limit of {} entities reached", capacity)
+            }
+            Error::QueueFull => write!(f, "command queue is full"),
+            Error::ScheduleFull => write!(f, "too many scheduled setting changes"),
+            Error::InvalidMessage(e) => write!(f, "invalid message: {}", e),
+            Error::Worklet(e) => write!(f, "{}", e),
+            Error::Backend(e) => write!(f, "audio backend failed: {}", e),
+            Error::Osc(e) => write!(f, "osc: {}", e),
+            Error::Sync(e) => write!(f, "tempo sync failed: {}", e),
+            Error::Font(e) => write!(f, "invalid font: {}", e),
+            Error::Patch(e) => write!(f, "invalid patch: {}", e),
+            Error::Prompt(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
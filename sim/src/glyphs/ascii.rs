@@ -1,4 +1,4 @@
-use super::{CHARACTER_WIDTH, CHARACTER_HEIGHT};
+use super::CHARACTER_HEIGHT;
 
 pub const UNKNOWN: [u8; CHARACTER_HEIGHT] = [
     0b11111111, 0b10000001, 0b10000001, 0b10000001, 0b10000001, 0b10000001, 0b10000001, 0b11111111,
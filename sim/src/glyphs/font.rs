@@ -0,0 +1,436 @@
+use super::ascii::{ASCII, UNKNOWN};
+use super::{CHARACTER_HEIGHT, CHARACTER_WIDTH};
+use crate::{Bitmap, Error};
+use std::collections::HashMap;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+/// Monospaced bitmap font, glyph rows are stored as bytes padded to whole bytes
+#[derive(Debug, Clone)]
+pub struct Font {
+    width: i32,
+    height: i32,
+    glyphs: HashMap<char, Vec<u8>>,
+    /// drawn for characters the font has no glyph for
+    unknown: Vec<u8>,
+}
+
+impl Font {
+    /// The built-in 8x8 font covering printable ASCII
+    pub fn ascii() -> Self {
+        let glyphs = (32..=126u8)
+            .map(|c| {
+                let start = (c as usize - 32) * CHARACTER_HEIGHT;
+                (c as char, ASCII[start..start + CHARACTER_HEIGHT].to_vec())
+            })
+            .collect();
+
+        Font {
+            width: CHARACTER_WIDTH as i32,
+            height: CHARACTER_HEIGHT as i32,
+            glyphs,
+            unknown: UNKNOWN.to_vec(),
+        }
+    }
+
+    /// Load a PC Screen Font, version 1 or 2. Fonts without a unicode table
+    /// map glyph indices onto code points.
+    pub fn from_psf(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.starts_with(&PSF1_MAGIC) {
+            let mode = read(bytes, 2, 1)?[0];
+            let height = read(bytes, 3, 1)?[0] as usize;
+            let count = if mode & 0x01 != 0 { 512 } else { 256 };
+            let table = (mode & 0x06 != 0).then_some(4 + count * height);
+            check_size(8, height as i32)?;
+
+            let mut font = Font::new(8, height as i32);
+            let glyphs = (0..count)
+                .map(|i| read(bytes, 4 + i * height, height))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let codepoints = match table {
+                Some(offset) => psf1_table(bytes.get(offset..).unwrap_or_default(), count),
+                None => identity_table(count),
+            };
+            font.insert_all(&glyphs, codepoints);
+            Ok(font)
+        } else if bytes.starts_with(&PSF2_MAGIC) {
+            let header = read_u32(bytes, 8)? as usize;
+            let flags = read_u32(bytes, 12)?;
+            let count = read_u32(bytes, 16)? as usize;
+            let size = read_u32(bytes, 20)? as usize;
+            let height = read_u32(bytes, 24)? as i32;
+            let width = read_u32(bytes, 28)? as i32;
+
+            check_size(width, height)?;
+
+            let mut font = Font::new(width, height);
+            if size != font.stride() * height as usize {
+                return Err(Error::Font(format!("glyph size {} does not match", size)));
+            }
+
+            let glyphs = (0..count)
+                .map(|i| read(bytes, glyph_offset(header, i, size)?, size))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let codepoints = if flags & 0x01 != 0 {
+                let offset = glyph_offset(header, count, size)?;
+                psf2_table(bytes.get(offset..).unwrap_or_default(), count)
+            } else {
+                identity_table(count)
+            };
+            font.insert_all(&glyphs, codepoints);
+            Ok(font)
+        } else {
+            Err(Error::Font("not a psf font".into()))
+        }
+    }
+
+    /// Load a font in the Glyph Bitmap Distribution Format, glyphs are placed
+    /// in the bounding box of the font
+    pub fn from_bdf(bytes: &[u8]) -> Result<Self, Error> {
+        let text = String::from_utf8_lossy(bytes);
+        let mut lines = text.lines().map(str::trim);
+        let mut font: Option<Font> = None;
+        let mut origin = (0, 0);
+
+        while let Some(line) = lines.next() {
+            let mut words = line.split_whitespace();
+
+            match words.next() {
+                Some("FONTBOUNDINGBOX") => {
+                    let [w, h, x, y] = numbers(words)?;
+                    check_size(w, h)?;
+                    font = Some(Font::new(w, h));
+                    origin = (x, y);
+                }
+                Some("STARTCHAR") => {
+                    let font = font
+                        .as_mut()
+                        .ok_or_else(|| Error::Font("missing FONTBOUNDINGBOX".into()))?;
+                    let mut encoding = None;
+                    let mut bounds = [0; 4];
+
+                    for line in lines.by_ref() {
+                        let mut words = line.split_whitespace();
+
+                        match words.next() {
+                            Some("ENCODING") => {
+                                let [code] = numbers(words)?;
+                                encoding = char::from_u32(code as u32);
+                            }
+                            Some("BBX") => bounds = numbers(words)?,
+                            Some("BITMAP") => break,
+                            _ => (),
+                        }
+                    }
+
+                    let [w, h, x, y] = bounds;
+                    if !(0..=256).contains(&w) || !(0..=256).contains(&h) {
+                        return Err(Error::Font(format!("unsupported glyph size {}x{}", w, h)));
+                    }
+
+                    let mut glyph = vec![0; font.stride() * font.height as usize];
+                    // rows counted from the top of the bounding box of the font
+                    let top = font
+                        .height
+                        .checked_add(origin.1)
+                        .zip(h.checked_add(y))
+                        .and_then(|(top, bottom)| top.checked_sub(bottom));
+                    let left = x.checked_sub(origin.0);
+                    let (Some(top), Some(left)) = (top, left) else {
+                        return Err(Error::Font(format!(
+                            "glyph offset {} {} out of range",
+                            x, y
+                        )));
+                    };
+
+                    for row in 0..h {
+                        let hex = lines.next().unwrap_or_default();
+                        let bytes = hex
+                            .as_bytes()
+                            .chunks(2)
+                            .map(|pair| {
+                                let pair = std::str::from_utf8(pair).ok()?;
+                                u8::from_str_radix(pair, 16).ok()
+                            })
+                            .collect::<Option<Vec<_>>>()
+                            .ok_or_else(|| Error::Font(format!("invalid bitmap row {}", hex)))?;
+
+                        for column in 0..w {
+                            let byte = bytes.get(column as usize / 8).copied().unwrap_or(0);
+                            if byte >> (7 - column % 8) & 1 == 1 {
+                                font.set_pixel(
+                                    &mut glyph,
+                                    left.saturating_add(column),
+                                    top.saturating_add(row),
+                                );
+                            }
+                        }
+                    }
+
+                    if let Some(c) = encoding {
+                        font.glyphs.insert(c, glyph);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        font.ok_or_else(|| Error::Font("missing FONTBOUNDINGBOX".into()))
+    }
+
+    fn new(width: i32, height: i32) -> Self {
+        let mut font = Font {
+            width,
+            height,
+            glyphs: HashMap::new(),
+            unknown: vec![],
+        };
+
+        // outline of the glyph box
+        let mut unknown = vec![0; font.stride() * height as usize];
+        for x in 0..width {
+            font.set_pixel(&mut unknown, x, 0);
+            font.set_pixel(&mut unknown, x, height - 1);
+        }
+        for y in 0..height {
+            font.set_pixel(&mut unknown, 0, y);
+            font.set_pixel(&mut unknown, width - 1, y);
+        }
+        font.unknown = unknown;
+        font
+    }
+
+    pub fn get_size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        self.glyphs.contains_key(&c)
+    }
+
+    pub fn get_bitmap(&self, c: char) -> Bitmap {
+        let data = self.glyphs.get(&c).unwrap_or(&self.unknown);
+        Bitmap::new(self.width, self.height, data)
+    }
+
+    /// Bytes in a row of a glyph
+    fn stride(&self) -> usize {
+        (self.width.max(0) as usize).div_ceil(8)
+    }
+
+    fn set_pixel(&self, glyph: &mut [u8], x: i32, y: i32) {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            glyph[y as usize * self.stride() + x as usize / 8] |= 0b10000000 >> (x % 8);
+        }
+    }
+
+    fn insert_all(&mut self, glyphs: &[&[u8]], codepoints: Vec<Vec<char>>) {
+        for (glyph, codepoints) in glyphs.iter().zip(codepoints) {
+            for c in codepoints {
+                self.glyphs.insert(c, glyph.to_vec());
+            }
+        }
+    }
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Self::ascii()
+    }
+}
+
+/// Glyphs are limited to 256 pixels in both directions
+fn check_size(width: i32, height: i32) -> Result<(), Error> {
+    if (1..=256).contains(&width) && (1..=256).contains(&height) {
+        Ok(())
+    } else {
+        Err(Error::Font(format!(
+            "unsupported glyph size {}x{}",
+            width, height
+        )))
+    }
+}
+
+/// Offset of a glyph behind the header, failing when it does not fit in memory
+fn glyph_offset(header: usize, index: usize, size: usize) -> Result<usize, Error> {
+    index
+        .checked_mul(size)
+        .and_then(|offset| offset.checked_add(header))
+        .ok_or_else(|| Error::Font("glyph offset out of range".into()))
+}
+
+fn read(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], Error> {
+    bytes
+        .get(offset..offset.saturating_add(length))
+        .ok_or_else(|| Error::Font("unexpected end of font".into()))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    let bytes = read(bytes, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn numbers<'a, const N: usize>(
+    mut words: impl Iterator<Item = &'a str>,
+) -> Result<[i32; N], Error> {
+    let mut numbers = [0; N];
+
+    for number in numbers.iter_mut() {
+        *number = words
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| Error::Font("expected a number".into()))?;
+    }
+
+    Ok(numbers)
+}
+
+fn identity_table(count: usize) -> Vec<Vec<char>> {
+    (0..count as u32)
+        .map(|i| char::from_u32(i).into_iter().collect())
+        .collect()
+}
+
+/// Code points of each glyph, 0xffff ends the entry of a glyph
+/// and 0xfffe starts sequences of code points, which are skipped
+fn psf1_table(table: &[u8], count: usize) -> Vec<Vec<char>> {
+    let mut codepoints = vec![vec![]; count];
+    let mut glyph = 0;
+    let mut sequence = false;
+
+    for pair in table.chunks_exact(2) {
+        match u16::from_le_bytes([pair[0], pair[1]]) {
+            0xffff => {
+                glyph += 1;
+                sequence = false;
+            }
+            0xfffe => sequence = true,
+            code if !sequence && glyph < count => {
+                codepoints[glyph].extend(char::from_u32(code as u32));
+            }
+            _ => (),
+        }
+    }
+
+    codepoints
+}
+
+/// Code points of each glyph as UTF-8, 0xff ends the entry of a glyph
+/// and 0xfe starts sequences of code points, which are skipped
+fn psf2_table(table: &[u8], count: usize) -> Vec<Vec<char>> {
+    table
+        .split(|&byte| byte == 0xff)
+        .take(count)
+        .map(|entry| {
+            let single = entry.split(|&byte| byte == 0xfe).next().unwrap_or_default();
+            String::from_utf8_lossy(single)
+                .chars()
+                .filter(|&c| c != char::REPLACEMENT_CHARACTER)
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii() {
+        let font = Font::ascii();
+
+        assert_eq!(font.get_size(), (8, 8));
+        assert_eq!(font.get_bitmap('!').data, ASCII[8..16].to_vec());
+        assert_eq!(font.get_bitmap('~').data, ASCII[94 * 8..].to_vec());
+        // past the end of the table
+        assert_eq!(font.get_bitmap('\u{7f}').data, UNKNOWN.to_vec());
+        assert_eq!(font.get_bitmap('─').data, UNKNOWN.to_vec());
+    }
+
+    #[test]
+    fn test_psf2() {
+        let mut bytes = PSF2_MAGIC.to_vec();
+        // version, header size, flags, length, glyph size, height, width
+        for value in [0u32, 32, 1, 2, 6, 3, 10] {
+            bytes.extend(value.to_le_bytes());
+        }
+        // a 10x3 glyph takes two bytes per row
+        bytes.extend([0xff, 0xc0, 0x80, 0x40, 0xff, 0xc0]);
+        bytes.extend([0x00; 6]);
+        bytes.extend("─".as_bytes());
+        bytes.extend([0xff]);
+        bytes.extend(" \u{a0}".as_bytes());
+        bytes.extend([0xfe, b'x', 0xff]);
+
+        let font = Font::from_psf(&bytes).unwrap();
+        assert_eq!(font.get_size(), (10, 3));
+        assert_eq!(
+            font.get_bitmap('─').data,
+            vec![0xff, 0xc0, 0x80, 0x40, 0xff, 0xc0]
+        );
+        assert_eq!(font.get_bitmap('\u{a0}').data, vec![0; 6]);
+        assert!(font.contains(' '));
+        assert!(!font.contains('x'));
+
+        assert!(Font::from_psf(&bytes[..40]).is_err());
+        assert!(Font::from_psf(b"not a font").is_err());
+    }
+
+    #[test]
+    fn test_psf1() {
+        let mut bytes = vec![0x36, 0x04, 0x00, 2];
+        for i in 0..256u32 {
+            bytes.extend([i as u8, !(i as u8)]);
+        }
+
+        let font = Font::from_psf(&bytes).unwrap();
+        assert_eq!(font.get_size(), (8, 2));
+        assert_eq!(font.get_bitmap('A').data, vec![0x41, 0xbe]);
+
+        // glyphs without rows
+        assert!(Font::from_psf(&[0x36, 0x04, 0x00, 0]).is_err());
+    }
+
+    #[test]
+    fn test_bdf() {
+        let bdf = "STARTFONT 2.1
+FONTBOUNDINGBOX 6 4 0 -1
+CHARS 2
+STARTCHAR period
+ENCODING 46
+BBX 2 2 2 -1
+BITMAP
+C0
+C0
+ENDCHAR
+STARTCHAR box
+ENCODING 9472
+BBX 6 1 0 1
+BITMAP
+FC
+ENDCHAR
+ENDFONT
+";
+
+        let font = Font::from_bdf(bdf.as_bytes()).unwrap();
+        assert_eq!(font.get_size(), (6, 4));
+        assert_eq!(font.get_bitmap('.').data, vec![0x00, 0x00, 0x30, 0x30]);
+        assert_eq!(font.get_bitmap('─').data, vec![0x00, 0xfc, 0x00, 0x00]);
+        assert_eq!(font.get_bitmap('a').data, vec![0xfc, 0x84, 0x84, 0xfc]);
+
+        assert!(Font::from_bdf(b"STARTCHAR a").is_err());
+        assert!(Font::from_bdf(b"FONTBOUNDINGBOX 8 -1 0 0").is_err());
+        assert!(Font::from_bdf(b"FONTBOUNDINGBOX 8 8 0 0\nSTARTCHAR a\nBBX 8 100000 0 0").is_err());
+        assert!(Font::from_bdf(
+            format!(
+                "FONTBOUNDINGBOX 8 8 0 0\nSTARTCHAR a\nBBX 8 8 0 {}",
+                i32::MAX
+            )
+            .as_bytes()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use super::Font;
+use crate::{Color, Image};
+
+/// Marks text that was cut off at the clipping width or line limit
+const ELLIPSIS: &str = "...";
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// How text is laid out, lines are broken on newlines and optionally wrapped at the width
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TextLayout {
+    /// width in pixels the text is clipped to, unbounded when not set
+    pub width: Option<i32>,
+    /// maximum number of lines, unbounded when not set
+    pub lines: Option<usize>,
+    pub align: Align,
+    pub wrap: bool,
+    pub ellipsis: bool,
+}
+
+impl TextLayout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn width(mut self, width: i32) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn lines(mut self, lines: usize) -> Self {
+        self.lines = Some(lines);
+        self
+    }
+
+    pub fn align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn wrap(mut self) -> Self {
+        self.wrap = true;
+        self
+    }
+
+    pub fn ellipsis(mut self) -> Self {
+        self.ellipsis = true;
+        self
+    }
+
+    /// Number of characters of the font that fit on a line
+    fn columns(&self, font: &Font) -> Option<usize> {
+        let (width, _) = font.get_size();
+        self.width
+            .map(|columns| (columns / width.max(1)).max(0) as usize)
+    }
+
+    /// Break the text into the lines that are drawn
+    pub fn layout(&self, text: &str, font: &Font) -> Vec<String> {
+        let columns = self.columns(font);
+        let mut lines = vec![];
+
+        for line in text.lines() {
+            match columns {
+                Some(columns) if self.wrap => wrap(line, columns, &mut lines),
+                _ => lines.push(line.to_string()),
+            }
+        }
+
+        let mut clipped = false;
+        if let Some(limit) = self.lines {
+            clipped = lines.len() > limit;
+            lines.truncate(limit);
+        }
+
+        if let Some(columns) = columns {
+            let last = lines.len().saturating_sub(1);
+
+            for (i, line) in lines.iter_mut().enumerate() {
+                let length = line.chars().count();
+
+                if self.ellipsis && (length > columns || (clipped && i == last)) {
+                    let ellipsis = ELLIPSIS.len().min(columns);
+                    let kept = length.min(columns - ellipsis);
+                    *line = line.chars().take(kept).collect();
+                    line.push_str(&ELLIPSIS[..ellipsis]);
+                } else if length > columns {
+                    *line = line.chars().take(columns).collect();
+                }
+            }
+        }
+
+        lines
+    }
+}
+
+/// Word wrap a line, words longer than a line are broken
+fn wrap(line: &str, columns: usize, lines: &mut Vec<String>) {
+    let mut current = String::new();
+
+    if columns == 0 {
+        lines.push(current);
+        return;
+    }
+
+    for word in line.split(' ') {
+        let length = current.chars().count();
+        let word_length = word.chars().count();
+
+        if length > 0 && length + 1 + word_length > columns {
+            lines.push(std::mem::take(&mut current));
+        } else if length > 0 {
+            current.push(' ');
+        }
+
+        let mut chars = word.chars().peekable();
+        while chars.peek().is_some() {
+            if current.chars().count() == columns {
+                lines.push(std::mem::take(&mut current));
+            }
+            current.extend(chars.next());
+        }
+    }
+
+    lines.push(current);
+}
+
+/// Render text to an image as wide as the clipping width, or the longest line without one
+pub fn render_text(text: &str, color: Color, font: &Font, layout: &TextLayout) -> Image {
+    let (font_width, font_height) = font.get_size();
+    let lines = layout.layout(text, font);
+    let length = |line: &String| line.chars().count() as i32 * font_width;
+    let width = layout
+        .width
+        .unwrap_or_else(|| lines.iter().map(length).max().unwrap_or(0));
+    let mut image = Image::new(width.max(0), lines.len() as i32 * font_height);
+
+    for (i, line) in lines.iter().enumerate() {
+        let x = match layout.align {
+            Align::Left => 0,
+            Align::Center => (width - length(line)) / 2,
+            Align::Right => width - length(line),
+        };
+        let y = i as i32 * font_height;
+
+        for (j, c) in line.chars().enumerate() {
+            let glyph = Image::from_bitmap(&font.get_bitmap(c), color);
+            image.layer(&glyph, x + j as i32 * font_width, y);
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let font = Font::ascii();
+        let text = "gate length 0.5\nlevel 1";
+
+        assert_eq!(
+            TextLayout::new().layout(text, &font),
+            vec!["gate length 0.5", "level 1"]
+        );
+        assert_eq!(
+            TextLayout::new().width(8 * 8).wrap().layout(text, &font),
+            vec!["gate", "length", "0.5", "level 1"]
+        );
+        assert_eq!(
+            TextLayout::new()
+                .width(8 * 4)
+                .wrap()
+                .layout("abcdefghij", &font),
+            vec!["abcd", "efgh", "ij"]
+        );
+        assert_eq!(
+            TextLayout::new()
+                .width(8 * 8)
+                .ellipsis()
+                .layout(text, &font),
+            vec!["gate ...", "level 1"]
+        );
+        assert_eq!(
+            TextLayout::new()
+                .width(8 * 10)
+                .lines(1)
+                .ellipsis()
+                .layout(text, &font),
+            vec!["gate le..."]
+        );
+        assert_eq!(
+            TextLayout::new().width(8 * 4).layout(text, &font),
+            vec!["gate", "leve"]
+        );
+    }
+
+    #[test]
+    fn test_render_text() {
+        let layout = TextLayout::new().width(32).align(Align::Right);
+        let image = render_text("!\n!!", Color::full(), &Font::ascii(), &layout);
+
+        assert_eq!(image.width, 32);
+        assert_eq!(image.height, 16);
+
+        // the exclamation mark is drawn in the fourth column of its glyph
+        let row = |y: usize| image.to_ascii()[y * 32..(y + 1) * 32].to_string();
+        assert_eq!(row(0), "00000000000000000000000000010000");
+        assert_eq!(row(8), "00000000000000000001000000010000");
+    }
+}
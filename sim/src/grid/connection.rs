@@ -0,0 +1,149 @@
+use super::Position;
+use screech::{Input, Output};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Connection {
+    pub from: Position,
+    pub to: Position,
+    pub output: Output,
+    pub input: Input,
+    /// the connection closes a feedback loop, its input reads the output
+    /// of the previous block instead of the current one
+    pub delayed: bool,
+}
+
+impl Connection {
+    pub fn new(from: Position, to: Position, output: Output, input: Input) -> Self {
+        Connection {
+            from,
+            to,
+            output,
+            input,
+            delayed: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// Sort positions so every position comes after the positions it receives signals from.
+/// Connections that would create a cycle are marked as delayed,
+/// returns the sorted indices and the positions that are part of a cycle
+pub fn sort(positions: &[Position], connections: &mut [Connection]) -> (Vec<usize>, Vec<Position>) {
+    let mut marks = vec![None; positions.len()];
+    let mut order = vec![];
+    let mut cycles = vec![];
+    let mut stack = vec![];
+
+    for connection in connections.iter_mut() {
+        connection.delayed = false;
+    }
+
+    // visit in reading order so the delayed connections are predictable
+    let mut start: Vec<usize> = (0..positions.len()).collect();
+    start.sort_by_key(|&i| (positions[i].y, positions[i].x));
+
+    for i in start {
+        if marks[i].is_none() {
+            visit(
+                i,
+                positions,
+                connections,
+                &mut marks,
+                &mut stack,
+                &mut order,
+                &mut cycles,
+            );
+        }
+    }
+
+    (order, cycles)
+}
+
+fn visit(
+    node: usize,
+    positions: &[Position],
+    connections: &mut [Connection],
+    marks: &mut [Option<Mark>],
+    stack: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+    cycles: &mut Vec<Position>,
+) {
+    marks[node] = Some(Mark::Visiting);
+    stack.push(node);
+
+    for c in 0..connections.len() {
+        if connections[c].to != positions[node] {
+            continue;
+        }
+
+        let source = match positions.iter().position(|p| *p == connections[c].from) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        match marks[source] {
+            Some(Mark::Visiting) => {
+                connections[c].delayed = true;
+
+                let index = stack.iter().position(|&n| n == source).unwrap_or(0);
+                for &n in &stack[index..] {
+                    if !cycles.contains(&positions[n]) {
+                        cycles.push(positions[n]);
+                    }
+                }
+            }
+            Some(Mark::Done) => (),
+            None => visit(source, positions, connections, marks, stack, order, cycles),
+        }
+    }
+
+    stack.pop();
+    marks[node] = Some(Mark::Done);
+    order.push(node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect(from: Position, to: Position) -> Connection {
+        Connection::new(from, to, Output::new(0, "output"), Input::new(0, "input"))
+    }
+
+    #[test]
+    fn test_sort() {
+        let a = Position::new(0, 0);
+        let b = Position::new(1, 0);
+        let c = Position::new(1, 1);
+        let mut connections = vec![connect(b, c), connect(a, b)];
+
+        let (order, cycles) = sort(&[c, b, a], &mut connections);
+
+        assert_eq!(order, vec![2, 1, 0]);
+        assert!(cycles.is_empty());
+        assert!(connections.iter().all(|c| !c.delayed));
+    }
+
+    #[test]
+    fn test_sort_cycle() {
+        let a = Position::new(0, 0);
+        let b = Position::new(1, 0);
+        let c = Position::new(1, 1);
+        let d = Position::new(5, 5);
+        let mut connections = vec![connect(a, b), connect(b, c), connect(c, a), connect(c, d)];
+
+        let (order, cycles) = sort(&[a, b, c, d], &mut connections);
+
+        assert_eq!(order, vec![1, 2, 0, 3]);
+        assert_eq!(cycles, vec![a, c, b]);
+        assert_eq!(
+            connections.iter().map(|c| c.delayed).collect::<Vec<_>>(),
+            vec![true, false, false, false]
+        );
+    }
+}
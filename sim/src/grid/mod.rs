@@ -1,43 +1,403 @@
+pub mod connection;
 pub mod position;
 pub mod rect;
 
-use crate::entity::Entity;
-use crate::{Color, Image};
+use crate::entity::{Entity, Setting};
+use crate::Error;
+pub use connection::Connection;
 pub use position::Position;
 pub use rect::Rect;
+use std::collections::HashMap;
+
+/// Setting changes the grid keeps waiting, later changes are rejected
+pub const MAX_SCHEDULED: usize = 256;
 
 pub struct Grid {
-    pub cursor_position: Position,
-    pub window_position: Position,
+    /// last error reported while sampling the grid
+    pub error: Option<Error>,
+    /// entities sorted so every entity comes after the entities it receives signals from
     entities: Vec<Box<dyn Entity>>,
+    connections: Vec<Connection>,
+    /// positions of entities that are part of a feedback loop
+    cycles: Vec<Position>,
+    /// maximum amount of entities, `None` for no limit
+    capacity: Option<usize>,
+    /// setting changes waiting to be applied, with the time in samples they apply at
+    scheduled: Vec<(u64, Position, Setting)>,
 }
 
 impl Grid {
     pub fn new() -> Self {
         Grid {
-            cursor_position: Position::origin(),
-            window_position: Position::new(-8, -4),
+            error: None,
             entities: vec![],
+            connections: vec![],
+            cycles: vec![],
+            capacity: None,
+            scheduled: Vec::with_capacity(MAX_SCHEDULED),
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut grid = Grid::new();
+        grid.capacity = Some(capacity);
+        grid
+    }
+
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+    }
+
+    /// Fails when placing an entity at a position would go over the capacity,
+    /// replacing an entity is always possible
+    pub fn check_capacity(&self, position: Position) -> Result<(), Error> {
+        let occupied = self.entities.iter().any(|e| e.get_position() == position);
+
+        match self.capacity {
+            Some(capacity) if !occupied && self.entities.len() >= capacity => {
+                Err(Error::CapacityReached(capacity))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Place an entity on the grid, returning the entity it replaced
+    pub fn add_entity(
+        &mut self,
+        mut entity: Box<dyn Entity>,
+        position: Position,
+    ) -> Result<Option<Box<dyn Entity>>, Error> {
+        self.check_capacity(position)?;
+
+        let replaced = self.take_entity(position);
+
+        entity.set_position(position);
+        self.entities.push(entity);
+        self.update_connections();
+
+        Ok(replaced)
+    }
+
+    pub fn remove_entity(&mut self, position: Position) -> Option<Box<dyn Entity>> {
+        let removed = self.take_entity(position);
+        self.update_connections();
+        removed
+    }
+
+    /// Move the entities inside a rectangle by an offset as one block,
+    /// returning the entities that were in the way at the destination
+    pub fn move_entities(&mut self, rect: &Rect, offset: Position) -> Vec<Box<dyn Entity>> {
+        let destination = Rect::new(rect.width, rect.height, rect.position.add(offset));
+        let mut moved = vec![];
+
+        // take them all first so the moved entities do not replace each other
+        while let Some(i) = self
+            .entities
+            .iter()
+            .position(|e| rect.intersect_position(e.get_position()))
+        {
+            moved.push(self.entities.remove(i));
+        }
+
+        let mut replaced = vec![];
+        while let Some(i) = self
+            .entities
+            .iter()
+            .position(|e| destination.intersect_position(e.get_position()))
+        {
+            replaced.push(self.entities.remove(i));
+        }
+
+        for mut entity in moved {
+            entity.set_position(entity.get_position().add(offset));
+            self.entities.push(entity);
+        }
+
+        for (_, position, _) in self.scheduled.iter_mut() {
+            if rect.intersect_position(*position) {
+                *position = position.add(offset);
+            }
+        }
+
+        self.update_connections();
+        replaced
+    }
+
+    fn take_entity(&mut self, position: Position) -> Option<Box<dyn Entity>> {
+        self.entities
+            .iter()
+            .position(|e| e.get_position() == position)
+            .map(|i| self.entities.remove(i))
+    }
+
+    pub fn get_connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    pub fn is_in_cycle(&self, position: Position) -> bool {
+        self.cycles.contains(&position)
+    }
+
+    pub fn get_cycles(&self) -> &[Position] {
+        &self.cycles
+    }
+
+    /// Connect all adjacent entities and sort them for sampling,
+    /// feedback loops are broken up by delaying one of their connections
+    fn update_connections(&mut self) {
+        let positions: Vec<Position> = self.entities.iter().map(|e| e.get_position()).collect();
+        let lookup: HashMap<Position, usize> =
+            positions.iter().enumerate().map(|(i, p)| (*p, i)).collect();
+
+        let mut connections = vec![];
+
+        for (entity, &position) in self.entities.iter().zip(positions.iter()) {
+            for side in [Position::LEFT, Position::UP, Position::RIGHT, Position::DOWN] {
+                let neighbour = position.add(side);
+
+                if let Some(&i) = lookup.get(&neighbour) {
+                    for (output, input) in entity.find_connections(&self.entities[i].as_kind(), side)
+                    {
+                        connections.push(Connection::new(neighbour, position, output, input));
+                    }
+                }
+            }
+        }
+
+        let (order, cycles) = connection::sort(&positions, &mut connections);
+        let mut entities: Vec<Option<Box<dyn Entity>>> = self.entities.drain(..).map(Some).collect();
+
+        self.entities = order.iter().filter_map(|&i| entities[i].take()).collect();
+        self.connections = connections;
+        self.cycles = cycles;
+    }
+
+    /// Queue a setting change for the entity at `position`,
+    /// `time` is in samples as reported by [`crate::Audio::get_time`].
+    /// Fails when `MAX_SCHEDULED` changes are already waiting, so the queue never grows
+    pub fn schedule_setting(
+        &mut self,
+        time: u64,
+        position: Position,
+        setting: Setting,
+    ) -> Result<(), Error> {
+        if self.scheduled.len() >= MAX_SCHEDULED {
+            return Err(Error::ScheduleFull);
+        }
+
+        let index = self.scheduled.partition_point(|(t, _, _)| *t <= time);
+        self.scheduled.insert(index, (time, position, setting));
+        Ok(())
+    }
+
+    /// Hand the setting changes that fall within the next buffer to their entities,
+    /// changes scheduled in the past are applied at the start of the buffer.
+    /// Returns the last change an entity rejected, the other changes are still handed out
+    pub fn dispatch_settings(&mut self, start: u64, length: usize) -> Result<(), Error> {
+        let end = start + length as u64;
+        let count = self.scheduled.partition_point(|(t, _, _)| *t < end);
+        let mut result = Ok(());
+
+        for (time, position, setting) in self.scheduled.drain(..count) {
+            if let Some(entity) = self.entities.iter_mut().find(|e| e.get_position() == position) {
+                let offset = time.saturating_sub(start) as usize;
+
+                if let Err(e) = entity.schedule_setting(offset, &setting) {
+                    result = Err(Error::Entity(position, Box::new(e)));
+                }
+            }
+        }
+
+        result
+    }
+
+    pub fn get_entities(&self) -> &[Box<dyn Entity>] {
+        &self.entities
+    }
+
+    pub fn get_mut_entity(&mut self, position: Position) -> Option<&mut Box<dyn Entity>> {
+        self.entities.iter_mut().find(|e| e.get_position() == position)
+    }
+
+    pub fn get_mut_entities(&mut self) -> impl Iterator<Item = &mut Box<dyn Entity>> {
+        self.entities.iter_mut()
+    }
+
+    /// Position of the entity that caused the last sampling error
+    pub fn get_error_position(&self) -> Option<Position> {
+        match &self.error {
+            Some(Error::Entity(position, _)) => Some(*position),
+            _ => None,
         }
     }
+}
 
-    pub fn get_mut_entities(&mut self) -> Vec<&mut Box<dyn Entity>> {
-        self.entities.iter_mut().collect()
+impl Default for Grid {
+    fn default() -> Self {
+        Self::new()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{SampleHold, SettingValue, Step, Trigger};
+    use screech::Screech;
+
+    #[test]
+    fn test_connections() {
+        let mut screech = Screech::new(4, 4);
+        let mut grid = Grid::new();
+
+        grid.add_entity(Box::new(SampleHold::new(&mut screech)), Position::new(1, 1))
+            .unwrap();
+        grid.add_entity(Box::new(Step::new(&mut screech)), Position::new(0, 1))
+            .unwrap();
+        grid.add_entity(Box::new(Trigger::new(&mut screech)), Position::new(1, 0))
+            .unwrap();
+
+        let connections: Vec<(Position, Position)> = grid
+            .get_connections()
+            .iter()
+            .map(|c| (c.from, c.to))
+            .collect();
+
+        assert_eq!(
+            connections,
+            vec![
+                (Position::new(0, 1), Position::new(1, 1)),
+                (Position::new(1, 0), Position::new(1, 1)),
+            ]
+        );
+
+        let order: Vec<Position> = grid
+            .get_mut_entities()
+            .map(|e| e.get_position())
+            .collect();
+
+        assert_eq!(
+            order,
+            vec![Position::new(1, 0), Position::new(0, 1), Position::new(1, 1)]
+        );
+        assert!(!grid.is_in_cycle(Position::new(1, 1)));
 
-    pub fn get_image_for_pos(&self, pos: Position) -> Option<Image> {
-	if self.cursor_position == pos {
-	    let mut image = Image::new(4, 4);
-	    image.clear(Color::new(251, 255, 38, 255));
-	    Some(image)
-	} else {
-	    for entity in self.entities.iter() {
-		if entity.get_position() == pos {
-		    return entity.get_grid_display();
-		}
-	    }
+        grid.remove_entity(Position::new(0, 1));
+        assert_eq!(grid.get_connections().len(), 1);
+    }
+
+    #[test]
+    fn test_move_entities() {
+        let mut screech = Screech::new(4, 4);
+        let mut grid = Grid::new();
+
+        grid.add_entity(Box::new(Trigger::new(&mut screech)), Position::new(0, 0))
+            .unwrap();
+        grid.add_entity(Box::new(Step::new(&mut screech)), Position::new(1, 0))
+            .unwrap();
+        grid.add_entity(Box::new(SampleHold::new(&mut screech)), Position::new(2, 0))
+            .unwrap();
+
+        // the step moves onto the sample and hold, the trigger follows
+        let rect = Rect::new(1, 0, Position::new(0, 0));
+        let replaced = grid.move_entities(&rect, Position::new(1, 0));
+        assert_eq!(replaced.len(), 1);
+        assert_eq!(replaced[0].get_position(), Position::new(2, 0));
+
+        let mut positions: Vec<Position> = grid
+            .get_entities()
+            .iter()
+            .map(|e| e.get_position())
+            .collect();
+        positions.sort_by_key(|p| p.x);
+        assert_eq!(positions, vec![Position::new(1, 0), Position::new(2, 0)]);
+        assert_eq!(grid.get_connections().len(), 1);
+
+        // the whole block is moved, clearing cells that are empty in the source
+        grid.add_entity(Box::new(SampleHold::new(&mut screech)), Position::new(3, 0))
+            .unwrap();
+        grid.move_entities(&Rect::new(2, 0, Position::new(0, 0)), Position::new(3, 0));
+
+        let mut positions: Vec<Position> = grid
+            .get_entities()
+            .iter()
+            .map(|e| e.get_position())
+            .collect();
+        positions.sort_by_key(|p| p.x);
+        assert_eq!(positions, vec![Position::new(4, 0), Position::new(5, 0)]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut screech = Screech::new(4, 4);
+        let mut grid = Grid::with_capacity(1);
+
+        assert!(grid
+            .add_entity(Box::new(Step::new(&mut screech)), Position::new(0, 0))
+            .unwrap()
+            .is_none());
+
+        // replacing an entity does not count towards the limit
+        assert!(grid
+            .add_entity(Box::new(Step::new(&mut screech)), Position::new(0, 0))
+            .unwrap()
+            .is_some());
+
+        assert!(matches!(
+            grid.add_entity(Box::new(Step::new(&mut screech)), Position::new(1, 0)),
+            Err(Error::CapacityReached(1))
+        ));
+    }
+
+    #[test]
+    fn test_dispatch_settings() {
+        let mut screech = Screech::new(4, 4);
+        let mut grid = Grid::new();
+        let setting = Setting::new(SettingValue::Float(0.0), "cap");
+
+        grid.add_entity(Box::new(Step::new(&mut screech)), Position::new(0, 0))
+            .unwrap();
+
+        grid.schedule_setting(10, Position::new(0, 0), setting.clone())
+            .unwrap();
+        grid.schedule_setting(2, Position::new(0, 0), setting.clone())
+            .unwrap();
+        grid.schedule_setting(4, Position::new(5, 5), setting)
+            .unwrap();
+
+        grid.dispatch_settings(0, 4).unwrap();
+        assert_eq!(
+            grid.scheduled.iter().map(|(t, _, _)| *t).collect::<Vec<_>>(),
+            vec![4, 10]
+        );
+
+        // changes for missing entities are dropped
+        grid.dispatch_settings(4, 4).unwrap();
+        assert_eq!(grid.scheduled.len(), 1);
+    }
+
+    #[test]
+    fn test_schedule_full() {
+        let mut screech = Screech::new(4, 4);
+        let mut grid = Grid::new();
+        let setting = Setting::new(SettingValue::Float(1.0), "bpm");
+
+        grid.add_entity(Box::new(Trigger::new(&mut screech)), Position::new(0, 0))
+            .unwrap();
+
+        for _ in 0..MAX_SCHEDULED {
+            grid.schedule_setting(0, Position::new(0, 0), setting.clone())
+                .unwrap();
+        }
+        assert!(matches!(
+            grid.schedule_setting(0, Position::new(0, 0), setting),
+            Err(Error::ScheduleFull)
+        ));
 
-	    None
-	}
+        // the trigger keeps the first changes and rejects the rest
+        assert!(matches!(
+            grid.dispatch_settings(0, 4),
+            Err(Error::Entity(_, e)) if matches!(*e, Error::ScheduleFull)
+        ));
+        assert!(grid.scheduled.is_empty());
     }
 }
@@ -8,6 +8,11 @@ pub struct Position {
 }
 
 impl Position {
+    pub const LEFT: Position = Position { x: -1, y: 0 };
+    pub const RIGHT: Position = Position { x: 1, y: 0 };
+    pub const UP: Position = Position { x: 0, y: -1 };
+    pub const DOWN: Position = Position { x: 0, y: 1 };
+
     pub fn new(x: i32, y: i32) -> Self {
         Position { x, y }
     }
@@ -22,6 +27,7 @@ impl Position {
         self
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn add(mut self, pos: Self) -> Self {
         self.x += pos.x;
         self.y += pos.y;
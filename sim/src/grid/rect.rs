@@ -24,7 +24,15 @@ impl Rect {
             || rect.position.y + rect.height < self.position.y)
     }
 
-    #[allow(dead_code)]
+    /// Rectangle covering the cells between two corners, both included
+    pub fn from_corners(a: Position, b: Position) -> Self {
+        Rect {
+            position: Position::new(a.x.min(b.x), a.y.min(b.y)),
+            width: (a.x - b.x).abs(),
+            height: (a.y - b.y).abs(),
+        }
+    }
+
     pub fn intersect_position(&self, pos: Position) -> bool {
         pos.x >= self.position.x
             && pos.x <= self.position.x + self.width
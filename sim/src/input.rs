@@ -1,5 +1,22 @@
 use std::collections::HashMap;
 
+/// Keys held down during a pointer event
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub control: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+/// Position of the pointer in pixels of the viewport
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Pointer {
+    pub x: i32,
+    pub y: i32,
+    pub modifiers: Modifiers,
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Input {
     Char(char),
@@ -16,11 +33,23 @@ pub enum Input {
     Right,
     Down,
     Left,
+    PageUp,
+    PageDown,
+    PointerMove(Pointer),
+    /// primary button pressed
+    PointerDown(Pointer),
+    PointerUp(Pointer),
+    /// wheel turned by a number of steps horizontally and vertically,
+    /// positive to the right and down
+    Wheel(Pointer, i32, i32),
 }
 
 pub struct InputState {
     pub buffer: Vec<Input>,
     is_key_down_map: HashMap<Input, bool>,
+    /// last position of the pointer, `None` until it entered the viewport
+    pointer: Option<Pointer>,
+    is_pointer_down: bool,
 }
 
 impl InputState {
@@ -28,6 +57,8 @@ impl InputState {
         InputState {
             buffer: vec![],
             is_key_down_map: HashMap::new(),
+            pointer: None,
+            is_pointer_down: false,
         }
     }
 
@@ -44,7 +75,43 @@ impl InputState {
         self.is_key_down_map.insert(input, false);
     }
 
+    pub fn get_pointer(&self) -> Option<Pointer> {
+        self.pointer
+    }
+
+    pub fn is_pointer_down(&self) -> bool {
+        self.is_pointer_down
+    }
+
+    pub fn pointer_move(&mut self, pointer: Pointer) {
+        self.buffer.push(Input::PointerMove(pointer));
+        self.pointer = Some(pointer);
+    }
+
+    pub fn pointer_down(&mut self, pointer: Pointer) {
+        self.buffer.push(Input::PointerDown(pointer));
+        self.pointer = Some(pointer);
+        self.is_pointer_down = true;
+    }
+
+    pub fn pointer_up(&mut self, pointer: Pointer) {
+        self.buffer.push(Input::PointerUp(pointer));
+        self.pointer = Some(pointer);
+        self.is_pointer_down = false;
+    }
+
+    pub fn wheel(&mut self, pointer: Pointer, x: i32, y: i32) {
+        self.buffer.push(Input::Wheel(pointer, x, y));
+        self.pointer = Some(pointer);
+    }
+
     pub fn clear_buffer(&mut self) {
         self.buffer.clear();
     }
 }
+
+impl Default for InputState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
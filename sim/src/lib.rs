@@ -1,11 +1,46 @@
 mod audio;
+#[cfg(not(target_arch = "wasm32"))]
+mod backend;
+mod engine;
 mod entity;
+mod error;
 mod glyphs;
 mod grid;
 mod input;
+mod midi;
+mod osc;
+mod patch;
+mod remote;
+mod sample;
+mod sync;
+mod tracker;
+mod transport;
 mod ui;
 
 pub use audio::Audio;
-pub use grid::Grid;
-pub use input::{Input, InputState};
+#[cfg(all(feature = "alsa", not(target_arch = "wasm32")))]
+pub use backend::AlsaBackend;
+#[cfg(not(target_arch = "wasm32"))]
+pub use backend::{Backend, NullBackend, Stream, StreamConfig, WavBackend};
+pub use engine::{
+    Command, Controller, Engine, EntitySnapshot, MessageRing, Meter, Relay, RingStorage,
+    Snapshot, Worklet, MAX_MESSAGE_SIZE, RENDER_QUANTUM,
+};
+pub use entity::{EntityType, Setting, SettingValue};
+pub use error::Error;
+pub use glyphs::{Align, Font, TextLayout};
+pub use grid::{Connection, Grid, Position, Rect};
+pub use input::{Input, InputState, Modifiers, Pointer};
+#[cfg(feature = "alsa")]
+pub use midi::AlsaMidiTransport;
+pub use midi::{
+    BufferedTransport, MidiFile, MidiInput, MidiMapping, MidiMessage, MidiParser, MidiTrack,
+    MidiTransport,
+};
+pub use osc::{command_from_osc, gate_message, OscArgument, OscClient, OscMessage, OscServer};
+pub use patch::{Patch, PatchEntity};
+pub use remote::Remote;
+pub use sample::Sample;
+pub use sync::{clock_micros, TempoSync, Timeline};
+pub use transport::Clock;
 pub use ui::{Bitmap, Color, Graphics, Image, UserInterface};
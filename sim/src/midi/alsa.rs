@@ -0,0 +1,90 @@
+use super::MidiTransport;
+use crate::Error;
+use std::ffi::{c_char, c_int, c_long, c_void, CStr, CString};
+use std::ptr;
+
+const SND_RAWMIDI_NONBLOCK: c_int = 0x0002;
+const EAGAIN: c_long = 11;
+
+#[repr(C)]
+struct SndRawmidi {
+    _private: [u8; 0],
+}
+
+#[link(name = "asound")]
+extern "C" {
+    fn snd_rawmidi_open(
+        input: *mut *mut SndRawmidi,
+        output: *mut *mut SndRawmidi,
+        name: *const c_char,
+        mode: c_int,
+    ) -> c_int;
+    fn snd_rawmidi_read(rawmidi: *mut SndRawmidi, buffer: *mut c_void, size: usize) -> c_long;
+    fn snd_rawmidi_close(rawmidi: *mut SndRawmidi) -> c_int;
+    fn snd_strerror(errnum: c_int) -> *const c_char;
+}
+
+fn error(result: c_int) -> Error {
+    let message = unsafe { CStr::from_ptr(snd_strerror(result)) };
+    Error::Backend(message.to_string_lossy().into())
+}
+
+/// Reads an ALSA raw MIDI port such as "hw:1,0,0"
+pub struct AlsaMidiTransport {
+    rawmidi: *mut SndRawmidi,
+}
+
+// the port is only read by the thread owning the transport
+unsafe impl Send for AlsaMidiTransport {}
+
+impl AlsaMidiTransport {
+    pub fn open(port: &str) -> Result<Self, Error> {
+        let name =
+            CString::new(port).map_err(|_| Error::Backend(format!("invalid port {}", port)))?;
+        let mut rawmidi = ptr::null_mut();
+
+        let result = unsafe {
+            snd_rawmidi_open(
+                &mut rawmidi,
+                ptr::null_mut(),
+                name.as_ptr(),
+                SND_RAWMIDI_NONBLOCK,
+            )
+        };
+
+        if result < 0 {
+            return Err(error(result));
+        }
+
+        Ok(AlsaMidiTransport { rawmidi })
+    }
+}
+
+impl MidiTransport for AlsaMidiTransport {
+    fn receive(&mut self, bytes: &mut Vec<u8>) -> Result<(), Error> {
+        let mut buffer = [0u8; 256];
+
+        loop {
+            let read = unsafe {
+                snd_rawmidi_read(
+                    self.rawmidi,
+                    buffer.as_mut_ptr() as *mut c_void,
+                    buffer.len(),
+                )
+            };
+
+            match read {
+                r if r == -EAGAIN => return Ok(()),
+                r if r < 0 => return Err(error(r as c_int)),
+                0 => return Ok(()),
+                r => bytes.extend_from_slice(&buffer[..r as usize]),
+            }
+        }
+    }
+}
+
+impl Drop for AlsaMidiTransport {
+    fn drop(&mut self) {
+        unsafe { snd_rawmidi_close(self.rawmidi) };
+    }
+}
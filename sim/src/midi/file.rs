@@ -0,0 +1,161 @@
+use super::MidiMessage;
+
+/// Messages of a single track with their time in ticks
+#[derive(Debug, Clone, Default)]
+pub struct MidiTrack {
+    pub name: String,
+    pub events: Vec<(u64, MidiMessage)>,
+}
+
+impl MidiTrack {
+    pub fn new(name: &str) -> Self {
+        MidiTrack {
+            name: name.into(),
+            events: vec![],
+        }
+    }
+}
+
+/// Standard MIDI File in format 1, the tempo is written to a separate first track
+#[derive(Debug, Clone)]
+pub struct MidiFile {
+    /// ticks per quarter note
+    pub division: u16,
+    pub bpm: f32,
+    pub tracks: Vec<MidiTrack>,
+}
+
+impl MidiFile {
+    pub fn new(division: u16, bpm: f32) -> Self {
+        MidiFile {
+            division,
+            bpm,
+            tracks: vec![],
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&(self.tracks.len() as u16 + 1).to_be_bytes());
+        bytes.extend_from_slice(&self.division.to_be_bytes());
+
+        // microseconds per quarter note
+        let tempo = (60_000_000.0 / self.bpm.max(1.0)) as u32;
+        let mut conductor = vec![0x00, 0xFF, 0x51, 0x03];
+        conductor.extend_from_slice(&tempo.to_be_bytes()[1..]);
+        write_chunk(&mut bytes, conductor);
+
+        for track in self.tracks.iter() {
+            write_chunk(&mut bytes, encode_track(track));
+        }
+
+        bytes
+    }
+}
+
+fn encode_track(track: &MidiTrack) -> Vec<u8> {
+    let mut data = vec![0x00, 0xFF, 0x03];
+    write_variable_length(&mut data, track.name.len() as u64);
+    data.extend_from_slice(track.name.as_bytes());
+
+    let mut events = track.events.clone();
+    events.sort_by_key(|(time, _)| *time);
+
+    let mut last = 0;
+
+    for (time, message) in events {
+        // transport messages have no meaning inside a file
+        if !matches!(
+            message,
+            MidiMessage::NoteOn { .. }
+                | MidiMessage::NoteOff { .. }
+                | MidiMessage::ControlChange { .. }
+        ) {
+            continue;
+        }
+
+        write_variable_length(&mut data, time - last);
+        message.encode(&mut data);
+        last = time;
+    }
+
+    data
+}
+
+/// Write a track chunk, closing it with an end of track event
+fn write_chunk(bytes: &mut Vec<u8>, mut data: Vec<u8>) {
+    data.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    bytes.extend_from_slice(b"MTrk");
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.append(&mut data);
+}
+
+/// Seven bits per byte with the highest bit set on all but the last byte
+fn write_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variable_length() {
+        let mut bytes = vec![];
+        write_variable_length(&mut bytes, 0);
+        write_variable_length(&mut bytes, 0x7F);
+        write_variable_length(&mut bytes, 0x80);
+        write_variable_length(&mut bytes, 0x0FFF_FFFF);
+
+        assert_eq!(bytes, vec![0x00, 0x7F, 0x81, 0x00, 0xFF, 0xFF, 0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let mut file = MidiFile::new(96, 120.0);
+        let mut track = MidiTrack::new("a");
+        track.events.push((
+            96,
+            MidiMessage::NoteOff {
+                channel: 0,
+                note: 60,
+                velocity: 0,
+            },
+        ));
+        track.events.push((
+            0,
+            MidiMessage::NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100,
+            },
+        ));
+        file.tracks.push(track);
+
+        let bytes = file.to_bytes();
+
+        assert_eq!(&bytes[..14], b"MThd\0\0\0\x06\0\x01\0\x02\0\x60");
+        // 500000 microseconds per quarter note at 120 bpm
+        assert_eq!(
+            &bytes[14..33],
+            b"MTrk\0\0\0\x0b\0\xff\x51\x03\x07\xa1\x20\0\xff\x2f\0"
+        );
+        assert_eq!(
+            &bytes[33..],
+            b"MTrk\0\0\0\x11\0\xff\x03\x01a\0\x90\x3c\x64\x60\x80\x3c\0\0\xff\x2f\0"
+        );
+    }
+}
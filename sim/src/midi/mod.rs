@@ -0,0 +1,319 @@
+#[cfg(feature = "alsa")]
+mod alsa;
+mod file;
+
+use crate::grid::Position;
+use crate::Error;
+#[cfg(feature = "alsa")]
+pub use alsa::AlsaMidiTransport;
+pub use file::{MidiFile, MidiTrack};
+
+/// Channel messages and transport messages the sim responds to,
+/// channels are numbered 0 to 15
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl MidiMessage {
+    /// Append the message as it is sent over the wire
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => bytes.extend_from_slice(&[0x90 | channel, note, velocity]),
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => bytes.extend_from_slice(&[0x80 | channel, note, velocity]),
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => bytes.extend_from_slice(&[0xB0 | channel, controller, value]),
+            MidiMessage::Clock => bytes.push(0xF8),
+            MidiMessage::Start => bytes.push(0xFA),
+            MidiMessage::Continue => bytes.push(0xFB),
+            MidiMessage::Stop => bytes.push(0xFC),
+        }
+    }
+}
+
+/// Control change learned onto a setting of the entity at a position
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiMapping {
+    pub channel: u8,
+    pub controller: u8,
+    pub position: Position,
+    pub setting: String,
+}
+
+impl MidiMapping {
+    pub fn matches(&self, channel: u8, controller: u8) -> bool {
+        self.channel == channel && self.controller == controller
+    }
+}
+
+/// Turns a raw MIDI byte stream into messages, keeping track of running status
+/// and skipping messages the sim has no use for such as system exclusive
+#[derive(Debug, Default)]
+pub struct MidiParser {
+    status: Option<u8>,
+    data: [u8; 2],
+    length: usize,
+    sysex: bool,
+}
+
+impl MidiParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(&mut self, bytes: &[u8], messages: &mut Vec<MidiMessage>) {
+        for &byte in bytes {
+            if let Some(message) = self.push(byte) {
+                messages.push(message);
+            }
+        }
+    }
+
+    /// Feed a single byte, returning a message once it is complete
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage> {
+        match byte {
+            // real time messages can appear anywhere, even inside other messages
+            0xF8 => Some(MidiMessage::Clock),
+            0xFA => Some(MidiMessage::Start),
+            0xFB => Some(MidiMessage::Continue),
+            0xFC => Some(MidiMessage::Stop),
+            0xF9..=0xFF => None,
+            0xF0 => {
+                self.sysex = true;
+                self.status = None;
+                None
+            }
+            0xF7 => {
+                self.sysex = false;
+                None
+            }
+            0x80..=0xF6 => {
+                // system common messages cancel running status
+                self.status = if byte < 0xF0 { Some(byte) } else { None };
+                self.sysex = false;
+                self.length = 0;
+                None
+            }
+            _ if self.sysex => None,
+            _ => {
+                let status = self.status?;
+
+                self.data[self.length] = byte;
+                self.length += 1;
+
+                if self.length < data_length(status) {
+                    return None;
+                }
+
+                self.length = 0;
+                self.message(status)
+            }
+        }
+    }
+
+    fn message(&self, status: u8) -> Option<MidiMessage> {
+        let channel = status & 0x0F;
+        let [first, second] = self.data;
+
+        match status & 0xF0 {
+            0x90 if second > 0 => Some(MidiMessage::NoteOn {
+                channel,
+                note: first,
+                velocity: second,
+            }),
+            // a note on without velocity is commonly sent in place of a note off
+            0x80 | 0x90 => Some(MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: second,
+            }),
+            0xB0 => Some(MidiMessage::ControlChange {
+                channel,
+                controller: first,
+                value: second,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn data_length(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    }
+}
+
+/// Source of raw MIDI bytes, for example Web MIDI or an ALSA raw MIDI port
+pub trait MidiTransport {
+    /// Append the bytes received since the last call without blocking
+    fn receive(&mut self, bytes: &mut Vec<u8>) -> Result<(), Error>;
+}
+
+/// Transport for hosts that get MIDI bytes pushed to them, like Web MIDI
+#[derive(Debug, Default)]
+pub struct BufferedTransport {
+    bytes: Vec<u8>,
+}
+
+impl BufferedTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+impl MidiTransport for BufferedTransport {
+    fn receive(&mut self, bytes: &mut Vec<u8>) -> Result<(), Error> {
+        bytes.append(&mut self.bytes);
+        Ok(())
+    }
+}
+
+/// Reads a transport and parses what it received
+pub struct MidiInput<T> {
+    pub transport: T,
+    parser: MidiParser,
+    bytes: Vec<u8>,
+}
+
+impl<T: MidiTransport> MidiInput<T> {
+    pub fn new(transport: T) -> Self {
+        MidiInput {
+            transport,
+            parser: MidiParser::new(),
+            bytes: vec![],
+        }
+    }
+
+    /// Messages completed since the last poll
+    pub fn poll(&mut self) -> Result<Vec<MidiMessage>, Error> {
+        let mut messages = vec![];
+
+        self.bytes.clear();
+        self.transport.receive(&mut self.bytes)?;
+        self.parser.parse(&self.bytes, &mut messages);
+
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> Vec<MidiMessage> {
+        let mut messages = vec![];
+        MidiParser::new().parse(bytes, &mut messages);
+        messages
+    }
+
+    #[test]
+    fn test_running_status() {
+        assert_eq!(
+            parse(&[0x91, 60, 100, 64, 90, 60, 0]),
+            vec![
+                MidiMessage::NoteOn {
+                    channel: 1,
+                    note: 60,
+                    velocity: 100
+                },
+                MidiMessage::NoteOn {
+                    channel: 1,
+                    note: 64,
+                    velocity: 90
+                },
+                MidiMessage::NoteOff {
+                    channel: 1,
+                    note: 60,
+                    velocity: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interleaved_and_ignored() {
+        assert_eq!(
+            parse(&[
+                0xB0, 7, 0xF8, 127, // clock in the middle of a control change
+                0xC0, 5, // program change is ignored
+                0xF0, 1, 2, 3, 0xF7, // system exclusive is skipped
+                0x80, 60, 0, 0xFC,
+            ]),
+            vec![
+                MidiMessage::Clock,
+                MidiMessage::ControlChange {
+                    channel: 0,
+                    controller: 7,
+                    value: 127
+                },
+                MidiMessage::NoteOff {
+                    channel: 0,
+                    note: 60,
+                    velocity: 0
+                },
+                MidiMessage::Stop,
+            ]
+        );
+
+        // data without a status byte is dropped
+        assert!(parse(&[60, 100]).is_empty());
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let message = MidiMessage::ControlChange {
+            channel: 15,
+            controller: 74,
+            value: 12,
+        };
+        let mut bytes = vec![];
+        message.encode(&mut bytes);
+
+        assert_eq!(parse(&bytes), vec![message]);
+    }
+
+    #[test]
+    fn test_input() {
+        let mut input = MidiInput::new(BufferedTransport::new());
+
+        input.transport.push(&[0x90, 60]);
+        assert!(input.poll().unwrap().is_empty());
+
+        input.transport.push(&[100]);
+        assert_eq!(input.poll().unwrap().len(), 1);
+    }
+}
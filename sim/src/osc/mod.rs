@@ -0,0 +1,403 @@
+mod udp;
+
+use crate::engine::{Command, Snapshot};
+use crate::entity::{EntityType, SettingValue};
+use crate::grid::Position;
+use crate::Error;
+pub use udp::{OscClient, OscServer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArgument {
+    Int(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    String(String),
+    Boolean(bool),
+}
+
+impl OscArgument {
+    fn tag(&self) -> char {
+        match self {
+            OscArgument::Int(_) => 'i',
+            OscArgument::Float(_) => 'f',
+            OscArgument::Long(_) => 'h',
+            OscArgument::Double(_) => 'd',
+            OscArgument::String(_) => 's',
+            OscArgument::Boolean(true) => 'T',
+            OscArgument::Boolean(false) => 'F',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub arguments: Vec<OscArgument>,
+}
+
+impl OscMessage {
+    pub fn new(address: &str, arguments: Vec<OscArgument>) -> Self {
+        OscMessage {
+            address: address.into(),
+            arguments,
+        }
+    }
+
+    /// Encode as an OSC 1.0 packet, big endian and padded to four bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        let tags: String = self.arguments.iter().map(|a| a.tag()).collect();
+
+        write_string(&mut bytes, &self.address);
+        write_string(&mut bytes, &format!(",{}", tags));
+
+        for argument in self.arguments.iter() {
+            match argument {
+                OscArgument::Int(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+                OscArgument::Float(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+                OscArgument::Long(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+                OscArgument::Double(v) => bytes.extend_from_slice(&v.to_be_bytes()),
+                OscArgument::String(v) => write_string(&mut bytes, v),
+                OscArgument::Boolean(_) => (),
+            }
+        }
+
+        bytes
+    }
+
+    /// Decode a packet, the messages of a bundle are returned in order
+    /// and their time tags are ignored
+    pub fn decode(bytes: &[u8]) -> Result<Vec<OscMessage>, Error> {
+        let mut messages = vec![];
+        decode_packet(bytes, &mut messages)?;
+        Ok(messages)
+    }
+}
+
+fn decode_packet(bytes: &[u8], messages: &mut Vec<OscMessage>) -> Result<(), Error> {
+    let mut reader = Reader { bytes, offset: 0 };
+
+    if bytes.starts_with(b"#bundle\0") {
+        reader.take(16)?;
+
+        while reader.offset < bytes.len() {
+            let size = reader.i32()?;
+            let size = usize::try_from(size).map_err(|_| invalid("negative element size"))?;
+            decode_packet(reader.take(size)?, messages)?;
+        }
+
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(invalid("address does not start with /"));
+    }
+
+    // a missing type tag string is allowed by older implementations
+    let tags = if reader.offset < bytes.len() {
+        reader.string()?
+    } else {
+        String::from(",")
+    };
+
+    let tags = tags
+        .strip_prefix(',')
+        .ok_or_else(|| invalid("missing type tags"))?;
+    let mut arguments = vec![];
+
+    for tag in tags.chars() {
+        arguments.push(match tag {
+            'i' => OscArgument::Int(reader.i32()?),
+            'f' => OscArgument::Float(f32::from_bits(reader.i32()? as u32)),
+            'h' => OscArgument::Long(reader.i64()?),
+            'd' => OscArgument::Double(f64::from_bits(reader.i64()? as u64)),
+            's' => OscArgument::String(reader.string()?),
+            'T' => OscArgument::Boolean(true),
+            'F' => OscArgument::Boolean(false),
+            tag => return Err(invalid(&format!("unsupported type {}", tag))),
+        });
+    }
+
+    messages.push(OscMessage::new(&address, arguments));
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::Osc(message.into())
+}
+
+/// Write a null terminated string padded to a multiple of four bytes
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(0);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + length)
+            .ok_or_else(|| invalid("packet is too short"))?;
+
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, Error> {
+        let mut array = [0; 4];
+        array.copy_from_slice(self.take(4)?);
+        Ok(i32::from_be_bytes(array))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        let mut array = [0; 8];
+        array.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(array))
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let rest = &self.bytes[self.offset.min(self.bytes.len())..];
+        let length = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid("string is not terminated"))?;
+        let value = std::str::from_utf8(&rest[..length])
+            .map_err(|_| invalid("string is not utf-8"))?
+            .to_string();
+
+        self.take((length + 4) & !3)?;
+        Ok(value)
+    }
+}
+
+/// Turn a message into a command for the grid:
+///
+/// - `/sim/transport/play`, `/sim/transport/stop` and `/sim/transport/bpm <value>`
+/// - `/sim/cell/<x>/<y>/add <type>` and `/sim/cell/<x>/<y>/remove`
+/// - `/sim/cell/<x>/<y>/watch <bool>` to send OSC for the gates of the entity
+/// - `/sim/cell/<x>/<y>/<setting> <value>`, the value is converted to the type of the setting
+pub fn command_from_osc(message: &OscMessage, snapshot: &Snapshot) -> Result<Command, Error> {
+    let parts: Vec<&str> = message.address.split('/').skip(1).collect();
+    let argument = message.arguments.first();
+
+    match parts.as_slice() {
+        ["sim", "transport", "play"] => Ok(Command::Play),
+        ["sim", "transport", "stop"] => Ok(Command::Stop),
+        ["sim", "transport", "bpm"] => argument
+            .and_then(|argument| setting_value(argument, &SettingValue::Float(0.0)))
+            .and_then(|value| match value {
+                SettingValue::Float(bpm) if bpm > 0.0 => Some(Command::SetTempo(bpm as f64)),
+                _ => None,
+            })
+            .ok_or_else(|| invalid("invalid tempo")),
+        ["sim", "cell", x, y, action] => {
+            let position = Position::new(
+                x.parse().map_err(|_| invalid("invalid cell"))?,
+                y.parse().map_err(|_| invalid("invalid cell"))?,
+            );
+
+            match (*action, argument) {
+                ("add", Some(OscArgument::String(name))) => EntityType::from_name(name)
+                    .map(|entity_type| Command::AddEntity(position, entity_type))
+                    .ok_or_else(|| invalid(&format!("unknown entity {}", name))),
+                ("remove", _) => Ok(Command::RemoveEntity(position)),
+                ("watch", argument) => Ok(Command::WatchGate(
+                    position,
+                    argument.map(is_on).unwrap_or(true),
+                )),
+                (name, Some(argument)) => {
+                    let mut setting = snapshot
+                        .get_entity(position)
+                        .and_then(|entity| entity.settings.iter().find(|s| s.description == name))
+                        .ok_or_else(|| invalid(&format!("no setting {} at {},{}", name, x, y)))?
+                        .clone();
+
+                    setting.value = setting_value(argument, &setting.value).ok_or_else(|| {
+                        Error::InvalidSetting(
+                            setting.description.to_string(),
+                            format!("{:?}", argument),
+                        )
+                    })?;
+
+                    Ok(Command::UpdateSetting(position, setting))
+                }
+                _ => Err(invalid("missing value")),
+            }
+        }
+        _ => Err(invalid(&format!("unknown address {}", message.address))),
+    }
+}
+
+/// Message sent when the gate of a watched entity opens or closes
+pub fn gate_message(position: Position, gate: bool) -> OscMessage {
+    OscMessage::new(
+        &format!("/sim/cell/{}/{}/gate", position.x, position.y),
+        vec![OscArgument::Int(gate as i32)],
+    )
+}
+
+fn is_on(argument: &OscArgument) -> bool {
+    match argument {
+        OscArgument::Int(v) => *v != 0,
+        OscArgument::Float(v) => *v != 0.0,
+        OscArgument::Long(v) => *v != 0,
+        OscArgument::Double(v) => *v != 0.0,
+        OscArgument::String(v) => v == "true" || v == "1",
+        OscArgument::Boolean(v) => *v,
+    }
+}
+
+fn setting_value(argument: &OscArgument, current: &SettingValue) -> Option<SettingValue> {
+    let number = match argument {
+        OscArgument::Int(v) => Some(*v as f32),
+        OscArgument::Float(v) => Some(*v),
+        OscArgument::Long(v) => Some(*v as f32),
+        OscArgument::Double(v) => Some(*v as f32),
+        OscArgument::String(v) => v.parse().ok(),
+        OscArgument::Boolean(v) => Some(*v as i32 as f32),
+    };
+
+    match current {
+        SettingValue::Float(_) => number.map(SettingValue::Float),
+        SettingValue::Integer(_) => number
+            .filter(|v| *v >= 0.0)
+            .map(|v| SettingValue::Integer(v.round() as usize)),
+        SettingValue::Boolean(_) => Some(SettingValue::Boolean(is_on(argument))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EntitySnapshot;
+    use crate::entity::Setting;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            entities: vec![EntitySnapshot {
+                position: Position::new(3, 4),
+                kind: EntityType::Trigger,
+                grid_display: None,
+                detail_display: None,
+                settings: vec![
+                    Setting::new(SettingValue::Float(480.0), "bpm"),
+                    Setting::new(SettingValue::Integer(8), "steps"),
+                ],
+                level: 0.0,
+                display_level: None,
+                sample: None,
+            }],
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let message = OscMessage::new(
+            "/sim",
+            vec![OscArgument::Int(1), OscArgument::Boolean(true)],
+        );
+
+        assert_eq!(message.encode(), b"/sim\0\0\0\0,iT\0\0\0\0\x01".to_vec());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let message = OscMessage::new(
+            "/sim/cell/3/4/add",
+            vec![
+                OscArgument::String("euclid".into()),
+                OscArgument::Float(0.5),
+                OscArgument::Long(-1 << 40),
+                OscArgument::Double(0.1),
+                OscArgument::Boolean(false),
+            ],
+        );
+
+        assert_eq!(
+            OscMessage::decode(&message.encode()).unwrap(),
+            vec![message]
+        );
+    }
+
+    #[test]
+    fn test_bundle() {
+        let first = OscMessage::new("/a", vec![OscArgument::Int(7)]).encode();
+        let second = OscMessage::new("/b", vec![]).encode();
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+
+        for message in [&first, &second] {
+            bundle.extend_from_slice(&(message.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(message);
+        }
+
+        let messages = OscMessage::decode(&bundle).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].address, "/b");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(OscMessage::decode(b"").is_err());
+        assert!(OscMessage::decode(b"/sim").is_err());
+        assert!(OscMessage::decode(b"sim\0").is_err());
+        assert!(OscMessage::decode(b"/sim\0\0\0\0,i\0\0").is_err());
+        assert!(OscMessage::decode(b"/sim\0\0\0\0,x\0\0").is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let snapshot = snapshot();
+        let command = |address: &str, arguments| {
+            command_from_osc(&OscMessage::new(address, arguments), &snapshot)
+        };
+
+        assert!(matches!(
+            command("/sim/transport/play", vec![]),
+            Ok(Command::Play)
+        ));
+        assert!(matches!(
+            command("/sim/transport/bpm", vec![OscArgument::Float(98.0)]),
+            Ok(Command::SetTempo(bpm)) if bpm == 98.0
+        ));
+        assert!(matches!(
+            command("/sim/cell/3/4/bpm", vec![OscArgument::Int(120)]),
+            Ok(Command::UpdateSetting(p, Setting { value: SettingValue::Float(v), .. }))
+                if p == Position::new(3, 4) && v == 120.0
+        ));
+        assert!(matches!(
+            command("/sim/cell/3/4/steps", vec![OscArgument::Float(4.2)]),
+            Ok(Command::UpdateSetting(
+                _,
+                Setting {
+                    value: SettingValue::Integer(4),
+                    ..
+                }
+            ))
+        ));
+        assert!(matches!(
+            command("/sim/cell/-1/0/add", vec![OscArgument::String("slew".into())]),
+            Ok(Command::AddEntity(p, EntityType::Slew)) if p == Position::new(-1, 0)
+        ));
+        assert!(matches!(
+            command("/sim/cell/3/4/watch", vec![]),
+            Ok(Command::WatchGate(_, true))
+        ));
+
+        assert!(command("/sim/cell/3/4/steps", vec![OscArgument::Int(-1)]).is_err());
+        assert!(command("/sim/cell/3/4/pulses", vec![OscArgument::Int(1)]).is_err());
+        assert!(command("/sim/cell/x/4/remove", vec![]).is_err());
+        assert!(command("/other", vec![]).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use super::OscMessage;
+use crate::Error;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+
+/// Largest packet read from the socket
+const MAX_PACKET_SIZE: usize = 8192;
+
+fn error(e: std::io::Error) -> Error {
+    Error::Osc(e.to_string())
+}
+
+/// Receives OSC packets on a localhost UDP port without blocking
+pub struct OscServer {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl OscServer {
+    /// Listen on a port of the loopback interface, port 0 picks a free port
+    pub fn bind(port: u16) -> Result<Self, Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).map_err(error)?;
+        socket.set_nonblocking(true).map_err(error)?;
+
+        Ok(OscServer {
+            socket,
+            buffer: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr().map_err(error)
+    }
+
+    /// Messages received since the last poll, packets that can not be
+    /// decoded are skipped so one bad sender does not block the others
+    pub fn poll(&mut self) -> Result<Vec<OscMessage>, Error> {
+        let mut messages = vec![];
+
+        loop {
+            match self.socket.recv(&mut self.buffer) {
+                Ok(length) => {
+                    if let Ok(mut packet) = OscMessage::decode(&self.buffer[..length]) {
+                        messages.append(&mut packet);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(messages),
+                Err(e) => return Err(error(e)),
+            }
+        }
+    }
+}
+
+/// Sends OSC packets to a single address
+pub struct OscClient {
+    socket: UdpSocket,
+}
+
+impl OscClient {
+    pub fn connect(address: SocketAddr) -> Result<Self, Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).map_err(error)?;
+        socket.connect(address).map_err(error)?;
+
+        Ok(OscClient { socket })
+    }
+
+    pub fn send(&self, message: &OscMessage) -> Result<(), Error> {
+        self.socket.send(&message.encode()).map_err(error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::OscArgument;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_send_and_poll() {
+        let mut server = OscServer::bind(0).unwrap();
+        let client = OscClient::connect(server.local_addr().unwrap()).unwrap();
+        let message = OscMessage::new("/sim/transport/play", vec![OscArgument::Int(1)]);
+
+        assert!(server.poll().unwrap().is_empty());
+
+        client.send(&message).unwrap();
+
+        let mut messages = vec![];
+        for _ in 0..100 {
+            messages.append(&mut server.poll().unwrap());
+
+            if !messages.is_empty() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        assert_eq!(messages, vec![message]);
+    }
+}
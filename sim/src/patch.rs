@@ -0,0 +1,613 @@
+use crate::engine::{Command, Snapshot};
+use crate::entity::{EntityType, Setting, SettingValue};
+use crate::grid::{Position, Rect};
+use crate::{Error, Sample};
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::Arc;
+
+/// Largest distance of an entity from the corner of a patch read from JSON,
+/// so pasting it next to the cursor stays far from the limits of a position
+const MAX_COORDINATE: i32 = 1 << 16;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Entity copied from the grid with its position relative to the copied region
+#[derive(Debug, Clone)]
+pub struct PatchEntity {
+    pub position: Position,
+    pub kind: EntityType,
+    pub settings: Vec<Setting>,
+    /// name of the sample played by the entity, see [`Patch::samples`]
+    pub sample: Option<String>,
+}
+
+/// Entities and settings of a region of the grid, exchanged as JSON text like
+/// `{"entities":[{"x":0,"y":0,"type":"trigger","settings":{"bpm":480.0}}]}`.
+/// Floats always have a decimal point, integers never do. Samplers refer to
+/// their sample by name with `"sample":"kick"`, exports embed the samples as
+/// base64 encoded WAVE files in `"samples":{"kick":"UklGR..."}`.
+#[derive(Debug, Clone, Default)]
+pub struct Patch {
+    pub entities: Vec<PatchEntity>,
+    /// samples the entities refer to by name
+    pub samples: Vec<Arc<Sample>>,
+}
+
+impl Patch {
+    /// Copy the entities inside a rectangle of the grid
+    pub fn from_snapshot(snapshot: &Snapshot, rect: &Rect) -> Self {
+        let mut patch = Patch::default();
+
+        for entity in snapshot.entities.iter() {
+            if !rect.intersect_position(entity.position) {
+                continue;
+            }
+
+            if let Some(sample) = &entity.sample {
+                patch.add_sample(sample);
+            }
+
+            patch.entities.push(PatchEntity {
+                position: entity.position.subtract(rect.position),
+                kind: entity.kind,
+                settings: entity.settings.clone(),
+                sample: entity.sample.as_ref().map(|sample| sample.name.clone()),
+            });
+        }
+
+        patch
+    }
+
+    /// Take the samples referred to but not embedded from the entities
+    /// of a snapshot playing a sample with the same name
+    pub fn find_samples(&mut self, snapshot: &Snapshot) {
+        for entity in snapshot.entities.iter() {
+            if let Some(sample) = &entity.sample {
+                if self
+                    .entities
+                    .iter()
+                    .any(|e| e.sample.as_deref() == Some(sample.name.as_str()))
+                {
+                    self.add_sample(sample);
+                }
+            }
+        }
+    }
+
+    fn add_sample(&mut self, sample: &Arc<Sample>) {
+        if self.get_sample(&sample.name).is_none() {
+            self.samples.push(sample.clone());
+        }
+    }
+
+    pub fn get_sample(&self, name: &str) -> Option<&Arc<Sample>> {
+        self.samples.iter().find(|sample| sample.name == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Commands placing the entities with their settings, relative to a position
+    pub fn commands(&self, position: Position) -> Vec<Command> {
+        let mut commands = vec![];
+
+        for entity in self.entities.iter() {
+            let position = position.add(entity.position);
+            commands.push(Command::AddEntity(position, entity.kind));
+
+            for setting in entity.settings.iter() {
+                commands.push(Command::UpdateSetting(position, setting.clone()));
+            }
+
+            if let Some(sample) = entity
+                .sample
+                .as_ref()
+                .and_then(|name| self.get_sample(name))
+            {
+                commands.push(Command::LoadSample(position, sample.clone()));
+            }
+        }
+
+        commands
+    }
+
+    /// Patch text with the samples referred to by name only, for the clipboard
+    pub fn to_json(&self) -> String {
+        self.json(false)
+    }
+
+    /// Patch text with the samples embedded, for saving it to a file
+    pub fn export(&self) -> String {
+        self.json(true)
+    }
+
+    fn json(&self, embed: bool) -> String {
+        let entities: Vec<String> = self
+            .entities
+            .iter()
+            .map(|entity| {
+                let settings: Vec<String> = entity
+                    .settings
+                    .iter()
+                    .map(|setting| {
+                        let value = match setting.value {
+                            SettingValue::Float(v) if v.is_finite() => format!("{:?}", v),
+                            SettingValue::Float(_) => String::from("0.0"),
+                            SettingValue::Integer(v) => v.to_string(),
+                            SettingValue::Boolean(v) => v.to_string(),
+                        };
+
+                        format!("{}:{}", quote(&setting.description), value)
+                    })
+                    .collect();
+
+                let sample = entity
+                    .sample
+                    .as_ref()
+                    .map(|name| format!(",\"sample\":{}", quote(name)))
+                    .unwrap_or_default();
+
+                format!(
+                    "{{\"x\":{},\"y\":{},\"type\":{}{},\"settings\":{{{}}}}}",
+                    entity.position.x,
+                    entity.position.y,
+                    quote(entity.kind.get_name()),
+                    sample,
+                    settings.join(",")
+                )
+            })
+            .collect();
+
+        if !embed || self.samples.is_empty() {
+            return format!("{{\"entities\":[{}]}}", entities.join(","));
+        }
+
+        let samples: Vec<String> = self
+            .samples
+            .iter()
+            .map(|sample| {
+                format!(
+                    "{}:\"{}\"",
+                    quote(&sample.name),
+                    base64_encode(&sample.to_wav())
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"entities\":[{}],\"samples\":{{{}}}}}",
+            entities.join(","),
+            samples.join(",")
+        )
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        let mut chars = text.chars().peekable();
+        let json = parse(&mut chars)?;
+
+        skip_whitespace(&mut chars);
+        if chars.peek().is_some() {
+            return Err(invalid("trailing characters"));
+        }
+
+        let entities = match json.get("entities") {
+            Some(Json::Array(entities)) => entities,
+            _ => return Err(invalid("missing entities")),
+        };
+
+        let entities = entities
+            .iter()
+            .map(|entity| {
+                let coordinate = |key| match entity.get(key) {
+                    Some(Json::Number(n)) => n
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|c| (-MAX_COORDINATE..=MAX_COORDINATE).contains(c))
+                        .ok_or_else(|| invalid(n)),
+                    _ => Err(invalid(&format!("missing {}", key))),
+                };
+
+                let kind = match entity.get("type") {
+                    Some(Json::String(name)) => EntityType::from_name(name)
+                        .ok_or_else(|| invalid(&format!("unknown type {}", name)))?,
+                    _ => return Err(invalid("missing type")),
+                };
+
+                let settings = match entity.get("settings") {
+                    Some(Json::Object(settings)) => settings
+                        .iter()
+                        .map(|(name, value)| setting(name, value))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None => vec![],
+                    _ => return Err(invalid("settings are not an object")),
+                };
+
+                let sample = match entity.get("sample") {
+                    Some(Json::String(name)) => Some(name.clone()),
+                    None => None,
+                    _ => return Err(invalid("sample is not a name")),
+                };
+
+                Ok(PatchEntity {
+                    position: Position::new(coordinate("x")?, coordinate("y")?),
+                    kind,
+                    settings,
+                    sample,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let samples = match json.get("samples") {
+            Some(Json::Object(samples)) => samples
+                .iter()
+                .map(|(name, data)| match data {
+                    Json::String(data) => {
+                        Ok(Arc::new(Sample::from_wav(name, &base64_decode(data)?)?))
+                    }
+                    _ => Err(invalid(&format!("sample {} is not a string", name))),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => vec![],
+            _ => return Err(invalid("samples are not an object")),
+        };
+
+        Ok(Patch { entities, samples })
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |group, (i, &byte)| group | (byte as u32) << (16 - i * 8));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(group >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn base64_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut group = 0u32;
+
+    for (i, c) in text.bytes().enumerate() {
+        let value = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .ok_or_else(|| invalid("sample is not base64"))?;
+
+        group = group << 6 | value as u32;
+
+        // every character after the first of a group of four completes a byte
+        if i % 4 != 0 {
+            bytes.push((group >> (6 - (i % 4) * 2)) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn setting(name: &str, value: &Json) -> Result<Setting, Error> {
+    let value = match value {
+        Json::Boolean(v) => SettingValue::Boolean(*v),
+        Json::Number(n) if n.contains(['.', 'e', 'E']) => {
+            SettingValue::Float(n.parse().map_err(|_| invalid(n))?)
+        }
+        Json::Number(n) => SettingValue::Integer(n.parse().map_err(|_| invalid(n))?),
+        _ => return Err(invalid(&format!("invalid value for {}", name))),
+    };
+
+    Ok(Setting::new(value, name.to_string()))
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Patch(reason.into())
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+/// The parts of JSON a patch is made of, numbers keep their text
+enum Json {
+    Null,
+    Boolean(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), Error> {
+    skip_whitespace(chars);
+
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        _ => Err(invalid(&format!("expected {}", expected))),
+    }
+}
+
+fn parse(chars: &mut Peekable<Chars>) -> Result<Json, Error> {
+    skip_whitespace(chars);
+
+    match chars.peek() {
+        Some('{') => {
+            chars.next();
+            let mut fields = vec![];
+
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Json::Object(fields));
+            }
+
+            loop {
+                skip_whitespace(chars);
+                let key = match parse(chars)? {
+                    Json::String(key) => key,
+                    _ => return Err(invalid("expected a key")),
+                };
+                expect(chars, ':')?;
+                fields.push((key, parse(chars)?));
+
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some('}') => return Ok(Json::Object(fields)),
+                    _ => return Err(invalid("expected , or }")),
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut values = vec![];
+
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Json::Array(values));
+            }
+
+            loop {
+                values.push(parse(chars)?);
+
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Json::Array(values)),
+                    _ => return Err(invalid("expected , or ]")),
+                }
+            }
+        }
+        Some('"') => {
+            chars.next();
+            let mut text = String::new();
+
+            loop {
+                match chars.next() {
+                    Some('"') => return Ok(Json::String(text)),
+                    Some('\\') => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some('r') => text.push('\r'),
+                        Some('b') => text.push('\u{8}'),
+                        Some('f') => text.push('\u{c}'),
+                        Some('u') => {
+                            let code: String = chars.by_ref().take(4).collect();
+                            let c = u32::from_str_radix(&code, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| invalid("invalid escape"))?;
+                            text.push(c);
+                        }
+                        Some(c) => text.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    },
+                    Some(c) => text.push(c),
+                    None => return Err(invalid("unterminated string")),
+                }
+            }
+        }
+        Some(c) if *c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+
+            while let Some(c) =
+                chars.next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+            {
+                number.push(c);
+            }
+
+            Ok(Json::Number(number))
+        }
+        Some(_) => {
+            let mut word = String::new();
+
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphabetic()) {
+                word.push(c);
+            }
+
+            match word.as_str() {
+                "true" => Ok(Json::Boolean(true)),
+                "false" => Ok(Json::Boolean(false)),
+                "null" => Ok(Json::Null),
+                _ => Err(invalid("unexpected character")),
+            }
+        }
+        None => Err(invalid("unexpected end")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EntitySnapshot;
+
+    fn entity(x: i32, y: i32, kind: EntityType, settings: Vec<Setting>) -> EntitySnapshot {
+        EntitySnapshot {
+            position: Position::new(x, y),
+            kind,
+            grid_display: None,
+            detail_display: None,
+            settings,
+            level: 0.0,
+            display_level: None,
+            sample: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let snapshot = Snapshot {
+            entities: vec![
+                entity(
+                    3,
+                    4,
+                    EntityType::Trigger,
+                    vec![
+                        Setting::new(SettingValue::Float(480.0), "bpm"),
+                        Setting::new(SettingValue::Boolean(true), "sync"),
+                    ],
+                ),
+                entity(
+                    4,
+                    5,
+                    EntityType::Euclid,
+                    vec![Setting::new(SettingValue::Integer(8), "steps")],
+                ),
+                entity(9, 9, EntityType::Step, vec![]),
+            ],
+            ..Snapshot::default()
+        };
+
+        let patch = Patch::from_snapshot(
+            &snapshot,
+            &Rect::from_corners(Position::new(4, 5), Position::new(3, 4)),
+        );
+        let json = patch.to_json();
+        assert_eq!(
+            json,
+            "{\"entities\":[\
+             {\"x\":0,\"y\":0,\"type\":\"trigger\",\"settings\":{\"bpm\":480.0,\"sync\":true}},\
+             {\"x\":1,\"y\":1,\"type\":\"euclid\",\"settings\":{\"steps\":8}}]}"
+        );
+
+        let parsed = Patch::from_json(&format!(" {} ", json.replace(',', " , "))).unwrap();
+        assert_eq!(parsed.to_json(), json);
+
+        let commands = parsed.commands(Position::new(10, 10));
+        assert_eq!(commands.len(), 5);
+        assert!(matches!(
+            &commands[3],
+            Command::AddEntity(p, EntityType::Euclid) if *p == Position::new(11, 11)
+        ));
+        assert!(matches!(
+            &commands[4],
+            Command::UpdateSetting(
+                _,
+                Setting {
+                    value: SettingValue::Integer(8),
+                    ..
+                }
+            )
+        ));
+    }
+
+    #[test]
+    fn test_samples() {
+        let kick = Arc::new(Sample::new("kick", 4, vec![vec![0.5, -0.25, 1.0]]));
+        let mut sampler = entity(1, 0, EntityType::Sampler, vec![]);
+        sampler.sample = Some(kick.clone());
+        let snapshot = Snapshot {
+            entities: vec![sampler],
+            ..Snapshot::default()
+        };
+
+        let patch = Patch::from_snapshot(
+            &snapshot,
+            &Rect::from_corners(Position::new(0, 0), Position::new(1, 0)),
+        );
+
+        // the clipboard refers to the sample, pasting finds it on the grid
+        let json = patch.to_json();
+        assert_eq!(
+            json,
+            "{\"entities\":[{\"x\":1,\"y\":0,\"type\":\"sampler\",\"sample\":\"kick\",\"settings\":{}}]}"
+        );
+        let mut parsed = Patch::from_json(&json).unwrap();
+        assert_eq!(parsed.to_json(), json);
+        assert!(parsed.samples.is_empty());
+        assert_eq!(parsed.commands(Position::origin()).len(), 1);
+
+        parsed.find_samples(&snapshot);
+        assert!(matches!(
+            &parsed.commands(Position::origin())[1],
+            Command::LoadSample(p, sample) if *p == Position::new(1, 0) && Arc::ptr_eq(sample, &kick)
+        ));
+
+        // exports embed the sample
+        let exported = Patch::from_json(&patch.export()).unwrap();
+        assert_eq!(exported.to_json(), json);
+        assert_eq!(exported.get_sample("kick"), Some(&kick));
+
+        for length in 0..6 {
+            let bytes: Vec<u8> = (0..length).map(|i| 250 - i * 47).collect();
+            assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(base64_encode(b"sim"), "c2lt");
+        assert_eq!(base64_encode(b"si"), "c2k=");
+        assert!(base64_decode("c2*t").is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Patch::from_json("").is_err());
+        assert!(Patch::from_json("{\"entities\":[}").is_err());
+        assert!(Patch::from_json("{\"entities\":[{\"x\":0,\"y\":0,\"type\":\"lfo\"}]}").is_err());
+        assert!(Patch::from_json("{\"entities\":[{\"x\":0,\"type\":\"step\"}]}").is_err());
+        assert!(Patch::from_json("{\"entities\":[]} x").is_err());
+        assert!(
+            Patch::from_json("{\"entities\":[{\"x\":70000,\"y\":0,\"type\":\"step\"}]}").is_err()
+        );
+        assert!(
+            Patch::from_json("{\"entities\":[{\"x\":0,\"y\":-2147483648,\"type\":\"step\"}]}")
+                .is_err()
+        );
+        assert!(Patch::from_json("{\"entities\":[{\"x\":0,\"y\":0,\"type\":\"step\"}]}").is_ok());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::osc::{command_from_osc, gate_message, OscClient, OscServer};
+use crate::sync::{clock_micros, TempoSync, Timeline};
+use crate::{Command, Controller, Error};
+
+/// Microseconds between alignments of the engine with an unchanged timeline,
+/// so the engine does not drift away from the local clock
+const SYNC_INTERVAL: i64 = 1_000_000;
+
+/// Connects a [`Controller`] to the network, hosts call [`Remote::update`]
+/// from their user interface loop along with [`Controller::update`]
+#[derive(Default)]
+pub struct Remote {
+    /// receives the OSC messages editing the grid
+    server: Option<OscServer>,
+    /// receives the OSC messages for the gates of watched entities
+    client: Option<OscClient>,
+    /// tempo and beat phase shared with other peers
+    sync: Option<TempoSync>,
+    /// timeline last sent to the engine and when it was sent
+    synced: Option<(Timeline, i64)>,
+}
+
+impl Remote {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_osc_server(mut self, server: OscServer) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    pub fn with_osc_client(mut self, client: OscClient) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Follow the tempo of the peers, tempo changes received over OSC
+    /// are made for all peers
+    pub fn with_tempo_sync(mut self, sync: TempoSync) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    /// Send the commands received over OSC to the engine, the gates of
+    /// watched entities to the OSC client and align the engine with the
+    /// peers. Messages that are not valid for the grid are skipped,
+    /// the first one is returned once the others were sent.
+    pub fn update(&mut self, controller: &mut Controller) -> Result<(), Error> {
+        let now = clock_micros();
+        let mut invalid = None;
+
+        if let Some(server) = self.server.as_mut() {
+            for message in server.poll()? {
+                match (
+                    command_from_osc(&message, controller.get_snapshot()),
+                    self.sync.as_mut(),
+                ) {
+                    (Ok(Command::SetTempo(bpm)), Some(sync)) => sync.set_bpm(bpm, now),
+                    (Ok(command), _) => controller.send(command)?,
+                    (Err(e), _) => {
+                        invalid.get_or_insert(e);
+                    }
+                }
+            }
+        }
+
+        if let Some(client) = self.client.as_ref() {
+            for (_, position, gate) in controller.receive_gates() {
+                client.send(&gate_message(position, gate))?;
+            }
+        }
+
+        if let Some(sync) = self.sync.as_mut() {
+            sync.poll(now)?;
+
+            let timeline = sync.get_timeline();
+            let due = self
+                .synced
+                .is_none_or(|(synced, time)| synced != timeline || now - time >= SYNC_INTERVAL);
+
+            // the time of the last snapshot stands in for the time of the engine now
+            if due {
+                controller.send(sync.sync_command(now, controller.get_snapshot().time))?;
+                self.synced = Some((timeline, now));
+            }
+        }
+
+        invalid.map_or(Ok(()), Err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::{OscArgument, OscMessage};
+    use crate::{Engine, EntityType, Position};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Run a step until it succeeds, packets can take a moment to arrive
+    fn retry(mut step: impl FnMut() -> bool) {
+        for _ in 0..200 {
+            if step() {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        panic!("no packets arrived");
+    }
+
+    #[test]
+    fn test_osc_round_trip() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let server = OscServer::bind(0).unwrap();
+        let sender = OscClient::connect(server.local_addr().unwrap()).unwrap();
+        let mut receiver = OscServer::bind(0).unwrap();
+        let client = OscClient::connect(receiver.local_addr().unwrap()).unwrap();
+        let mut remote = Remote::new()
+            .with_osc_server(server)
+            .with_osc_client(client);
+
+        let add = OscMessage::new(
+            "/sim/cell/0/0/add",
+            vec![OscArgument::String("trigger".into())],
+        );
+        let watch = OscMessage::new("/sim/cell/0/0/watch", vec![OscArgument::Int(1)]);
+        sender.send(&add).unwrap();
+        sender.send(&watch).unwrap();
+
+        retry(|| {
+            remote.update(&mut controller).unwrap();
+            engine.sample();
+            controller.update();
+
+            let snapshot = controller.get_snapshot();
+            snapshot.watched.contains(&Position::origin())
+                && snapshot
+                    .get_entity(Position::origin())
+                    .is_some_and(|entity| entity.kind == EntityType::Trigger)
+        });
+
+        let mut messages = vec![];
+        retry(|| {
+            engine.sample();
+            remote.update(&mut controller).unwrap();
+            messages.append(&mut receiver.poll().unwrap());
+            !messages.is_empty()
+        });
+
+        assert_eq!(messages[0], gate_message(Position::origin(), true));
+    }
+
+    #[test]
+    fn test_tempo_sync() {
+        let (mut engine, mut controller) = Engine::new(4, 4, 8);
+        let server = OscServer::bind(0).unwrap();
+        let sender = OscClient::connect(server.local_addr().unwrap()).unwrap();
+        let mut sync = TempoSync::bind(0, 120.0).unwrap();
+        let mut peer = TempoSync::bind(0, 120.0).unwrap();
+        sync.add_peer(peer.local_addr().unwrap());
+        peer.add_peer(sync.local_addr().unwrap());
+        let mut remote = Remote::new().with_osc_server(server).with_tempo_sync(sync);
+
+        // the engine follows the tempo of the peer
+        peer.set_bpm(150.0, clock_micros());
+        retry(|| {
+            peer.poll(clock_micros()).unwrap();
+            remote.update(&mut controller).unwrap();
+            engine.sample();
+            controller.update();
+            controller.get_snapshot().clock.bpm == 150.0
+        });
+
+        // and the peer follows tempo changes received over OSC
+        let bpm = OscMessage::new("/sim/transport/bpm", vec![OscArgument::Float(100.0)]);
+        sender.send(&bpm).unwrap();
+        retry(|| {
+            peer.poll(clock_micros()).unwrap();
+            remote.update(&mut controller).unwrap();
+            engine.sample();
+            controller.update();
+            peer.get_timeline().bpm == 100.0 && controller.get_snapshot().clock.bpm == 100.0
+        });
+    }
+}
@@ -0,0 +1,295 @@
+use crate::Error;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Audio data loaded from a file, stored per channel as f32 between -1.0 and 1.0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// name used to refer to the sample from a patch
+    pub name: String,
+    pub sample_rate: usize,
+    pub channels: Vec<Vec<f32>>,
+}
+
+impl Sample {
+    pub fn new(name: &str, sample_rate: usize, channels: Vec<Vec<f32>>) -> Self {
+        Sample {
+            name: name.into(),
+            sample_rate,
+            channels,
+        }
+    }
+
+    /// Decode a RIFF WAVE file containing 16 or 24 bit PCM or 32 bit float data
+    pub fn from_wav(name: &str, bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(Error::Wav("not a RIFF WAVE file".into()));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut offset: usize = 12;
+
+        while offset.checked_add(8).is_some_and(|end| end <= bytes.len()) {
+            let id = &bytes[offset..offset + 4];
+            let size = read_u32(bytes, offset + 4) as usize;
+            let start = offset + 8;
+            let end = start.saturating_add(size).min(bytes.len());
+
+            match id {
+                b"fmt " if end - start >= 16 => {
+                    let mut tag = read_u16(bytes, start);
+
+                    // the extensible format stores the actual format in the sub format guid
+                    if tag == FORMAT_EXTENSIBLE && end - start >= 26 {
+                        tag = read_u16(bytes, start + 24);
+                    }
+
+                    format = Some((
+                        tag,
+                        read_u16(bytes, start + 2) as usize,
+                        read_u32(bytes, start + 4) as usize,
+                        read_u16(bytes, start + 14),
+                    ));
+                }
+                b"data" => data = Some(&bytes[start..end]),
+                _ => (),
+            }
+
+            // chunks are padded to an even amount of bytes, a size past the
+            // end of the address space ends the file
+            match start
+                .checked_add(size)
+                .and_then(|end| end.checked_add(size % 2))
+            {
+                Some(next) => offset = next,
+                None => break,
+            }
+        }
+
+        let (tag, channel_count, sample_rate, bits) =
+            format.ok_or_else(|| Error::Wav("missing fmt chunk".into()))?;
+        let data = data.ok_or_else(|| Error::Wav("missing data chunk".into()))?;
+
+        if sample_rate == 0 {
+            return Err(Error::Wav("sample rate is zero".into()));
+        }
+
+        if channel_count == 0 || channel_count > 2 {
+            return Err(Error::Wav(format!(
+                "unsupported channel count: {}",
+                channel_count
+            )));
+        }
+
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+            (FORMAT_PCM, 24) => |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0,
+            (FORMAT_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => {
+                return Err(Error::Wav(format!(
+                    "unsupported format {} with {} bits",
+                    tag, bits
+                )))
+            }
+        };
+
+        let width = bits as usize / 8;
+        let mut channels = vec![vec![]; channel_count];
+
+        for frame in data.chunks_exact(width * channel_count) {
+            for (channel, bytes) in channels.iter_mut().zip(frame.chunks_exact(width)) {
+                channel.push(decode(bytes));
+            }
+        }
+
+        Ok(Sample::new(name, sample_rate, channels))
+    }
+
+    /// Encode as a 32 bit float WAVE file, used for embedding the sample in exports
+    pub fn to_wav(&self) -> Vec<u8> {
+        let channel_count = self.channels.len();
+        let frames = self.len();
+        let data_size = frames * channel_count * 4;
+        let mut bytes = Vec::with_capacity(44 + data_size);
+
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_size as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&FORMAT_FLOAT.to_le_bytes());
+        bytes.extend_from_slice(&(channel_count as u16).to_le_bytes());
+        bytes.extend_from_slice(&(self.sample_rate as u32).to_le_bytes());
+        bytes.extend_from_slice(&((self.sample_rate * channel_count * 4) as u32).to_le_bytes());
+        bytes.extend_from_slice(&((channel_count * 4) as u16).to_le_bytes());
+        bytes.extend_from_slice(&32u16.to_le_bytes());
+
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data_size as u32).to_le_bytes());
+
+        for i in 0..frames {
+            for channel in &self.channels {
+                bytes.extend_from_slice(&channel[i].to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Create a copy of the sample at a new sample rate using linear interpolation
+    pub fn resample(&self, sample_rate: usize) -> Self {
+        if sample_rate == self.sample_rate {
+            return self.clone();
+        }
+
+        // there is nothing to interpolate without a rate to convert from or to
+        if sample_rate == 0 || self.sample_rate == 0 {
+            return Sample::new(&self.name, sample_rate, vec![vec![]; self.channels.len()]);
+        }
+
+        let ratio = self.sample_rate as f32 / sample_rate as f32;
+        let frames = (self.len() as f32 / ratio).floor() as usize;
+
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| {
+                (0..frames)
+                    .map(|i| interpolate(channel, i as f32 * ratio))
+                    .collect()
+            })
+            .collect();
+
+        Sample::new(&self.name, sample_rate, channels)
+    }
+
+    /// Amount of frames in the sample
+    pub fn len(&self) -> usize {
+        self.channels.first().map(|c| c.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read a value at a fractional frame position,
+    /// mono samples return the same data for every channel
+    pub fn get(&self, channel: usize, position: f32) -> f32 {
+        match self.channels.get(channel).or_else(|| self.channels.last()) {
+            Some(channel) => interpolate(channel, position),
+            None => 0.0,
+        }
+    }
+}
+
+fn interpolate(samples: &[f32], position: f32) -> f32 {
+    let index = position.floor() as usize;
+    let fraction = position - index as f32;
+
+    match (samples.get(index), samples.get(index + 1)) {
+        (Some(a), Some(b)) => a + (b - a) * fraction,
+        (Some(a), None) => *a,
+        _ => 0.0,
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(tag: u16, channels: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        wav_with_rate(tag, channels, bits, 44100, data)
+    }
+
+    fn wav_with_rate(tag: u16, channels: u16, bits: u16, rate: u32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&tag.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.extend_from_slice(&bits.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn test_from_wav_pcm_16() {
+        let data = [0x00, 0x40, 0x00, 0xC0, 0x00, 0x00, 0xFF, 0x7F];
+        let sample = Sample::from_wav("kick", &wav(FORMAT_PCM, 2, 16, &data)).unwrap();
+
+        assert_eq!(sample.name, "kick");
+        assert_eq!(sample.sample_rate, 44100);
+        assert_eq!(sample.channels, vec![vec![0.5, 0.0], vec![-0.5, 0.9999695]]);
+    }
+
+    #[test]
+    fn test_from_wav_pcm_24() {
+        let data = [0x00, 0x00, 0x40, 0x00, 0x00, 0xC0];
+        let sample = Sample::from_wav("snare", &wav(FORMAT_PCM, 1, 24, &data)).unwrap();
+
+        assert_eq!(sample.channels, vec![vec![0.5, -0.5]]);
+    }
+
+    #[test]
+    fn test_wav_float_round_trip() {
+        let sample = Sample::new("hat", 48000, vec![vec![0.25, -1.0], vec![0.75, 1.0]]);
+        let decoded = Sample::from_wav("hat", &sample.to_wav()).unwrap();
+
+        assert_eq!(decoded, sample);
+    }
+
+    #[test]
+    fn test_from_wav_unsupported() {
+        assert!(Sample::from_wav("x", b"not a wave file").is_err());
+        assert!(Sample::from_wav("x", &wav(FORMAT_PCM, 1, 8, &[0, 1])).is_err());
+        assert!(Sample::from_wav("x", &wav(FORMAT_PCM, 3, 16, &[0; 6])).is_err());
+        assert!(Sample::from_wav("x", &wav_with_rate(FORMAT_PCM, 1, 16, 0, &[0; 2])).is_err());
+
+        // a chunk size reaching past the end of the file
+        let mut bytes = wav(FORMAT_PCM, 1, 16, &[0; 2]);
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Sample::from_wav("x", &bytes).is_ok());
+    }
+
+    #[test]
+    fn test_resample() {
+        let sample = Sample::new("s", 24000, vec![vec![0.0, 1.0, 0.0, -1.0]]);
+        let resampled = sample.resample(48000);
+
+        assert_eq!(resampled.sample_rate, 48000);
+        assert_eq!(
+            resampled.channels,
+            vec![vec![0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]]
+        );
+
+        let silent = Sample::new("s", 0, vec![vec![1.0; 4]]).resample(48000);
+        assert_eq!(silent.channels, vec![Vec::<f32>::new()]);
+        assert!(sample.resample(0).is_empty());
+    }
+}
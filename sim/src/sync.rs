@@ -0,0 +1,429 @@
+use crate::engine::Command;
+use crate::osc::{OscArgument, OscMessage};
+use crate::Error;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Microseconds between announcements of the timeline and clock measurements
+const ANNOUNCE_INTERVAL: i64 = 100_000;
+/// Peers that have not been heard from for this long are dropped
+const PEER_TIMEOUT: i64 = 2_000_000;
+
+/// Microseconds since the unix epoch, used as the local clock of a peer
+pub fn clock_micros() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as i64)
+        .unwrap_or(0)
+}
+
+/// Tempo shared by all peers, beat 0 falls on `origin` in local clock time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeline {
+    pub bpm: f64,
+    pub origin: i64,
+    /// time of the last tempo change in local clock time,
+    /// 0 for a timeline nobody changed yet
+    pub changed: i64,
+    /// time of the last tempo change on the clock of its author,
+    /// tells copies of the same change apart from other changes
+    pub stamp: i64,
+    /// peer that made the last change
+    pub author: u64,
+}
+
+impl Timeline {
+    pub fn new(bpm: f64, origin: i64, author: u64) -> Self {
+        Timeline {
+            bpm: bpm.max(1.0),
+            origin,
+            changed: 0,
+            stamp: 0,
+            author,
+        }
+    }
+
+    pub fn beat_at(&self, time: i64) -> f64 {
+        (time - self.origin) as f64 * self.bpm / 60_000_000.0
+    }
+
+    /// Change the tempo while keeping the beat at `now`
+    pub fn with_bpm(&self, bpm: f64, now: i64, author: u64) -> Self {
+        let bpm = bpm.max(1.0);
+        let beat = self.beat_at(now);
+
+        Timeline {
+            bpm,
+            origin: now - (beat * 60_000_000.0 / bpm) as i64,
+            changed: now,
+            stamp: now,
+            author,
+        }
+    }
+
+    /// The most recent change wins, between unchanged timelines the oldest session wins.
+    /// Copies of the same change never replace each other, so measurement errors
+    /// of the clock offsets do not bounce between peers.
+    fn replaces(&self, other: &Timeline) -> bool {
+        (self.stamp, self.author) != (other.stamp, other.author)
+            && (self.changed, -self.origin, self.author)
+                > (other.changed, -other.origin, other.author)
+    }
+
+    /// Same timeline on a clock that is `offset` microseconds ahead
+    fn shift(&self, offset: i64) -> Self {
+        Timeline {
+            origin: self.origin + offset,
+            changed: if self.changed == 0 {
+                0
+            } else {
+                self.changed + offset
+            },
+            ..*self
+        }
+    }
+}
+
+struct Peer {
+    id: u64,
+    address: SocketAddr,
+    /// microseconds the clock of the peer is ahead of the local clock
+    offset: i64,
+    /// round trip of the measurement the offset was taken from
+    round_trip: Option<i64>,
+    last_seen: i64,
+}
+
+/// Joins the peers listening on a range of UDP ports to agree on tempo and beat phase,
+/// in the spirit of Ableton Link. Clock offsets between peers are measured with
+/// ping messages and messages are encoded as OSC.
+pub struct TempoSync {
+    socket: UdpSocket,
+    id: u64,
+    /// address and ports the timeline is announced to when joined
+    broadcast: Option<(Ipv4Addr, Range<u16>)>,
+    /// peers added by address, announced to as well
+    addresses: Vec<SocketAddr>,
+    timeline: Timeline,
+    peers: Vec<Peer>,
+    last_announce: Option<i64>,
+    buffer: Vec<u8>,
+}
+
+impl TempoSync {
+    /// Listen on the first free port in `ports` and announce the timeline to
+    /// all ports in the range on the broadcast address, use the loopback
+    /// address to only sync instances on the same machine
+    pub fn join(broadcast: Ipv4Addr, ports: Range<u16>, bpm: f64) -> Result<Self, Error> {
+        let bind = if broadcast.is_loopback() {
+            Ipv4Addr::LOCALHOST
+        } else {
+            Ipv4Addr::UNSPECIFIED
+        };
+
+        let socket = ports
+            .clone()
+            .find_map(|port| UdpSocket::bind((bind, port)).ok())
+            .ok_or_else(|| Error::Sync(format!("no free port in {:?}", ports)))?;
+
+        socket.set_broadcast(true).map_err(error)?;
+
+        let mut sync = Self::with_socket(socket, bpm)?;
+        sync.broadcast = Some((broadcast, ports));
+        Ok(sync)
+    }
+
+    /// Listen on a port of the loopback interface, port 0 picks a free port,
+    /// and only announce the timeline to the peers added with [`TempoSync::add_peer`]
+    pub fn bind(port: u16, bpm: f64) -> Result<Self, Error> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, port)).map_err(error)?;
+        Self::with_socket(socket, bpm)
+    }
+
+    fn with_socket(socket: UdpSocket, bpm: f64) -> Result<Self, Error> {
+        socket.set_nonblocking(true).map_err(error)?;
+
+        let now = clock_micros();
+        let port = socket.local_addr().map_err(error)?.port() as u64;
+        // unique enough to tell peers apart, processes on a machine use different ports
+        let id = (now as u64).rotate_left(16) ^ port ^ ((std::process::id() as u64) << 32);
+
+        Ok(TempoSync {
+            socket,
+            id,
+            broadcast: None,
+            addresses: vec![],
+            timeline: Timeline::new(bpm, now, id),
+            peers: vec![],
+            last_announce: None,
+            buffer: vec![0; 1024],
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr().map_err(error)
+    }
+
+    /// Announce the timeline to a peer outside the joined port range
+    pub fn add_peer(&mut self, address: SocketAddr) {
+        self.addresses.push(address);
+        self.last_announce = None;
+    }
+
+    pub fn get_timeline(&self) -> Timeline {
+        self.timeline
+    }
+
+    pub fn get_peer_count(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Change the tempo for all peers
+    pub fn set_bpm(&mut self, bpm: f64, now: i64) {
+        self.timeline = self.timeline.with_bpm(bpm, now, self.id);
+        self.last_announce = None;
+    }
+
+    /// Command aligning an engine with the timeline, `time` is the time in samples
+    /// of the engine at `now`, for example the time of the last snapshot
+    pub fn sync_command(&self, now: i64, time: u64) -> Command {
+        Command::SyncTempo(self.timeline.bpm, self.timeline.beat_at(now), time)
+    }
+
+    /// Handle the messages received since the last poll and announce the timeline
+    /// when it is due, `now` is the local clock from [`clock_micros`]
+    pub fn poll(&mut self, now: i64) -> Result<(), Error> {
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((length, address)) => {
+                    if let Ok(messages) = OscMessage::decode(&self.buffer[..length]) {
+                        for message in messages {
+                            self.handle(&message, address, now);
+                        }
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // errors for packets sent to ports nobody listens on
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => (),
+                Err(e) => return Err(error(e)),
+            }
+        }
+
+        self.peers
+            .retain(|peer| now - peer.last_seen < PEER_TIMEOUT);
+
+        if self
+            .last_announce
+            .is_none_or(|last| now - last >= ANNOUNCE_INTERVAL)
+        {
+            self.announce(now);
+            self.last_announce = Some(now);
+        }
+
+        Ok(())
+    }
+
+    fn announce(&self, now: i64) {
+        let timeline = OscMessage::new(
+            "/sim/tempo/timeline",
+            vec![
+                OscArgument::Long(self.id as i64),
+                OscArgument::Double(self.timeline.bpm),
+                OscArgument::Long(self.timeline.origin),
+                OscArgument::Long(self.timeline.changed),
+                OscArgument::Long(self.timeline.stamp),
+                OscArgument::Long(self.timeline.author as i64),
+            ],
+        )
+        .encode();
+
+        let own_port = self.socket.local_addr().map(|a| a.port()).ok();
+
+        // sending fails for ports without a listener on some systems
+        if let Some((broadcast, ports)) = self.broadcast.as_ref() {
+            for port in ports.clone().filter(|port| Some(*port) != own_port) {
+                let _ = self.socket.send_to(&timeline, (*broadcast, port));
+            }
+        }
+
+        for address in self.addresses.iter() {
+            let _ = self.socket.send_to(&timeline, address);
+        }
+
+        let ping = OscMessage::new(
+            "/sim/tempo/ping",
+            vec![OscArgument::Long(self.id as i64), OscArgument::Long(now)],
+        )
+        .encode();
+
+        for peer in self.peers.iter() {
+            let _ = self.socket.send_to(&ping, peer.address);
+        }
+    }
+
+    fn handle(&mut self, message: &OscMessage, address: SocketAddr, now: i64) {
+        use OscArgument::{Double, Long};
+
+        let id = match message.arguments.first() {
+            Some(Long(id)) if *id as u64 != self.id => *id as u64,
+            _ => return,
+        };
+
+        let index = self.peer(id, address, now);
+        let peer = &mut self.peers[index];
+
+        match (message.address.as_str(), &message.arguments[1..]) {
+            (
+                "/sim/tempo/timeline",
+                [Double(bpm), Long(origin), Long(changed), Long(stamp), Long(author)],
+            ) => {
+                let timeline = Timeline {
+                    bpm: *bpm,
+                    origin: *origin,
+                    changed: *changed,
+                    stamp: *stamp,
+                    author: *author as u64,
+                }
+                .shift(-peer.offset);
+
+                if timeline.replaces(&self.timeline) {
+                    self.timeline = timeline;
+                }
+            }
+            ("/sim/tempo/ping", [Long(sent)]) => {
+                let pong = OscMessage::new(
+                    "/sim/tempo/pong",
+                    vec![Long(self.id as i64), Long(*sent), Long(now)],
+                );
+                let _ = self.socket.send_to(&pong.encode(), address);
+            }
+            ("/sim/tempo/pong", [Long(sent), Long(received)]) => {
+                let round_trip = now - sent;
+
+                // the measurement with the shortest round trip is the most accurate
+                if peer.round_trip.is_none_or(|best| round_trip <= best) {
+                    peer.round_trip = Some(round_trip);
+                    peer.offset = received - (sent + now) / 2;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Index of a peer, adding peers the first time they are heard from
+    fn peer(&mut self, id: u64, address: SocketAddr, now: i64) -> usize {
+        let index = match self.peers.iter().position(|peer| peer.id == id) {
+            Some(index) => index,
+            None => {
+                self.peers.push(Peer {
+                    id,
+                    address,
+                    offset: 0,
+                    round_trip: None,
+                    last_seen: now,
+                });
+                self.peers.len() - 1
+            }
+        };
+
+        self.peers[index].address = address;
+        self.peers[index].last_seen = now;
+        index
+    }
+}
+
+fn error(e: std::io::Error) -> Error {
+    Error::Sync(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_timeline() {
+        let timeline = Timeline::new(120.0, 1_000_000, 1);
+        assert_eq!(timeline.beat_at(3_000_000), 4.0);
+
+        let faster = timeline.with_bpm(240.0, 3_000_000, 2);
+        assert_eq!(faster.beat_at(3_000_000), 4.0);
+        assert_eq!(faster.beat_at(4_000_000), 8.0);
+        assert!(faster.replaces(&timeline));
+
+        // without changes the session that started first is kept
+        let newer = Timeline::new(90.0, 2_000_000, 3);
+        assert!(timeline.replaces(&newer));
+        assert!(!newer.replaces(&timeline));
+
+        assert_eq!(timeline.shift(500).origin, 1_000_500);
+        assert_eq!(timeline.shift(500).changed, 0);
+        assert!(!timeline.shift(-500).replaces(&timeline));
+        assert_eq!(faster.shift(500).changed, 3_000_500);
+        assert!(!faster.shift(-500).replaces(&faster));
+    }
+
+    #[test]
+    fn test_changes_on_other_clocks() {
+        let timeline = Timeline::new(120.0, 0, 1);
+        let local = timeline.with_bpm(100.0, 5_000_000, 1);
+
+        // changed a second before the local change on a clock that is two seconds ahead
+        let remote = timeline.with_bpm(140.0, 6_000_000, 2).shift(-2_000_000);
+
+        assert_eq!(remote.changed, 4_000_000);
+        assert!(local.replaces(&remote));
+        assert!(!remote.replaces(&local));
+    }
+
+    /// Poll the peers until they are done, packets and clock measurements take a moment
+    fn poll_until(peers: &mut [&mut TempoSync], done: impl Fn(&[&mut TempoSync]) -> bool) {
+        let start = Instant::now();
+
+        while start.elapsed() < Duration::from_secs(10) {
+            for peer in peers.iter_mut() {
+                peer.poll(clock_micros()).unwrap();
+            }
+
+            if done(peers) {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        panic!("peers did not agree");
+    }
+
+    #[test]
+    fn test_two_peers_on_localhost() {
+        let mut first = TempoSync::bind(0, 90.0).unwrap();
+        let mut second = TempoSync::bind(0, 90.0).unwrap();
+        first.add_peer(second.local_addr().unwrap());
+        second.add_peer(first.local_addr().unwrap());
+
+        // the second peer follows the change of the first
+        first.set_bpm(120.0, clock_micros());
+        poll_until(&mut [&mut first, &mut second], |peers| {
+            peers[1].get_timeline().bpm == 120.0 && peers[1].get_peer_count() == 1
+        });
+
+        first.set_bpm(140.0, clock_micros());
+        poll_until(&mut [&mut first, &mut second], |peers| {
+            peers[1].get_timeline().bpm == 140.0
+        });
+
+        // both peers agree on the beat, up to the accuracy of the clock measurement
+        let now = clock_micros();
+        let difference = first.get_timeline().beat_at(now) - second.get_timeline().beat_at(now);
+        assert!(difference.abs() < 0.01);
+
+        assert!(matches!(
+            second.sync_command(now, 4800),
+            Command::SyncTempo(bpm, _, 4800) if bpm == 140.0
+        ));
+    }
+}
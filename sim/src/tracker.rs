@@ -0,0 +1,169 @@
+use screech::traits::Tracker;
+use screech::{Input, Message, Output, Signal};
+use std::collections::HashMap;
+
+/// Tracker growing with the amount of sources like screech's `DynamicTracker`,
+/// clearing a source also forgets its inputs so removed entities leave nothing behind
+pub struct SignalTracker {
+    next_id: usize,
+    buffer_size: usize,
+    inputs: HashMap<usize, HashMap<&'static str, Vec<Output>>>,
+    signals: HashMap<usize, HashMap<&'static str, Signal>>,
+    messages: HashMap<usize, Vec<Message<()>>>,
+}
+
+impl SignalTracker {
+    pub fn new(buffer_size: usize) -> Self {
+        SignalTracker {
+            next_id: 0,
+            buffer_size,
+            inputs: HashMap::new(),
+            signals: HashMap::new(),
+            messages: HashMap::new(),
+        }
+    }
+
+    /// Amount of sources with signals or inputs
+    #[cfg(test)]
+    pub fn source_count(&self) -> usize {
+        let mut ids: Vec<&usize> = self.signals.keys().chain(self.inputs.keys()).collect();
+        ids.sort();
+        ids.dedup();
+        ids.len()
+    }
+}
+
+impl Tracker for SignalTracker {
+    fn get_buffer_size(&self) -> &usize {
+        &self.buffer_size
+    }
+
+    fn resize_buffers(&mut self, buffer_size: usize) {
+        self.buffer_size = buffer_size;
+
+        for signal in self
+            .signals
+            .values_mut()
+            .flat_map(|source| source.values_mut())
+        {
+            signal.samples.resize(buffer_size, 0.0);
+        }
+    }
+
+    fn create_source_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn clear_source(&mut self, id: usize) {
+        self.signals.remove(&id);
+        self.inputs.remove(&id);
+        self.messages.remove(&id);
+    }
+
+    fn get_sources(&self, id: &usize) -> Vec<usize> {
+        self.inputs
+            .get(id)
+            .iter()
+            .flat_map(|inputs| inputs.values().flatten())
+            .map(|output| *output.get_source_id())
+            .collect()
+    }
+
+    fn get_output(&self, output: &Output) -> Option<&Signal> {
+        self.signals
+            .get(output.get_source_id())
+            .and_then(|signals| signals.get(output.get_signal_id()))
+    }
+
+    fn get_mut_output(&mut self, output: &Output) -> Option<&mut Signal> {
+        self.signals
+            .get_mut(output.get_source_id())
+            .and_then(|signals| signals.get_mut(output.get_signal_id()))
+    }
+
+    fn init_output(&mut self, output: &Output) {
+        self.signals
+            .entry(*output.get_source_id())
+            .or_default()
+            .insert(output.get_signal_id(), Signal::empty(self.buffer_size));
+    }
+
+    fn init_input(&mut self, input: &Input) {
+        self.inputs
+            .entry(*input.get_source_id())
+            .or_default()
+            .insert(input.get_signal_id(), vec![]);
+    }
+
+    fn get_input(&self, input: &Input) -> Option<&[Output]> {
+        self.inputs
+            .get(input.get_source_id())
+            .and_then(|inputs| inputs.get(input.get_signal_id()))
+            .map(|outputs| outputs.as_slice())
+    }
+
+    fn connect_signal(&mut self, output: &Output, input: &Input) {
+        if let Some(outputs) = self
+            .inputs
+            .get_mut(input.get_source_id())
+            .and_then(|inputs| inputs.get_mut(input.get_signal_id()))
+        {
+            outputs.push(*output);
+        }
+    }
+
+    fn clear_connection(&mut self, output: &Output, input: &Input) {
+        if let Some(outputs) = self
+            .inputs
+            .get_mut(input.get_source_id())
+            .and_then(|inputs| inputs.get_mut(input.get_signal_id()))
+        {
+            outputs.retain(|o| o != output);
+        }
+    }
+
+    fn send_message(&mut self, id: &usize, message: Message<()>) {
+        if let Some(messages) = self.messages.get_mut(id) {
+            messages.push(message);
+        }
+    }
+
+    fn get_messages(&self, id: &usize) -> Option<&[Message<()>]> {
+        self.messages.get(id).map(|messages| messages.as_slice())
+    }
+
+    fn clear_messages(&mut self) {
+        self.messages.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_source() {
+        let mut tracker = SignalTracker::new(4);
+        let first = tracker.create_source_id();
+        let second = tracker.create_source_id();
+        let output = Output::new(first, "output");
+        let input = Input::new(second, "input");
+
+        tracker.init_output(&output);
+        tracker.init_input(&input);
+        tracker.connect_signal(&output, &input);
+        assert_eq!(tracker.get_sources(&second), vec![first]);
+        assert_eq!(tracker.get_output(&output).unwrap().samples.len(), 4);
+        assert_eq!(tracker.source_count(), 2);
+
+        tracker.clear_source(second);
+        assert!(tracker.get_input(&input).is_none());
+        assert_eq!(tracker.source_count(), 1);
+
+        tracker.clear_source(first);
+        assert!(tracker.get_output(&output).is_none());
+        assert_eq!(tracker.source_count(), 0);
+    }
+}
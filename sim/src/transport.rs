@@ -0,0 +1,74 @@
+/// Tempo and beat position at the start of a buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clock {
+    pub bpm: f64,
+    pub beat: f64,
+}
+
+impl Clock {
+    pub fn new(bpm: f64) -> Self {
+        Clock { bpm, beat: 0.0 }
+    }
+
+    /// Beat position a number of samples after the start of the buffer
+    pub fn beat_at(&self, offset: usize, sample_rate: usize) -> f64 {
+        self.beat + offset as f64 * self.bpm / 60.0 / sample_rate as f64
+    }
+}
+
+/// Global transport of the engine, the beat only moves while playing
+#[derive(Debug, Clone, Copy)]
+pub struct Transport {
+    pub playing: bool,
+    pub clock: Clock,
+}
+
+impl Transport {
+    pub fn new(bpm: f64) -> Self {
+        Transport {
+            playing: true,
+            clock: Clock::new(bpm),
+        }
+    }
+
+    pub fn advance(&mut self, samples: usize, sample_rate: usize) {
+        self.clock.beat = self.clock.beat_at(samples, sample_rate);
+    }
+
+    /// Change the tempo without moving the current beat
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.clock.bpm = bpm.max(1.0);
+    }
+
+    /// Follow a timeline that is at `beat` on sample `time`,
+    /// `now` is the time in samples of the start of the next buffer
+    pub fn align(&mut self, bpm: f64, beat: f64, time: u64, now: u64, sample_rate: usize) {
+        let elapsed = now as f64 - time as f64;
+
+        self.set_bpm(bpm);
+        self.clock.beat = beat + elapsed * self.clock.bpm / 60.0 / sample_rate as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align() {
+        let mut transport = Transport::new(120.0);
+
+        transport.advance(500, 1000);
+        assert_eq!(transport.clock.beat, 1.0);
+
+        // a timeline at beat 8 two seconds before the current time
+        transport.align(60.0, 8.0, 1000, 3000, 1000);
+        assert_eq!(
+            transport.clock,
+            Clock {
+                bpm: 60.0,
+                beat: 10.0
+            }
+        );
+    }
+}
@@ -1,8 +1,80 @@
 use super::{Color, Image};
+use crate::glyphs::{render_text, Font, TextLayout};
 
 pub trait Graphics {
     fn clear(&mut self);
     fn get_viewport(&self) -> (i32, i32);
     fn draw_rect(&mut self, color: Color, x: i32, y: i32, w: i32, h: i32);
     fn draw_image(&mut self, image: &Image, x: i32, y: i32);
+
+    fn draw_text(
+        &mut self,
+        text: &str,
+        color: Color,
+        font: &Font,
+        layout: &TextLayout,
+        x: i32,
+        y: i32,
+    ) {
+        let image = render_text(text, color, font, layout);
+        self.draw_image(&image, x, y);
+    }
+
+    /// Draw a one pixel wide line between two points, both ends included
+    fn draw_line(&mut self, color: Color, x0: i32, y0: i32, x1: i32, y1: i32) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+
+        loop {
+            self.draw_rect(color, x, y, 1, 1);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let e2 = error * 2;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Pixels(Vec<(i32, i32)>);
+
+    impl Graphics for Pixels {
+        fn clear(&mut self) {}
+
+        fn get_viewport(&self) -> (i32, i32) {
+            (8, 8)
+        }
+
+        fn draw_rect(&mut self, _: Color, x: i32, y: i32, _: i32, _: i32) {
+            self.0.push((x, y));
+        }
+
+        fn draw_image(&mut self, _: &Image, _: i32, _: i32) {}
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let mut g = Pixels(vec![]);
+        g.draw_line(Color::full(), 0, 0, 4, 2);
+        assert_eq!(g.0, vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]);
+
+        let mut g = Pixels(vec![]);
+        g.draw_line(Color::full(), 2, 3, 2, 1);
+        assert_eq!(g.0, vec![(2, 3), (2, 2), (2, 1)]);
+    }
 }
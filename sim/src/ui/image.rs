@@ -17,15 +17,19 @@ impl Image {
     }
 
     pub fn from_bitmap(bitmap: &Bitmap, color: Color) -> Self {
-        let mut data = Vec::with_capacity(bitmap.data.len() * 8);
+        let mut data = Vec::with_capacity((bitmap.width * bitmap.height).max(0) as usize);
+        // rows are padded to whole bytes
+        let stride = (bitmap.width.max(0) as usize).div_ceil(8);
 
-        for byte in &bitmap.data {
-            for i in 0..8 {
-                if byte >> (7 - i) & 0b1 == 0b1 {
-                    // if the byte is positive set the color of the bitmap
+        for y in 0..bitmap.height.max(0) as usize {
+            for x in 0..bitmap.width.max(0) as usize {
+                let byte = bitmap.data.get(y * stride + x / 8).copied().unwrap_or(0);
+
+                if byte >> (7 - x % 8) & 0b1 == 0b1 {
+                    // if the bit is set use the color of the bitmap
                     data.push(color);
                 } else {
-                    // for empty bytes set a transparant pixel
+                    // for empty bits set a transparant pixel
                     data.push(Color::empty());
                 }
             }
@@ -53,6 +57,15 @@ impl Image {
         }
     }
 
+    /// Add a color to a rectangle of pixels, leaving out the pixels outside the image
+    pub fn add_rect(&mut self, x: i32, y: i32, width: i32, height: i32, color: Color) {
+        for row in y.max(0)..(y + height).min(self.height) {
+            for column in x.max(0)..(x + width).min(self.width) {
+                self.data[(row * self.width + column) as usize].add(color);
+            }
+        }
+    }
+
     pub fn clear(&mut self, color: Color) {
         for c in self.data.iter_mut() {
             *c = color;
@@ -89,11 +102,11 @@ impl Image {
 
         for color in self.data.iter() {
             if color == &Color::full() {
-                ascii.push_str("1");
+                ascii.push('1');
             } else if color == &Color::empty() {
-                ascii.push_str("0");
+                ascii.push('0');
             } else {
-                ascii.push_str("?");
+                ascii.push('?');
             }
         }
 
@@ -192,6 +205,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_image_from_padded_bitmap() {
+        let bitmap = Bitmap::new(10, 2, &[0b10101110, 0b01000000, 0b01010111, 0b10000000]);
+
+        let image = Image::from_bitmap(&bitmap, Color::full());
+
+        assert_eq!(
+            &image.to_ascii(),
+            "1010111001\
+	     0101011110\
+	    "
+        );
+    }
+
     #[test]
     fn test_layer() {
         let mut a = Image::new(4, 4);
@@ -2,18 +2,31 @@ mod bitmap;
 mod color;
 mod graphics;
 mod image;
+mod prompt;
+mod scope;
 
-use crate::glyphs::bitmap_from_char;
-use crate::{Grid, Input, InputState};
-use crate::grid::Position;
+use crate::glyphs::{Font, TextLayout};
+use crate::{Command, Controller, EntitySnapshot, EntityType, Error, Input, InputState, Pointer};
+use crate::grid::{Position, Rect};
+use crate::entity::Setting;
+use crate::patch::Patch;
 pub use bitmap::Bitmap;
 pub use color::Color;
 pub use graphics::Graphics;
 pub use image::Image;
+use std::collections::HashMap;
 
 static DETAIL_VIEW_HEIGHT: i32 = 120;
 static VIEW_BORDER: i32 = 2;
 static VIEW_MARGIN: i32 = 8;
+/// brightness kept by a gate flash each frame
+static FLASH_DECAY: f32 = 0.8;
+/// samples of the tapped output kept for the scope
+static SCOPE_HISTORY: usize = 8192;
+static SPECTRUM_SIZE: usize = 1024;
+static METER_WIDTH: i32 = 64;
+/// seconds a peak is held on the meters before it falls back
+static PEAK_HOLD_SECONDS: f64 = 1.0;
 
 #[derive(PartialEq)]
 enum ActiveView {
@@ -21,14 +34,59 @@ enum ActiveView {
     Detail,
 }
 
+/// What dragging the pointer over the grid does, with the cell it was last over
+#[derive(Clone, Copy)]
+enum Drag {
+    Select(Position),
+    Move(Position),
+}
+
 pub struct UserInterface {
     select_color: Color,
     background_color: Color,
+    error_color: Color,
+    cycle_color: Color,
+    connection_color: Color,
     grid_block_size: (i32, i32),
+    font: Font,
+    /// size of a glyph of the font
     font_size: (i32, i32),
     prompt: String,
     prompt_is_active: bool,
     active_view: ActiveView,
+    /// setting of the entity under the cursor selected in the detail view
+    selected_setting: usize,
+    /// last command that could not be sent to the engine
+    error: Option<Error>,
+    /// blocks kept between the cursor and the edge of the grid view while scrolling
+    scroll_margin: (i32, i32),
+    /// blocks visible in the grid view at the last render
+    grid_blocks: (i32, i32),
+    /// brightness of the gate flash of entities, fading out while rendering
+    flashes: HashMap<Position, f32>,
+    /// draw the connections between entities over the grid
+    show_connections: bool,
+    /// position of the entity the engine sends the output of
+    tapped: Option<Position>,
+    /// latest samples of the tapped output
+    scope: Vec<f32>,
+    /// samples shown across the width of the scope
+    scope_window: usize,
+    /// show the spectrum of the tapped output instead of the waveform
+    show_spectrum: bool,
+    /// held peak of the left and right channel, with the time in samples it was held at
+    peak_holds: [(f32, u64); 2],
+    /// a channel clipped since the indicators were cleared
+    clipped: [bool; 2],
+    /// corner of the selection opposite to the cursor while in visual mode
+    selection: Option<Position>,
+    /// entities yanked or cut from the grid, pasted at the cursor
+    clipboard: Option<Patch>,
+    /// size of the viewport at the last render
+    viewport: (i32, i32),
+    drag: Option<Drag>,
+    /// setting changed with the wheel with its latest value, the changes are undone together
+    adjusting: Option<(Position, Setting)>,
 }
 
 impl UserInterface {
@@ -36,15 +94,49 @@ impl UserInterface {
         UserInterface {
 	    select_color: Color::new(00, 209, 255, 255),
 	    background_color: Color::new(0, 0, 0, 255),
+	    error_color: Color::new(255, 56, 56, 255),
+	    cycle_color: Color::new(255, 145, 0, 128),
+	    connection_color: Color::new(255, 255, 255, 255),
             grid_block_size: (16, 16),
+            font: Font::ascii(),
             font_size: (8, 8),
             prompt: String::from(""),
 	    prompt_is_active: false,
 	    active_view: ActiveView::Grid,
+	    selected_setting: 0,
+	    error: None,
+	    scroll_margin: (2, 2),
+	    grid_blocks: (0, 0),
+	    flashes: HashMap::new(),
+	    show_connections: true,
+	    tapped: None,
+	    scope: vec![],
+	    scope_window: 256,
+	    show_spectrum: false,
+	    peak_holds: [(0.0, 0); 2],
+	    clipped: [false; 2],
+	    selection: None,
+	    clipboard: None,
+	    viewport: (0, 0),
+	    drag: None,
+	    adjusting: None,
         }
     }
 
-    pub fn process_input(&mut self, grid: &mut Grid, input_state: &InputState) {
+    pub fn set_font(&mut self, font: Font) {
+	self.font_size = font.get_size();
+	self.font = font;
+    }
+
+    pub fn set_scroll_margin(&mut self, x: i32, y: i32) {
+	self.scroll_margin = (x.max(0), y.max(0));
+    }
+
+    pub fn process_input(&mut self, controller: &mut Controller, input_state: &InputState) {
+	if let Some(error) = controller.take_error() {
+	    self.error = Some(error);
+	}
+
 	for input in &input_state.buffer {
 	    if self.prompt_is_active {
 		match input {
@@ -52,6 +144,7 @@ impl UserInterface {
 			self.prompt.push(*c);
 		    }
 		    Input::Enter => {
+			self.run_prompt(controller);
 			self.prompt.clear();
 			self.prompt_is_active = false;
 		    }
@@ -64,35 +157,141 @@ impl UserInterface {
 		    _ => (),
 		}
 	    } else {
-		match input {
-		    Input::Char('>') => {
-			self.prompt_is_active = true;
+		// anything but the wheel ends the changes made with it
+		let adjusting = matches!(input, Input::Wheel(..) | Input::PointerMove(_));
+		if !adjusting && self.adjusting.take().is_some() {
+		    self.send(controller, Command::EndGroup);
+		}
+		if let Input::PointerMove(_) | Input::PointerDown(_) | Input::PointerUp(_) | Input::Wheel(..) =
+		    input
+		{
+		    self.process_pointer(controller, input);
+		    continue;
+		}
+
+		if let Input::Char('>') = input {
+		    self.prompt_is_active = true;
+		}
+		// clear the clip indicators
+		if let Input::Char('C') = input {
+		    self.clipped = [false; 2];
+		}
+		if let Input::Char('u') = input {
+		    self.send(controller, Command::Undo);
+		}
+		if let Input::Char('r') = input {
+		    if input_state.is_key_down(Input::Control) {
+			self.send(controller, Command::Redo);
 		    }
-		    _ => (),
+		}
+		if let Input::Space = input {
+		    let command = if controller.get_snapshot().playing { Command::Stop } else { Command::Play };
+		    self.send(controller, command);
 		}
 		match self.active_view {
 		    ActiveView::Grid => {
 			match input {
+			    // drag the selection with the arrow keys
+			    Input::Right | Input::Left | Input::Up | Input::Down
+				if self.selection.is_some() =>
+			    {
+				let offset = match input {
+				    Input::Right => Position::new(1, 0),
+				    Input::Left => Position::new(-1, 0),
+				    Input::Up => Position::new(0, -1),
+				    _ => Position::new(0, 1),
+				};
+				self.move_selection(controller, offset);
+			    }
 			    Input::Char('l') | Input::Right => {
-				grid.cursor_position = grid.cursor_position.add(Position::new(1, 0));
+				controller.cursor_position = controller.cursor_position.add(Position::new(1, 0));
 			    }
 			    Input::Char('h') | Input::Left => {
-				grid.cursor_position = grid.cursor_position.add(Position::new(-1, 0));
+				controller.cursor_position = controller.cursor_position.add(Position::new(-1, 0));
 			    }
 			    Input::Char('k') | Input::Up => {
-				grid.cursor_position = grid.cursor_position.add(Position::new(0, -1));
+				controller.cursor_position = controller.cursor_position.add(Position::new(0, -1));
 			    }
 			    Input::Char('j') | Input::Down => {
-				grid.cursor_position = grid.cursor_position.add(Position::new(0, 1));
+				controller.cursor_position = controller.cursor_position.add(Position::new(0, 1));
+			    }
+			    Input::Char('L') => self.pan(controller, Position::new(1, 0)),
+			    Input::Char('H') => self.pan(controller, Position::new(-1, 0)),
+			    Input::Char('K') => self.pan(controller, Position::new(0, -1)),
+			    Input::Char('J') => self.pan(controller, Position::new(0, 1)),
+			    // move the window and the cursor by a page
+			    Input::PageUp | Input::PageDown => {
+				let page = if *input == Input::PageUp { -self.grid_blocks.1 } else { self.grid_blocks.1 };
+				controller.window_position = controller.window_position.add(Position::new(0, page));
+				controller.cursor_position = controller.cursor_position.add(Position::new(0, page));
+			    }
+			    Input::Char('z') => self.center(controller),
+			    Input::Char('c') => {
+				self.show_connections = !self.show_connections;
+			    }
+			    Input::Char('v') => {
+				self.selection = match self.selection {
+				    Some(_) => None,
+				    None => Some(controller.cursor_position),
+				};
 			    }
+			    Input::Escape => {
+				self.selection = None;
+			    }
+			    Input::Char('y') => self.yank(controller),
+			    Input::Char('d') => self.cut(controller),
+			    Input::Char('p') => self.paste(controller),
 			    Input::Tab => {
 				self.active_view = ActiveView::Detail;
 			    }
 			    _ => (),
 			}
+			self.scroll_to_cursor(controller);
 		    }
 		    ActiveView::Detail => {
+			let settings = controller
+			    .get_snapshot()
+			    .get_entity(controller.cursor_position)
+			    .map(|entity| entity.settings.clone())
+			    .unwrap_or_default();
+			let selected = settings.get(self.selected_setting.min(settings.len().saturating_sub(1)));
+
 			match input {
+			    Input::Char('j') | Input::Down => {
+				self.selected_setting = (self.selected_setting + 1).min(settings.len().saturating_sub(1));
+			    }
+			    Input::Char('k') | Input::Up => {
+				self.selected_setting = self.selected_setting.saturating_sub(1);
+			    }
+			    // map the next control change received onto the selected setting
+			    Input::Char('m') => if let Some(setting) = selected {
+				let command = Command::LearnMidi(controller.cursor_position, setting.description.to_string());
+				self.send(controller, command);
+			    }
+			    Input::Char('M') => if let Some(setting) = selected {
+				let command = Command::ForgetMidi(controller.cursor_position, setting.description.to_string());
+				self.send(controller, command);
+			    }
+			    Input::Escape => {
+				self.send(controller, Command::CancelLearn);
+			    }
+			    // switch the scope between the waveform and the spectrum
+			    Input::Char('f') => {
+				self.show_spectrum = !self.show_spectrum;
+			    }
+			    // zoom the time window of the scope
+			    Input::Char('+') | Input::Char('=') => {
+				self.scope_window = (self.scope_window / 2).max(16);
+			    }
+			    Input::Char('-') => {
+				self.scope_window = (self.scope_window * 2).min(SCOPE_HISTORY / 2);
+			    }
+			    // send osc for the gates of the entity under the cursor
+			    Input::Char('o') => {
+				let position = controller.cursor_position;
+				let watched = controller.get_snapshot().watched.contains(&position);
+				self.send(controller, Command::WatchGate(position, !watched));
+			    }
 			    Input::Tab => {
 				self.active_view = ActiveView::Grid;
 			    }
@@ -102,15 +301,356 @@ impl UserInterface {
 		};
 	    }
         }
+
+	// the scope follows the cursor, a tap that could not be sent is tried again next frame
+	if self.tapped != Some(controller.cursor_position) {
+	    self.scope.clear();
+	    if self.send(controller, Command::Tap(controller.cursor_position)) {
+		self.tapped = Some(controller.cursor_position);
+	    }
+	}
+
+	self.scope.extend(controller.receive_tap());
+	let excess = self.scope.len().saturating_sub(SCOPE_HISTORY);
+	self.scope.drain(..excess);
+    }
+
+    /// Send a command to the engine, returning if it was sent,
+    /// an error is kept until the next input
+    fn send(&mut self, controller: &mut Controller, command: Command) -> bool {
+        match controller.send(command) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+
+    /// Send commands that are undone together, none are sent when they do not all fit
+    fn send_group(&mut self, controller: &mut Controller, commands: Vec<Command>) {
+        let group = [Command::BeginGroup]
+            .into_iter()
+            .chain(commands)
+            .chain([Command::EndGroup]);
+
+        if let Err(e) = controller.send_all(group.collect()) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Click and drag on the grid to select or move cells, click a setting to select it,
+    /// the wheel scrolls the grid or changes the setting under the pointer
+    fn process_pointer(&mut self, controller: &mut Controller, input: &Input) {
+	match *input {
+	    Input::PointerDown(Pointer { x, y, .. }) => {
+		if let Some(position) = self.get_grid_position(controller, x, y) {
+		    self.active_view = ActiveView::Grid;
+
+		    let selected = self.get_selection(controller).intersect_position(position);
+
+		    if self.selection.is_some() && selected {
+			// the steps of the move are undone together
+			self.send(controller, Command::BeginGroup);
+			self.drag = Some(Drag::Move(position));
+		    } else {
+			controller.cursor_position = position;
+			self.selection = None;
+			self.drag = Some(Drag::Select(position));
+		    }
+		} else if let Some(row) = self.get_setting_row(x, y) {
+		    self.active_view = ActiveView::Detail;
+		    self.selected_setting = row;
+		}
+	    }
+	    Input::PointerMove(Pointer { x, y, .. }) => {
+		let position = self.get_grid_position(controller, x, y);
+
+		match (self.drag, position) {
+		    (Some(Drag::Select(anchor)), Some(position))
+			if position != controller.cursor_position =>
+		    {
+			self.selection = Some(anchor);
+			controller.cursor_position = position;
+		    }
+		    (Some(Drag::Move(last)), Some(position)) if position != last => {
+			self.move_selection(controller, position.subtract(last));
+			self.drag = Some(Drag::Move(position));
+		    }
+		    _ => (),
+		}
+	    }
+	    Input::PointerUp(_) => {
+		// a move is undone in one step, however far it was dragged
+		if let Some(Drag::Move(_)) = self.drag.take() {
+		    self.send(controller, Command::EndGroup);
+		}
+	    }
+	    Input::Wheel(Pointer { x, y, modifiers }, dx, dy) => {
+		if self.get_grid_position(controller, x, y).is_some() {
+		    // with shift the wheel scrolls sideways
+		    let offset = if modifiers.shift {
+			Position::new(dy, dx)
+		    } else {
+			Position::new(dx, dy)
+		    };
+		    self.pan(controller, offset);
+		} else if let Some(row) = self.get_setting_row(x, y) {
+		    let position = controller.cursor_position;
+		    let setting = controller
+			.get_snapshot()
+			.get_entity(position)
+			.and_then(|entity| entity.settings.get(row))
+			.cloned();
+
+		    if let Some(setting) = setting {
+			// continue from the last change, the snapshot may not have caught up with it
+			let setting = match self.adjusting.take() {
+			    Some((p, adjusted))
+				if p == position && adjusted.description == setting.description =>
+			    {
+				adjusted
+			    }
+			    _ => {
+				self.send(controller, Command::BeginGroup);
+				setting
+			    }
+			};
+
+			// scrolling up increases the value
+			let setting = setting.with_steps(-dy);
+			self.adjusting = Some((position, setting.clone()));
+			self.send(controller, Command::UpdateSetting(position, setting));
+		    }
+		}
+	    }
+	    _ => (),
+	}
+    }
+
+    /// Grid position under a pixel of the viewport, `None` outside of the grid view
+    fn get_grid_position(&self, controller: &Controller, x: i32, y: i32) -> Option<Position> {
+	let (gw, gh) = self.grid_block_size;
+	let (bx, by) = self.grid_blocks;
+	let offset = VIEW_MARGIN + VIEW_BORDER;
+	let block = Position::new((x - offset).div_euclid(gw), (y - offset).div_euclid(gh));
+
+	if (0..bx).contains(&block.x) && (0..by).contains(&block.y) {
+	    Some(controller.window_position.add(block))
+	} else {
+	    None
+	}
+    }
+
+    /// Row of the settings listed in the detail view under a pixel of the viewport
+    fn get_setting_row(&self, x: i32, y: i32) -> Option<usize> {
+	let (vw, vh) = self.viewport;
+	let (_, fh) = self.font_size;
+	let offset = VIEW_BORDER + VIEW_MARGIN / 2;
+	let left = VIEW_MARGIN + offset;
+	let top = vh - DETAIL_VIEW_HEIGHT - fh - VIEW_MARGIN * 2 + offset;
+	// the settings take the left half, the scope the right half
+	let width = (vw - VIEW_MARGIN * 2 - offset * 2) / 2;
+	let height = DETAIL_VIEW_HEIGHT - offset * 2;
+
+	if fh > 0 && (left..left + width).contains(&x) && (top..top + height).contains(&y) {
+	    Some(((y - top) / fh) as usize)
+	} else {
+	    None
+	}
+    }
+
+    /// Run the commands typed into the prompt, they are undone together
+    fn run_prompt(&mut self, controller: &mut Controller) {
+	let cursor = controller.cursor_position;
+	let commands = prompt::parse_prompt(&self.prompt, cursor, controller.get_snapshot());
+
+	match commands {
+	    Ok(commands) if !commands.is_empty() => {
+		self.send(controller, Command::BeginGroup);
+		for command in commands {
+		    self.send(controller, command);
+		}
+		self.send(controller, Command::EndGroup);
+	    }
+	    Ok(_) => (),
+	    Err(e) => self.error = Some(e),
+	}
+    }
+
+    /// Yank the selection and return it as patch text for the system clipboard
+    pub fn copy_patch(&mut self, controller: &Controller) -> String {
+	self.yank(controller);
+	self.clipboard.as_ref().map(Patch::to_json).unwrap_or_default()
+    }
+
+    /// Cut the selection and return it as patch text for the system clipboard
+    pub fn cut_patch(&mut self, controller: &mut Controller) -> String {
+	self.cut(controller);
+	self.clipboard.as_ref().map(Patch::to_json).unwrap_or_default()
+    }
+
+    /// Paste patch text from the system clipboard at the cursor
+    pub fn paste_patch(&mut self, controller: &mut Controller, text: &str) -> Result<(), Error> {
+	let mut patch = Patch::from_json(text)?;
+	patch.find_samples(controller.get_snapshot());
+	self.clipboard = Some(patch);
+	self.paste(controller);
+	Ok(())
+    }
+
+    /// Cells between the selection and the cursor, only the cursor outside of visual mode
+    fn get_selection(&self, controller: &Controller) -> Rect {
+	let cursor = controller.cursor_position;
+	Rect::from_corners(self.selection.unwrap_or(cursor), cursor)
+    }
+
+    fn yank(&mut self, controller: &Controller) {
+	let rect = self.get_selection(controller);
+	self.clipboard = Some(Patch::from_snapshot(controller.get_snapshot(), &rect));
+	self.selection = None;
+    }
+
+    fn cut(&mut self, controller: &mut Controller) {
+	let rect = self.get_selection(controller);
+	self.yank(controller);
+
+	if let Some(patch) = self.clipboard.clone() {
+	    let commands = patch
+		.entities
+		.iter()
+		.map(|entity| Command::RemoveEntity(rect.position.add(entity.position)))
+		.collect();
+	    self.send_group(controller, commands);
+	}
     }
 
-    pub fn render(&mut self, g: &mut dyn Graphics, grid: &Grid) {
+    fn paste(&mut self, controller: &mut Controller) {
+	if let Some(patch) = &self.clipboard {
+	    let commands = patch.commands(controller.cursor_position);
+	    self.send_group(controller, commands);
+	}
+    }
+
+    /// Move the entities in the selection along with the selection and the cursor
+    fn move_selection(&mut self, controller: &mut Controller, offset: Position) {
+	let rect = self.get_selection(controller);
+	self.send(controller, Command::MoveEntities(rect, offset));
+	self.selection = self.selection.map(|anchor| anchor.add(offset));
+	controller.cursor_position = controller.cursor_position.add(offset);
+    }
+
+    /// Scroll margins that fit in the grid view, leaving at least the middle block
+    fn get_scroll_margin(&self) -> (i32, i32) {
+	let (bx, by) = self.grid_blocks;
+	let (mx, my) = self.scroll_margin;
+	(mx.min((bx - 1) / 2).max(0), my.min((by - 1) / 2).max(0))
+    }
+
+    /// Scroll the window until the cursor is inside the scroll margins
+    fn scroll_to_cursor(&self, controller: &mut Controller) {
+	let (bx, by) = self.grid_blocks;
+	if bx <= 0 || by <= 0 {
+	    return;
+	}
+
+	let (mx, my) = self.get_scroll_margin();
+	let cursor = controller.cursor_position.subtract(controller.window_position);
+	let clamped = Position::new(cursor.x.clamp(mx, bx - 1 - mx), cursor.y.clamp(my, by - 1 - my));
+	controller.window_position = controller.window_position.add(cursor.subtract(clamped));
+    }
+
+    /// Move the window, the cursor only moves when it would leave the scroll margins
+    fn pan(&self, controller: &mut Controller, offset: Position) {
+	let (bx, by) = self.grid_blocks;
+	controller.window_position = controller.window_position.add(offset);
+	if bx <= 0 || by <= 0 {
+	    return;
+	}
+
+	let (mx, my) = self.get_scroll_margin();
+	let cursor = controller.cursor_position.subtract(controller.window_position);
+	let clamped = Position::new(cursor.x.clamp(mx, bx - 1 - mx), cursor.y.clamp(my, by - 1 - my));
+	controller.cursor_position = controller.window_position.add(clamped);
+    }
+
+    fn center(&self, controller: &mut Controller) {
+	let (bx, by) = self.grid_blocks;
+	controller.window_position = controller.cursor_position.subtract(Position::new(bx / 2, by / 2));
+    }
+
+    /// Number of blocks that fit in the grid view
+    fn get_grid_blocks(&self, viewport: (i32, i32)) -> (i32, i32) {
+	let (vw, vh) = viewport;
+	let (_, fh) = self.font_size;
+	let (gw, gh) = self.grid_block_size;
+
+	let grid_width = vw - VIEW_MARGIN * 2 - VIEW_BORDER * 2;
+	let grid_height = vh - DETAIL_VIEW_HEIGHT - fh - VIEW_MARGIN * 4 - VIEW_BORDER * 2;
+	(grid_width / gw, grid_height / gh)
+    }
+
+    pub fn render(&mut self, g: &mut dyn Graphics, controller: &Controller) {
         g.clear();
 
+	self.viewport = g.get_viewport();
+	self.grid_blocks = self.get_grid_blocks(self.viewport);
+	self.update_flashes(controller);
+	self.update_meters(controller);
         self.render_background(g);
-	self.render_grid(g, grid);
-	self.render_detail(g);
-	self.render_prompt(g);
+	self.render_grid(g, controller);
+	self.render_detail(g, controller);
+	self.render_prompt(g, controller);
+	self.render_meters(g, controller);
+    }
+
+    fn update_flashes(&mut self, controller: &Controller) {
+	self.flashes.retain(|_, flash| {
+	    *flash *= FLASH_DECAY;
+	    *flash > 0.05
+	});
+
+	for entity in controller.get_snapshot().entities.iter() {
+	    let gate = matches!(
+		entity.kind,
+		EntityType::Trigger | EntityType::Euclid | EntityType::VoiceAllocator | EntityType::MidiIn
+	    );
+
+	    if gate && entity.level >= 0.5 {
+		self.flashes.insert(entity.position, 1.0);
+	    }
+	}
+    }
+
+    fn update_meters(&mut self, controller: &Controller) {
+	let snapshot = controller.get_snapshot();
+	let hold_time = (PEAK_HOLD_SECONDS * snapshot.sample_rate as f64) as u64;
+
+	for (i, meter) in snapshot.meters.iter().enumerate() {
+	    let (hold, time) = &mut self.peak_holds[i];
+
+	    if meter.peak >= *hold || snapshot.time.saturating_sub(*time) >= hold_time {
+		*hold = meter.peak;
+		*time = snapshot.time;
+	    }
+
+	    self.clipped[i] |= meter.clipped;
+	}
+    }
+
+    fn get_kind_color(kind: EntityType) -> Color {
+	match kind {
+	    EntityType::Step => Color::new(120, 220, 120, 255),
+	    EntityType::Trigger => Color::new(255, 145, 0, 255),
+	    EntityType::Sampler => Color::new(255, 85, 170, 255),
+	    EntityType::SampleHold => Color::new(170, 130, 255, 255),
+	    EntityType::Slew => Color::new(80, 200, 200, 255),
+	    EntityType::Euclid => Color::new(255, 210, 60, 255),
+	    EntityType::VoiceAllocator => Color::new(120, 160, 255, 255),
+	    EntityType::MidiIn => Color::new(230, 230, 230, 255),
+	    EntityType::MidiOut => Color::new(160, 160, 160, 255),
+	    EntityType::Voice => Color::new(170, 195, 255, 255),
+	}
     }
 
     fn render_background(&mut self, g: &mut dyn Graphics) {
@@ -118,7 +658,7 @@ impl UserInterface {
         g.draw_rect(Color::new(0, 0, 0, 255), 0, 0, w, h);
     }
 
-    fn render_grid(&self, g: &mut dyn Graphics, grid: &Grid) {
+    fn render_grid(&self, g: &mut dyn Graphics, controller: &Controller) {
         let (vw, vh) = g.get_viewport();
         let (_, fh) = self.font_size;
 	let (gw, gh) = self.grid_block_size;
@@ -138,21 +678,37 @@ impl UserInterface {
             g.draw_rect(self.background_color, x + VIEW_BORDER, y + VIEW_BORDER, w - VIEW_BORDER * 2, h - VIEW_BORDER * 2);
 	}
 
-	let grid_width = vw - VIEW_MARGIN * 2 - VIEW_BORDER * 2;
-	let grid_height = vh - DETAIL_VIEW_HEIGHT - fh - VIEW_MARGIN * 4 - VIEW_BORDER * 2;
-	let grid_blocks_x = grid_width / gw;
-	let grid_blocks_y = grid_height / gh;
+	let (grid_blocks_x, grid_blocks_y) = self.get_grid_blocks((vw, vh));
+	let snapshot = controller.get_snapshot();
+	let selection = self.get_selection(controller);
 
 	for y in 0..grid_blocks_y {
 	    for x in 0..grid_blocks_x {
 		let offset = VIEW_MARGIN + VIEW_BORDER;
 		let pos_x = offset + x * gw;
 		let pos_y = offset + y * gh;
-		let pos = Position::new(x, y).add(grid.window_position);
+		let pos = Position::new(x, y).add(controller.window_position);
+
+		if snapshot.get_error_position() == Some(pos) {
+		    g.draw_rect(self.error_color, pos_x, pos_y, gw, gh);
+		} else if snapshot.is_in_cycle(pos) {
+		    g.draw_rect(self.cycle_color, pos_x, pos_y, gw, gh);
+		}
+
+		let entity = snapshot.get_entity(pos);
+		if let Some(entity) = entity {
+		    self.render_entity(g, entity, pos_x, pos_y);
+		}
+
+		if self.selection.is_some() && selection.intersect_position(pos) {
+		    let mut color = self.select_color;
+		    color.alpha = 64;
+		    g.draw_rect(color, pos_x, pos_y, gw, gh);
+		}
 
-		if let Some(image) = grid.get_image_for_pos(pos) {
+		if let Some(image) = controller.get_image_for_pos(pos) {
 		    g.draw_image(&image, pos_x, pos_y);
-		} else {
+		} else if entity.is_none() {
 		    let color = if y % 4 == 0 && x % 4 == 0 {
 			Color::new(255, 255, 255, 128)
 		    } else {
@@ -164,15 +720,78 @@ impl UserInterface {
 		}
 	    }
 	}
+
+	if self.show_connections {
+	    self.render_connections(g, controller, (grid_blocks_x, grid_blocks_y));
+	}
+    }
+
+    /// Arrows across the edges of connected cells, brighter the louder the signal
+    fn render_connections(&self, g: &mut dyn Graphics, controller: &Controller, blocks: (i32, i32)) {
+	let (gw, gh) = self.grid_block_size;
+	let offset = VIEW_MARGIN + VIEW_BORDER;
+	let snapshot = controller.get_snapshot();
+	let visible = |p: Position| (0..blocks.0).contains(&p.x) && (0..blocks.1).contains(&p.y);
+
+	for connection in snapshot.connections.iter() {
+	    let from = connection.from.subtract(controller.window_position);
+	    let to = connection.to.subtract(controller.window_position);
+	    if !visible(from) || !visible(to) {
+		continue;
+	    }
+
+	    let level = snapshot.get_entity(connection.from).map(|e| e.level).unwrap_or(0.0);
+	    let mut color = if connection.delayed { self.cycle_color } else { self.connection_color };
+	    color.alpha = (64.0 + level.clamp(0.0, 1.0) * 191.0) as u8;
+
+	    // only the part between the glyphs, around the shared edge of the cells
+	    let direction = to.subtract(from);
+	    let (dx, dy) = (direction.x.signum(), direction.y.signum());
+	    let x0 = offset + from.x * gw + gw / 2 + dx * gw / 4;
+	    let y0 = offset + from.y * gh + gh / 2 + dy * gh / 4;
+	    let x1 = offset + to.x * gw + gw / 2 - dx * gw / 4;
+	    let y1 = offset + to.y * gh + gh / 2 - dy * gh / 4;
+	    g.draw_line(color, x0, y0, x1, y1);
+
+	    // arrow head
+	    let (bx, by) = (x1 - dx * 2, y1 - dy * 2);
+	    g.draw_line(color, x1, y1, bx - dy * 2, by + dx * 2);
+	    g.draw_line(color, x1, y1, bx + dy * 2, by - dx * 2);
+	}
+    }
+
+    /// Glyph of the entity in its kind colour, with a flash on gates and a bar for its state
+    fn render_entity(&self, g: &mut dyn Graphics, entity: &EntitySnapshot, x: i32, y: i32) {
+	let (gw, gh) = self.grid_block_size;
+	let (fw, fh) = self.font_size;
+	let color = Self::get_kind_color(entity.kind);
+
+	if let Some(flash) = self.flashes.get(&entity.position) {
+	    let mut background = color;
+	    background.alpha = (flash * 160.0) as u8;
+	    g.draw_rect(background, x, y, gw, gh);
+	}
+
+	let glyph = entity.kind.get_glyph().to_string();
+	let glyph_x = x + ((gw - fw) / 2).max(0);
+	let glyph_y = y + ((gh - fh) / 2).max(0);
+	g.draw_text(&glyph, color, &self.font, &TextLayout::new(), glyph_x, glyph_y);
+
+	if let Some(level) = entity.display_level {
+	    let width = (level.clamp(0.0, 1.0) * gw as f32).round() as i32;
+	    if width > 0 {
+		g.draw_rect(color, x, y + gh - 2, width, 2);
+	    }
+	}
     }
 
-    fn render_detail(&self, g: &mut dyn Graphics) {
+    fn render_detail(&self, g: &mut dyn Graphics, controller: &Controller) {
         let (vw, vh) = g.get_viewport();
         let (_, fh) = self.font_size;
+	let x = VIEW_MARGIN;
+	let y = vh - DETAIL_VIEW_HEIGHT - fh - VIEW_MARGIN * 2;
 
 	if self.active_view == ActiveView::Detail {
-	    let x = VIEW_MARGIN;
-	    let y = vh - DETAIL_VIEW_HEIGHT - fh - VIEW_MARGIN * 2;
 	    let w = vw - VIEW_MARGIN * 2;
 	    let h = DETAIL_VIEW_HEIGHT;
 
@@ -184,11 +803,96 @@ impl UserInterface {
 	    g.draw_rect(color, x, y, w, h);
 	    g.draw_rect(self.background_color, x + VIEW_BORDER, y + VIEW_BORDER, w - VIEW_BORDER * 2, h - VIEW_BORDER * 2);
 	}
+
+	let snapshot = controller.get_snapshot();
+	let position = controller.cursor_position;
+	let settings = snapshot
+	    .get_entity(position)
+	    .map(|entity| entity.settings.as_slice())
+	    .unwrap_or_default();
+	let selected = self.selected_setting.min(settings.len().saturating_sub(1));
+	let offset = VIEW_BORDER + VIEW_MARGIN / 2;
+	let inner_width = vw - VIEW_MARGIN * 2 - offset * 2;
+	let inner_height = DETAIL_VIEW_HEIGHT - offset * 2;
+	// settings on the left, the scope of the entity on the right
+	let scope_width = if snapshot.get_entity(position).is_some() { inner_width / 2 } else { 0 };
+	let layout = TextLayout::new().width(inner_width - scope_width - VIEW_MARGIN).ellipsis();
+	let rows = (inner_height / fh).max(0) as usize;
+
+	// list the settings with the control change mapped onto them
+	for (i, setting) in settings.iter().enumerate().take(rows) {
+	    let learning = snapshot.learning.as_ref() == Some(&(position, setting.description.to_string()));
+	    let mapping = match snapshot.get_mapping(position, &setting.description) {
+		_ if learning => String::from(" learn"),
+		Some(mapping) => format!(" cc{}", mapping.controller),
+		None => String::new(),
+	    };
+
+	    let text = format!("{} {}{}", setting.description, setting.value, mapping);
+	    let color = if self.active_view == ActiveView::Detail && i == selected {
+		self.select_color
+	    } else {
+		Color::new(255, 255, 255, 255)
+	    };
+
+	    g.draw_text(&text, color, &self.font, &layout, x + offset, y + offset + i as i32 * fh);
+	}
+
+	if snapshot.watched.contains(&position) && settings.len() < rows {
+	    let text_y = y + offset + settings.len() as i32 * fh;
+	    g.draw_text("gate osc", Color::new(255, 255, 255, 128), &self.font, &layout, x + offset, text_y);
+	}
+
+	if scope_width > 0 {
+	    let scope_x = x + offset + inner_width - scope_width;
+	    self.render_scope(g, scope_x, y + offset, scope_width, inner_height);
+	}
     }
 
-    fn render_prompt(&mut self, g: &mut dyn Graphics) {
+    /// Waveform of the tapped output starting on a rising edge, or its spectrum on a log scale
+    fn render_scope(&self, g: &mut dyn Graphics, x: i32, y: i32, w: i32, h: i32) {
+	let color = self.select_color;
+	let mut faint = Color::new(255, 255, 255, 32);
+
+	if self.show_spectrum {
+	    let bins = scope::spectrum(&self.scope, SPECTRUM_SIZE);
+
+	    for column in 0..w {
+		// bins of the column on a logarithmic frequency scale
+		let bin = |c: i32| ((bins.len() as f32).powf(c as f32 / w as f32) as usize).min(bins.len() - 1);
+		let (low, high) = (bin(column), bin(column + 1).max(bin(column) + 1));
+		let magnitude = bins[low..high.min(bins.len())].iter().fold(0.0f32, |m, b| m.max(*b));
+
+		// 72 dB range
+		let decibels = 20.0 * magnitude.max(1e-6).log10();
+		let height = ((decibels + 72.0) / 72.0).clamp(0.0, 1.0) * h as f32;
+		g.draw_rect(color, x + column, y + h - height as i32, 1, height as i32);
+	    }
+	} else {
+	    g.draw_rect(faint, x, y + h / 2, w, 1);
+
+	    let start = scope::find_trigger(&self.scope, self.scope_window);
+	    let samples = &self.scope[start..(start + self.scope_window).min(self.scope.len())];
+	    let point = |column: i32| {
+		let sample = samples[(column as usize * samples.len() / w as usize).min(samples.len() - 1)];
+		y + h / 2 - (sample.clamp(-1.0, 1.0) * (h / 2 - 1) as f32) as i32
+	    };
+
+	    if !samples.is_empty() {
+		for column in 1..w {
+		    g.draw_line(color, x + column - 1, point(column - 1), x + column, point(column));
+		}
+	    }
+	}
+
+	faint.alpha = 128;
+	let label = if self.show_spectrum { String::from("fft") } else { format!("{} samples", self.scope_window) };
+	g.draw_text(&label, faint, &self.font, &TextLayout::new().width(w), x, y);
+    }
+
+    fn render_prompt(&mut self, g: &mut dyn Graphics, controller: &Controller) {
         let (fw, fh) = self.font_size;
-        let (_, vh) = g.get_viewport();
+        let (vw, vh) = g.get_viewport();
 	let x = VIEW_MARGIN;
         let y = vh - fh - VIEW_MARGIN;
 
@@ -197,14 +901,331 @@ impl UserInterface {
 	} else {
 	    Color::new(255, 255, 255, 128)
 	};
-	let bitmap = bitmap_from_char('>');
-	let char = Image::from_bitmap(&bitmap, text_color);
-	g.draw_image(&char, x, y);
-
-        for (i, c) in self.prompt.chars().enumerate() {
-            let bitmap = bitmap_from_char(c);
-            let char = Image::from_bitmap(&bitmap, text_color);
-            g.draw_image(&char, VIEW_MARGIN + (i as i32 + 1) * fw, y);
-        }
+	g.draw_text(">", text_color, &self.font, &TextLayout::new(), x, y);
+
+        // show the last error in place of the prompt while it is inactive
+        let error = self.error.as_ref().or(controller.get_snapshot().error.as_ref());
+        let (text, text_color) = match error {
+            Some(error) if !self.prompt_is_active => (error.to_string(), self.error_color),
+            _ => (self.prompt.clone(), text_color),
+        };
+
+        let status_width = METER_WIDTH + fh / 2 + VIEW_MARGIN;
+        let layout = TextLayout::new().width(vw - VIEW_MARGIN * 2 - fw - status_width).lines(1).ellipsis();
+        g.draw_text(&text, text_color, &self.font, &layout, x + fw, y);
+    }
+
+    /// Peak and RMS of the main output on a 60 dB scale, with the held peak and a clip indicator
+    fn render_meters(&self, g: &mut dyn Graphics, controller: &Controller) {
+	let (_, fh) = self.font_size;
+	let (vw, vh) = g.get_viewport();
+	let clip_size = fh / 2;
+	let x = vw - VIEW_MARGIN - METER_WIDTH - clip_size - 1;
+	let y = vh - fh - VIEW_MARGIN;
+	let faint = Color::new(255, 255, 255, 32);
+	let mut peak_color = self.select_color;
+	peak_color.alpha = 128;
+
+	let scale = |level: f32| {
+	    let decibels = 20.0 * level.max(1e-6).log10();
+	    (((decibels + 60.0) / 60.0).clamp(0.0, 1.0) * METER_WIDTH as f32) as i32
+	};
+
+	for (i, meter) in controller.get_snapshot().meters.iter().enumerate() {
+	    let bar_y = y + i as i32 * clip_size;
+	    let bar_height = clip_size - 1;
+
+	    g.draw_rect(faint, x, bar_y, METER_WIDTH, bar_height);
+	    g.draw_rect(peak_color, x, bar_y, scale(meter.peak), bar_height);
+	    g.draw_rect(self.select_color, x, bar_y, scale(meter.rms), bar_height);
+
+	    let hold = scale(self.peak_holds[i].0);
+	    if hold > 0 {
+		g.draw_rect(Color::full(), x + hold - 1, bar_y, 1, bar_height);
+	    }
+
+	    let clip_color = if self.clipped[i] { self.error_color } else { faint };
+	    g.draw_rect(clip_color, x + METER_WIDTH + 1, bar_y, clip_size, bar_height);
+	}
+    }
+}
+
+impl Default for UserInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Setting, SettingValue};
+
+    fn press(ui: &mut UserInterface, controller: &mut Controller, input: Input) {
+	let mut input_state = InputState::new();
+	input_state.key_down(input);
+	ui.process_input(controller, &input_state);
+    }
+
+    #[test]
+    fn test_scroll() {
+	let (_engine, mut controller) = Engine::new(4, 4, 2);
+	let mut ui = UserInterface::new();
+	ui.grid_blocks = (10, 6);
+	controller.window_position = Position::origin();
+
+	// the window follows the cursor once it enters the margin
+	for _ in 0..8 {
+	    press(&mut ui, &mut controller, Input::Char('l'));
+	}
+	assert_eq!(controller.cursor_position, Position::new(8, 0));
+	assert_eq!(controller.window_position, Position::new(1, -2));
+
+	// panning drags the cursor along at the margin
+	press(&mut ui, &mut controller, Input::Char('L'));
+	press(&mut ui, &mut controller, Input::Char('L'));
+	press(&mut ui, &mut controller, Input::Char('L'));
+	assert_eq!(controller.window_position, Position::new(4, -2));
+	assert_eq!(controller.cursor_position, Position::new(8, 0));
+	for _ in 0..3 {
+	    press(&mut ui, &mut controller, Input::Char('L'));
+	}
+	assert_eq!(controller.window_position, Position::new(7, -2));
+	assert_eq!(controller.cursor_position, Position::new(9, 0));
+
+	press(&mut ui, &mut controller, Input::PageDown);
+	assert_eq!(controller.window_position, Position::new(7, 4));
+	assert_eq!(controller.cursor_position, Position::new(9, 6));
+
+	press(&mut ui, &mut controller, Input::Char('z'));
+	assert_eq!(controller.window_position, Position::new(4, 3));
+    }
+
+    #[test]
+    fn test_tap_retry() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 1);
+	let mut ui = UserInterface::new();
+
+	controller.cursor_position = Position::origin();
+	ui.process_input(&mut controller, &InputState::new());
+	assert_eq!(ui.tapped, Some(Position::origin()));
+
+	// the queue is full so the tap waits for the engine
+	press(&mut ui, &mut controller, Input::Char('l'));
+	assert_eq!(ui.tapped, Some(Position::origin()));
+	assert!(ui.error.is_some());
+
+	engine.sample();
+	ui.process_input(&mut controller, &InputState::new());
+	assert_eq!(ui.tapped, Some(Position::new(1, 0)));
+    }
+
+    #[test]
+    fn test_selection() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 64);
+	let mut ui = UserInterface::new();
+	let positions = |controller: &Controller| {
+	    let snapshot = controller.get_snapshot();
+	    let mut positions: Vec<(i32, i32)> =
+		snapshot.entities.iter().map(|e| (e.position.x, e.position.y)).collect();
+	    positions.sort();
+	    positions
+	};
+
+	controller.cursor_position = Position::origin();
+	controller.send(Command::AddEntity(Position::new(0, 0), EntityType::Trigger)).unwrap();
+	controller.send(Command::AddEntity(Position::new(1, 1), EntityType::Step)).unwrap();
+	let bpm = Setting::new(SettingValue::Float(90.0), "bpm");
+	controller.send(Command::UpdateSetting(Position::new(0, 0), bpm)).unwrap();
+	engine.sample();
+	controller.update();
+
+	// yank the two cells and paste them to the right
+	for input in [Input::Char('v'), Input::Char('l'), Input::Char('j'), Input::Char('y')] {
+	    press(&mut ui, &mut controller, input);
+	}
+	assert!(ui.selection.is_none());
+	controller.cursor_position = Position::new(3, 0);
+	press(&mut ui, &mut controller, Input::Char('p'));
+	engine.sample();
+	controller.update();
+	assert_eq!(positions(&controller), vec![(0, 0), (1, 1), (3, 0), (4, 1)]);
+	let bpm = &controller.get_snapshot().get_entity(Position::new(3, 0)).unwrap().settings[0];
+	assert!(matches!(bpm.value, SettingValue::Float(v) if v == 90.0));
+
+	// drag the pasted cells down and cut them
+	for input in [Input::Char('v'), Input::Char('l'), Input::Char('j'), Input::Down] {
+	    press(&mut ui, &mut controller, input);
+	}
+	engine.sample();
+	controller.update();
+	assert_eq!(positions(&controller), vec![(0, 0), (1, 1), (3, 1), (4, 2)]);
+	assert_eq!(controller.cursor_position, Position::new(4, 2));
+
+	press(&mut ui, &mut controller, Input::Char('d'));
+	engine.sample();
+	controller.update();
+	assert_eq!(positions(&controller), vec![(0, 0), (1, 1)]);
+
+	// the clipboard goes through patch text
+	controller.cursor_position = Position::origin();
+	let text = ui.copy_patch(&controller);
+	assert!(text.contains("\"type\":\"trigger\""));
+	controller.cursor_position = Position::new(0, 5);
+	ui.paste_patch(&mut controller, &text).unwrap();
+	assert!(ui.paste_patch(&mut controller, "{").is_err());
+	engine.sample();
+	controller.update();
+	assert_eq!(positions(&controller), vec![(0, 0), (0, 5), (1, 1)]);
+    }
+
+    #[test]
+    fn test_paste_full() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 4);
+	let mut ui = UserInterface::new();
+	let text = "{\"entities\":[{\"x\":0,\"y\":0,\"type\":\"trigger\"},\
+	    {\"x\":1,\"y\":0,\"type\":\"step\"},{\"x\":2,\"y\":0,\"type\":\"slew\"}]}";
+
+	// three entities and the group markers do not fit in the queue, so nothing is pasted
+	ui.paste_patch(&mut controller, text).unwrap();
+	assert!(matches!(ui.error, Some(Error::QueueFull)));
+	engine.sample();
+	controller.update();
+	assert!(controller.get_snapshot().entities.is_empty());
+    }
+
+    #[test]
+    fn test_move_undo() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 64);
+	let mut ui = UserInterface::new();
+	let position = |controller: &Controller| controller.get_snapshot().entities[0].position;
+
+	controller.cursor_position = Position::origin();
+	controller.send(Command::AddEntity(Position::origin(), EntityType::Step)).unwrap();
+	for input in [Input::Char('v'), Input::Right, Input::Right, Input::Down] {
+	    press(&mut ui, &mut controller, input);
+	}
+	engine.sample();
+	controller.update();
+	assert_eq!(position(&controller), Position::new(2, 1));
+
+	// every step of a keyboard move is undone on its own
+	press(&mut ui, &mut controller, Input::Char('u'));
+	engine.sample();
+	controller.update();
+	assert_eq!(position(&controller), Position::new(2, 0));
+	press(&mut ui, &mut controller, Input::Char('u'));
+	press(&mut ui, &mut controller, Input::Char('u'));
+	engine.sample();
+	controller.update();
+	assert_eq!(position(&controller), Position::origin());
+    }
+
+    #[test]
+    fn test_prompt_undo() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 64);
+	let mut ui = UserInterface::new();
+	let count = |controller: &Controller| controller.get_snapshot().entities.len();
+
+	controller.cursor_position = Position::origin();
+	press(&mut ui, &mut controller, Input::Char('>'));
+	for c in "add trigger; set bpm 90".chars() {
+	    press(&mut ui, &mut controller, Input::Char(c));
+	}
+	press(&mut ui, &mut controller, Input::Enter);
+	engine.sample();
+	controller.update();
+	assert!(ui.error.is_none());
+	assert_eq!(count(&controller), 1);
+
+	// the whole prompt line is one step in the history
+	press(&mut ui, &mut controller, Input::Char('u'));
+	engine.sample();
+	controller.update();
+	assert_eq!(count(&controller), 0);
+
+	let mut input_state = InputState::new();
+	input_state.key_down(Input::Control);
+	input_state.key_down(Input::Char('r'));
+	ui.process_input(&mut controller, &input_state);
+	engine.sample();
+	controller.update();
+	let entity = controller.get_snapshot().get_entity(Position::origin()).unwrap();
+	assert!(matches!(entity.settings[0].value, SettingValue::Float(v) if v == 90.0));
+
+	press(&mut ui, &mut controller, Input::Char('>'));
+	for c in "jump".chars() {
+	    press(&mut ui, &mut controller, Input::Char(c));
+	}
+	press(&mut ui, &mut controller, Input::Enter);
+	assert!(ui.error.is_some());
+    }
+
+    #[test]
+    fn test_pointer() {
+	let (mut engine, mut controller) = Engine::new(4, 4, 64);
+	let mut ui = UserInterface::new();
+	ui.viewport = (400, 300);
+	ui.grid_blocks = ui.get_grid_blocks(ui.viewport);
+	controller.window_position = Position::new(-1, 0);
+	let pointer = |x, y| Pointer { x, y, modifiers: Default::default() };
+	// middle of a cell on the grid
+	let offset = VIEW_MARGIN + VIEW_BORDER + 8;
+	let cell = |x: i32, y: i32| pointer(offset + x * 16, offset + y * 16);
+
+	assert_eq!(ui.get_grid_position(&controller, 10, 10), Some(Position::new(-1, 0)));
+	assert_eq!(ui.get_grid_position(&controller, 9, 10), None);
+	assert_eq!(ui.get_grid_position(&controller, 10, 290), None);
+
+	controller.send(Command::AddEntity(Position::new(1, 1), EntityType::Trigger)).unwrap();
+	engine.sample();
+	controller.update();
+
+	// drag a selection over the trigger, then drag the selection to the right
+	let mut input_state = InputState::new();
+	input_state.pointer_down(cell(2, 1));
+	input_state.pointer_move(cell(3, 2));
+	input_state.pointer_up(cell(3, 2));
+	input_state.pointer_down(cell(2, 1));
+	input_state.pointer_move(cell(3, 1));
+	input_state.pointer_move(cell(5, 1));
+	input_state.pointer_up(cell(5, 1));
+	ui.process_input(&mut controller, &input_state);
+	engine.sample();
+	controller.update();
+
+	assert!(!input_state.is_pointer_down());
+	assert_eq!(ui.selection, Some(Position::new(4, 1)));
+	assert_eq!(controller.cursor_position, Position::new(5, 2));
+	assert!(controller.get_snapshot().get_entity(Position::new(4, 1)).is_some());
+
+	// the drag is undone in one step
+	press(&mut ui, &mut controller, Input::Char('u'));
+	engine.sample();
+	controller.update();
+	assert!(controller.get_snapshot().get_entity(Position::new(1, 1)).is_some());
+
+	// a click selects a single cell, the wheel changes the setting under the pointer
+	let mut input_state = InputState::new();
+	input_state.pointer_down(cell(2, 1));
+	input_state.pointer_up(cell(2, 1));
+	input_state.wheel(pointer(20, 172), 0, -2);
+	input_state.wheel(pointer(20, 172), 0, -1);
+	ui.process_input(&mut controller, &input_state);
+	engine.sample();
+	controller.update();
+
+	assert_eq!(ui.selection, None);
+	assert_eq!(controller.cursor_position, Position::new(1, 1));
+	let entity = controller.get_snapshot().get_entity(Position::new(1, 1)).unwrap();
+	let div = &entity.settings[1].value;
+	assert!(matches!(div, SettingValue::Float(v) if (v - 0.28).abs() < 1e-6));
+
+	// the wheel scrolls the grid
+	let window = controller.window_position;
+	let mut input_state = InputState::new();
+	input_state.wheel(cell(2, 1), 0, 3);
+	ui.process_input(&mut controller, &input_state);
+	assert_eq!(controller.window_position, window.add(Position::new(0, 3)));
     }
 }
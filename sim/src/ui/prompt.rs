@@ -0,0 +1,95 @@
+use crate::engine::{Command, Snapshot};
+use crate::entity::EntityType;
+use crate::grid::Position;
+use crate::Error;
+
+/// Commands for a line typed into the prompt, acting on the entity under the cursor.
+/// Several commands are separated by `;`, for example `add trigger; set bpm 90`.
+///
+/// - `add <type>` places an entity
+/// - `rm` removes the entity
+/// - `set <setting> <value>` changes a setting
+pub fn parse_prompt(
+    text: &str,
+    cursor: Position,
+    snapshot: &Snapshot,
+) -> Result<Vec<Command>, Error> {
+    let mut commands = vec![];
+    // the type of entity placed by an earlier command on the same line
+    let mut placed = None;
+
+    for line in text.split(';') {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            [] => (),
+            ["add", name] => {
+                let entity_type = EntityType::from_name(name)
+                    .ok_or_else(|| Error::Prompt(format!("unknown entity {}", name)))?;
+
+                placed = Some(entity_type);
+                commands.push(Command::AddEntity(cursor, entity_type));
+            }
+            ["rm"] => {
+                placed = None;
+                commands.push(Command::RemoveEntity(cursor));
+            }
+            ["set", name, value] => {
+                let settings = match placed {
+                    Some(entity_type) => entity_type.default_settings(),
+                    None => snapshot
+                        .get_entity(cursor)
+                        .map(|entity| entity.settings.clone())
+                        .unwrap_or_default(),
+                };
+
+                let mut setting = settings
+                    .into_iter()
+                    .find(|s| s.description == *name)
+                    .ok_or_else(|| Error::Prompt(format!("no setting {}", name)))?;
+
+                setting.try_update_value(value)?;
+                commands.push(Command::UpdateSetting(cursor, setting));
+            }
+            _ => return Err(Error::Prompt(format!("unknown command {}", line.trim()))),
+        }
+    }
+
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::SettingValue;
+
+    #[test]
+    fn test_parse_prompt() {
+        let cursor = Position::new(2, 3);
+        let snapshot = Snapshot::default();
+
+        let commands = parse_prompt("add trigger; set bpm 90 ;", cursor, &snapshot).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(
+            &commands[0],
+            Command::AddEntity(p, EntityType::Trigger) if *p == cursor
+        ));
+        assert!(matches!(
+            &commands[1],
+            Command::UpdateSetting(_, setting)
+                if matches!(setting.value, SettingValue::Float(v) if v == 90.0)
+        ));
+
+        assert!(matches!(
+            parse_prompt("rm", cursor, &snapshot).unwrap().as_slice(),
+            [Command::RemoveEntity(_)]
+        ));
+        assert!(parse_prompt("", cursor, &snapshot).unwrap().is_empty());
+
+        // settings need an entity under the cursor
+        assert!(parse_prompt("set bpm 90", cursor, &snapshot).is_err());
+        assert!(parse_prompt("add trigger; set bpm fast", cursor, &snapshot).is_err());
+        assert!(parse_prompt("add lfo", cursor, &snapshot).is_err());
+        assert!(parse_prompt("jump", cursor, &snapshot).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+use std::f32::consts::PI;
+
+/// Start of the latest window of `length` samples that begins where the signal rises
+/// through the middle of its range, the latest window when the signal does not rise
+pub fn find_trigger(samples: &[f32], length: usize) -> usize {
+    let last = samples.len().saturating_sub(length);
+    // search back at most one window so the display does not lag behind
+    let first = last.saturating_sub(length);
+
+    let range = &samples[first..];
+    let min = range.iter().fold(f32::MAX, |min, s| s.min(min));
+    let max = range.iter().fold(f32::MIN, |max, s| s.max(max));
+    let threshold = (min + max) / 2.0;
+
+    (first.max(1)..=last)
+        .rev()
+        .find(|&i| samples[i - 1] < threshold && samples[i] >= threshold)
+        .unwrap_or(last)
+}
+
+/// Magnitudes of the lower half of the spectrum of the last `size` samples with a Hann
+/// window, scaled so a sine with an amplitude of 1.0 peaks at 1.0. `size` is a power of two.
+pub fn spectrum(samples: &[f32], size: usize) -> Vec<f32> {
+    let offset = size.saturating_sub(samples.len());
+    let samples = &samples[samples.len().saturating_sub(size)..];
+    let mut real = vec![0.0; size];
+    let mut imaginary = vec![0.0; size];
+
+    for (i, s) in samples.iter().enumerate() {
+        let i = i + offset;
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos();
+        real[i] = s * window;
+    }
+
+    fft(&mut real, &mut imaginary);
+
+    (0..size / 2)
+        .map(|i| (real[i] * real[i] + imaginary[i] * imaginary[i]).sqrt() * 4.0 / size as f32)
+        .collect()
+}
+
+/// In place radix 2 fast fourier transform
+fn fft(real: &mut [f32], imaginary: &mut [f32]) {
+    let size = real.len();
+    let bits = size.trailing_zeros();
+
+    if size < 2 {
+        return;
+    }
+
+    for i in 0..size {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            real.swap(i, j);
+            imaginary.swap(i, j);
+        }
+    }
+
+    let mut length = 2;
+    while length <= size {
+        let angle = -2.0 * PI / length as f32;
+
+        for start in (0..size).step_by(length) {
+            for k in 0..length / 2 {
+                let (wr, wi) = ((angle * k as f32).cos(), (angle * k as f32).sin());
+                let (a, b) = (start + k, start + k + length / 2);
+                let tr = real[b] * wr - imaginary[b] * wi;
+                let ti = real[b] * wi + imaginary[b] * wr;
+
+                real[b] = real[a] - tr;
+                imaginary[b] = imaginary[a] - ti;
+                real[a] += tr;
+                imaginary[a] += ti;
+            }
+        }
+
+        length *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_trigger() {
+        // a gate opening every 8 samples, starting at sample 3
+        let samples: Vec<f32> = (0..64)
+            .map(|i| if (i + 5) % 8 < 4 { 1.0 } else { 0.0 })
+            .collect();
+
+        let start = find_trigger(&samples, 16);
+        assert_eq!(start, 43);
+        assert!(samples[start] == 1.0 && samples[start - 1] == 0.0);
+
+        // without a rising edge the latest window is shown
+        assert_eq!(find_trigger(&[0.5; 32], 16), 16);
+        assert_eq!(find_trigger(&[0.5; 8], 16), 0);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let samples: Vec<f32> = (0..256)
+            .map(|i| (2.0 * PI * 8.0 * i as f32 / 64.0).sin())
+            .collect();
+
+        let bins = spectrum(&samples, 64);
+        assert_eq!(bins.len(), 32);
+
+        let peak = (0..32)
+            .max_by(|&a, &b| bins[a].total_cmp(&bins[b]))
+            .unwrap();
+        assert_eq!(peak, 8);
+        assert!((bins[8] - 1.0).abs() < 0.01);
+        assert!(bins[20] < 0.01);
+    }
+}
@@ -1,11 +1,15 @@
 mod utils;
 mod web_graphics;
+mod worklet;
 use js_sys::{Float32Array, Uint8ClampedArray};
-use wasm_bindgen::__rt::core::{mem, slice};
+use wasm_bindgen::__rt::core::slice;
 use web_sys::console;
 
-use sim::{Audio, Grid, Input, InputState, UserInterface};
-use std::sync::Mutex;
+use sim::{
+    BufferedTransport, Command, Controller, Engine, Input, InputState, MidiInput, Modifiers, Patch,
+    Pointer, Sample, Snapshot, UserInterface,
+};
+use std::sync::{Arc, Mutex};
 use wasm_bindgen::prelude::*;
 use web_graphics::WebGraphics;
 
@@ -15,11 +19,18 @@ use web_graphics::WebGraphics;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-static AUDIO: Mutex<Option<Audio>> = Mutex::new(None);
-static GRID: Mutex<Option<Grid>> = Mutex::new(None);
+// the engine is only locked by `sample` and the controller only by the ui,
+// edits and state are passed between them through lock-free queues
+static ENGINE: Mutex<Option<Engine>> = Mutex::new(None);
+static CONTROLLER: Mutex<Option<Controller>> = Mutex::new(None);
 static UI: Mutex<Option<UserInterface>> = Mutex::new(None);
 static GRAPHICS: Mutex<Option<WebGraphics>> = Mutex::new(None);
 static INPUT: Mutex<Option<InputState>> = Mutex::new(None);
+// bytes received through web midi, parsed and sent to the engine on the next frame
+static MIDI: Mutex<Option<MidiInput<BufferedTransport>>> = Mutex::new(None);
+
+/// entities the grid can hold, every entity keeps its own signal buffers
+pub const MAX_ENTITIES: usize = 1024;
 
 #[wasm_bindgen]
 pub fn allocate_u8_buffer(size: usize) -> *mut u8 {
@@ -37,23 +48,34 @@ pub fn allocate_f32_buffer(size: usize) -> *mut f32 {
     ptr
 }
 
+/// # Safety
+///
+/// `ptr` must come from `allocate_u8_buffer` with at least `size` bytes
 #[wasm_bindgen]
-pub fn get_u8_buffer(ptr: *mut u8, size: usize) -> Uint8ClampedArray {
-    unsafe { Uint8ClampedArray::view(std::slice::from_raw_parts(ptr, size)) }
+pub unsafe fn get_u8_buffer(ptr: *mut u8, size: usize) -> Uint8ClampedArray {
+    Uint8ClampedArray::view(std::slice::from_raw_parts(ptr, size))
 }
 
+/// # Safety
+///
+/// `ptr` must come from `allocate_f32_buffer` with at least `size` floats
 #[wasm_bindgen]
-pub fn get_f32_buffer(ptr: *mut f32, size: usize) -> Float32Array {
-    unsafe { Float32Array::view(std::slice::from_raw_parts(ptr, size)) }
+pub unsafe fn get_f32_buffer(ptr: *mut f32, size: usize) -> Float32Array {
+    Float32Array::view(std::slice::from_raw_parts(ptr, size))
 }
 
 #[wasm_bindgen]
 pub fn init_sim(sample_rate: usize, buffer_size: usize, width: i32, height: i32) {
-    let mut audio = AUDIO.lock().unwrap();
-    let _ = audio.insert(Audio::new(sample_rate, buffer_size));
+    utils::set_panic_hook();
+
+    let (mut new_engine, new_controller) = Engine::new(sample_rate, buffer_size, 256);
+    new_engine.set_capacity(Some(MAX_ENTITIES));
+
+    let mut engine = ENGINE.lock().unwrap();
+    let _ = engine.insert(new_engine);
 
-    let mut grid = GRID.lock().unwrap();
-    let _ = grid.insert(Grid::new());
+    let mut controller = CONTROLLER.lock().unwrap();
+    let _ = controller.insert(new_controller);
 
     let mut ui = UI.lock().unwrap();
     let _ = ui.insert(UserInterface::new());
@@ -63,63 +85,213 @@ pub fn init_sim(sample_rate: usize, buffer_size: usize, width: i32, height: i32)
 
     let mut input = INPUT.lock().unwrap();
     let _ = input.insert(InputState::new());
+
+    let mut midi = MIDI.lock().unwrap();
+    let _ = midi.insert(MidiInput::new(BufferedTransport::new()));
 }
 
+/// Sample the next buffer into the left and then the right channel
+///
+/// # Safety
+///
+/// `pointer` must come from `allocate_f32_buffer` with `size` floats
 #[wasm_bindgen]
-pub fn sample(pointer: *mut f32, size: usize) {
-    let mut audio = AUDIO.lock().unwrap();
-    let mut grid = GRID.lock().unwrap();
-
-    match (grid.as_mut(), audio.as_mut()) {
-        (Some(mut grid), Some(audio)) => {
-            let (l, r) = audio.sample(&mut grid);
-            let buffer = unsafe { slice::from_raw_parts_mut(pointer, size) };
+pub unsafe fn sample(pointer: *mut f32, size: usize) {
+    let mut engine = ENGINE.lock().unwrap();
 
-            assert_eq!(l.len() + r.len(), size);
+    if let Some(engine) = engine.as_mut() {
+        let (l, r) = engine.sample();
+        let buffer = slice::from_raw_parts_mut(pointer, size);
 
-            for (i, s) in l.iter().enumerate() {
-                buffer[i] = *s;
-            }
+        assert_eq!(l.len() + r.len(), size);
 
-            for (i, s) in l.iter().enumerate() {
-                buffer[size / 2 + i] = *s;
-            }
-        }
-        _ => (),
+        let (left, right) = buffer.split_at_mut(size / 2);
+        left.copy_from_slice(l);
+        right.copy_from_slice(r);
     }
 }
 
+/// Handle the input since the last frame and render the interface
+///
+/// # Safety
+///
+/// `pointer` must come from `allocate_u8_buffer` with `size` bytes
 #[wasm_bindgen]
-pub fn render_image(pointer: *mut u8, size: usize) {
+pub unsafe fn render_image(pointer: *mut u8, size: usize) {
     let mut ui = UI.lock().unwrap();
     let mut graphics = GRAPHICS.lock().unwrap();
-    let mut grid = GRID.lock().unwrap();
+    let mut controller = CONTROLLER.lock().unwrap();
     let mut input_state = INPUT.lock().unwrap();
+    let mut midi = MIDI.lock().unwrap();
 
-    match (
-        grid.as_mut(),
+    if let (Some(controller), Some(ui), Some(graphics), Some(input_state), Some(midi)) = (
+        controller.as_mut(),
         ui.as_mut(),
         graphics.as_mut(),
         input_state.as_mut(),
+        midi.as_mut(),
     ) {
-        (Some(grid), Some(ui), Some(graphics), Some(input_state)) => {
-            ui.process_input(grid, input_state);
-            input_state.clear_buffer();
-            ui.render(graphics, grid);
-            let image = graphics.render_image();
+        for message in midi.poll().unwrap_or_default() {
+            let _ = controller.send(Command::Midi(message));
+        }
 
-            assert_eq!(size, image.data.len() * 4);
+        ui.process_input(controller, input_state);
+        input_state.clear_buffer();
 
-            let buffer = unsafe { slice::from_raw_parts_mut(pointer, size) };
+        // edits reach the worklet in the same frame and its latest state is rendered
+        worklet::update_relay();
+        controller.update();
+        ui.render(graphics, controller);
+        let image = graphics.render_image();
 
-            for (i, color) in image.data.iter().enumerate() {
-                buffer[i * 4] = color.red;
-                buffer[i * 4 + 1] = color.green;
-                buffer[i * 4 + 2] = color.blue;
-                buffer[i * 4 + 3] = color.alpha;
-            }
+        assert_eq!(size, image.data.len() * 4);
+
+        let buffer = slice::from_raw_parts_mut(pointer, size);
+
+        for (i, color) in image.data.iter().enumerate() {
+            buffer[i * 4] = color.red;
+            buffer[i * 4 + 1] = color.green;
+            buffer[i * 4 + 2] = color.blue;
+            buffer[i * 4 + 3] = color.alpha;
+        }
+    }
+}
+
+/// Messages sent by midi out entities since the last call, encoded as they go over the wire
+#[wasm_bindgen]
+pub fn receive_midi() -> Vec<u8> {
+    let mut controller = CONTROLLER.lock().unwrap();
+    let mut bytes = vec![];
+
+    if let Some(controller) = controller.as_mut() {
+        for (_, message) in controller.receive_midi() {
+            message.encode(&mut bytes);
+        }
+    }
+
+    bytes
+}
+
+/// Render the patch for a number of bars into a standard midi file
+#[wasm_bindgen]
+pub fn export_midi(bars: usize, bpm: f32) -> Vec<u8> {
+    let engine = ENGINE.lock().unwrap();
+    let controller = CONTROLLER.lock().unwrap();
+
+    // while the worklet plays, the patch is copied into an engine of its own
+    let copy = match (engine.as_ref(), controller.as_ref()) {
+        (None, Some(controller)) => engine_from_snapshot(controller.get_snapshot()),
+        _ => None,
+    };
+
+    match engine
+        .as_ref()
+        .or(copy.as_ref())
+        .map(|engine| engine.export_midi(bars, bpm))
+    {
+        Some(Ok(file)) => file.to_bytes(),
+        Some(Err(e)) => {
+            console::warn_1(&e.to_string().into());
+            vec![]
+        }
+        None => vec![],
+    }
+}
+
+/// Engine holding the entities of a snapshot, `None` for an empty grid
+fn engine_from_snapshot(snapshot: &Snapshot) -> Option<Engine> {
+    let rect = snapshot.get_bounds()?;
+    let commands = Patch::from_snapshot(snapshot, &rect).commands(rect.position);
+    let (mut engine, mut controller) =
+        Engine::new(snapshot.sample_rate.max(1), 256, commands.len());
+
+    controller.send_all(commands).ok()?;
+    // applies the commands
+    engine.sample();
+
+    Some(engine)
+}
+
+/// Yank the selection as patch text for the system clipboard
+#[wasm_bindgen]
+pub fn copy_patch() -> String {
+    let mut ui = UI.lock().unwrap();
+    let controller = CONTROLLER.lock().unwrap();
+
+    match (ui.as_mut(), controller.as_ref()) {
+        (Some(ui), Some(controller)) => ui.copy_patch(controller),
+        _ => String::new(),
+    }
+}
+
+/// Cut the selection as patch text for the system clipboard
+#[wasm_bindgen]
+pub fn cut_patch() -> String {
+    let mut ui = UI.lock().unwrap();
+    let mut controller = CONTROLLER.lock().unwrap();
+
+    match (ui.as_mut(), controller.as_mut()) {
+        (Some(ui), Some(controller)) => ui.cut_patch(controller),
+        _ => String::new(),
+    }
+}
+
+/// Paste patch text from the system clipboard at the cursor
+#[wasm_bindgen]
+pub fn paste_patch(text: String) {
+    let mut ui = UI.lock().unwrap();
+    let mut controller = CONTROLLER.lock().unwrap();
+
+    if let (Some(ui), Some(controller)) = (ui.as_mut(), controller.as_mut()) {
+        if let Err(e) = ui.paste_patch(controller, &text) {
+            console::warn_1(&e.to_string().into());
         }
-        _ => (),
+    }
+}
+
+/// The whole grid as patch text with the samples it plays embedded, for saving to a file
+#[wasm_bindgen]
+pub fn export_patch() -> String {
+    let controller = CONTROLLER.lock().unwrap();
+
+    controller
+        .as_ref()
+        .map(Controller::get_snapshot)
+        .and_then(|snapshot| {
+            let rect = snapshot.get_bounds()?;
+            Some(Patch::from_snapshot(snapshot, &rect).export())
+        })
+        .unwrap_or_default()
+}
+
+/// Play a WAVE file with the sampler at the cursor, resampled here
+/// so the audio side does not have to
+#[wasm_bindgen]
+pub fn load_sample(name: String, bytes: Vec<u8>) -> Result<(), JsValue> {
+    let sample = Sample::from_wav(&name, &bytes).map_err(|e| e.to_string())?;
+    let mut controller = CONTROLLER.lock().unwrap();
+
+    if let Some(controller) = controller.as_mut() {
+        let sample = match controller.get_snapshot().sample_rate {
+            0 => sample,
+            sample_rate => sample.resample(sample_rate),
+        };
+        let position = controller.cursor_position;
+
+        controller
+            .send(Command::LoadSample(position, Arc::new(sample)))
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn handle_midi(bytes: Vec<u8>) {
+    let mut midi = MIDI.lock().unwrap();
+
+    if let Some(midi) = midi.as_mut() {
+        midi.transport.push(&bytes);
     }
 }
 
@@ -142,6 +314,8 @@ pub fn handle_key_down(input: String) {
             "ArrowRight" => input_state.key_down(Input::Right),
             "ArrowDown" => input_state.key_down(Input::Down),
             "ArrowLeft" => input_state.key_down(Input::Left),
+            "PageUp" => input_state.key_down(Input::PageUp),
+            "PageDown" => input_state.key_down(Input::PageDown),
             _ => {
                 if let Some(c) = input.chars().next() {
                     input_state.key_down(Input::Char(c));
@@ -172,6 +346,8 @@ pub fn handle_key_up(input: String) {
             "ArrowRight" => input_state.key_up(Input::Right),
             "ArrowDown" => input_state.key_up(Input::Down),
             "ArrowLeft" => input_state.key_up(Input::Left),
+            "PageUp" => input_state.key_up(Input::PageUp),
+            "PageDown" => input_state.key_up(Input::PageDown),
             _ => {
                 if let Some(c) = input.chars().next() {
                     input_state.key_up(Input::Char(c));
@@ -182,3 +358,42 @@ pub fn handle_key_up(input: String) {
         console::log_1(&input.into());
     }
 }
+
+/// Forward a pointer event of the canvas by its dom event type, `x` and `y` are pixels of the
+/// viewport, `dx` and `dy` the steps the wheel turned
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn handle_pointer(
+    event: String,
+    x: i32,
+    y: i32,
+    dx: i32,
+    dy: i32,
+    shift: bool,
+    control: bool,
+    alt: bool,
+    meta: bool,
+) {
+    let mut input_state = INPUT.lock().unwrap();
+
+    if let Some(input_state) = input_state.as_mut() {
+        let pointer = Pointer {
+            x,
+            y,
+            modifiers: Modifiers {
+                shift,
+                control,
+                alt,
+                meta,
+            },
+        };
+
+        match event.as_ref() {
+            "pointermove" => input_state.pointer_move(pointer),
+            "pointerdown" => input_state.pointer_down(pointer),
+            "pointerup" => input_state.pointer_up(pointer),
+            "wheel" => input_state.wheel(pointer, dx, dy),
+            _ => (),
+        }
+    }
+}
@@ -22,7 +22,7 @@ impl WebGraphics {
 
 impl Graphics for WebGraphics {
     fn draw_image(&mut self, image: &Image, x: i32, y: i32) {
-        self.canvas.layer(&image, x, y);
+        self.canvas.layer(image, x, y);
     }
 
     fn draw_rect(&mut self, c: Color, x: i32, y: i32, w: i32, h: i32) {
@@ -0,0 +1,193 @@
+use crate::{CONTROLLER, ENGINE, MAX_ENTITIES};
+use js_sys::{Atomics, Int32Array, SharedArrayBuffer, Uint8Array};
+use sim::{
+    Command, EntityType, MessageRing, Patch, Position, Relay, RingStorage, Setting, SettingValue,
+    Worklet, RENDER_QUANTUM,
+};
+use std::cell::RefCell;
+use wasm_bindgen::__rt::core::slice;
+use wasm_bindgen::prelude::*;
+
+// commands the interface can send between two frames, room for pasting a large patch
+const QUEUE_SIZE: usize = 4096;
+
+// bytes used by the read and write index in front of the message data
+const HEADER_SIZE: u32 = 8;
+
+/// Message ring stored in a SharedArrayBuffer so the main thread
+/// and the worklet can both access it from their own wasm instance
+pub struct SharedRing {
+    indices: Int32Array,
+    data: Uint8Array,
+}
+
+impl SharedRing {
+    pub fn new(buffer: &SharedArrayBuffer) -> Self {
+        SharedRing {
+            indices: Int32Array::new_with_byte_offset_and_length(buffer, 0, 2),
+            data: Uint8Array::new_with_byte_offset(buffer, HEADER_SIZE),
+        }
+    }
+}
+
+impl RingStorage for SharedRing {
+    fn capacity(&self) -> usize {
+        self.data.length() as usize
+    }
+
+    fn load_index(&self, index: usize) -> usize {
+        Atomics::load(&self.indices, index as u32).unwrap_or(0) as usize
+    }
+
+    fn store_index(&self, index: usize, value: usize) {
+        let _ = Atomics::store(&self.indices, index as u32, value as i32);
+    }
+
+    fn get(&self, offset: usize) -> u8 {
+        self.data.get_index(offset as u32)
+    }
+
+    fn set(&self, offset: usize, value: u8) {
+        self.data.set_index(offset as u32, value);
+    }
+}
+
+// js objects can not be shared between threads, every wasm instance gets its own
+thread_local! {
+    static WORKLET: RefCell<Option<Worklet<SharedRing>>> = const { RefCell::new(None) };
+    static RELAY: RefCell<Option<Relay<SharedRing>>> = const { RefCell::new(None) };
+}
+
+/// Create a buffer to pass to `worklet_init` and `connect_message_ring`
+#[wasm_bindgen]
+pub fn create_message_ring(size: u32) -> SharedArrayBuffer {
+    SharedArrayBuffer::new(HEADER_SIZE + size)
+}
+
+/// Set up the sim inside an AudioWorkletProcessor, receiving edits through
+/// the `commands` ring and sending its state back through the `state` ring
+#[wasm_bindgen]
+pub fn worklet_init(sample_rate: usize, commands: SharedArrayBuffer, state: SharedArrayBuffer) {
+    crate::utils::set_panic_hook();
+
+    WORKLET.with(|worklet| {
+        let mut new_worklet = Worklet::new(
+            sample_rate,
+            MessageRing::new(SharedRing::new(&commands)),
+            MessageRing::new(SharedRing::new(&state)),
+        );
+        new_worklet.set_capacity(Some(MAX_ENTITIES));
+        let _ = worklet.borrow_mut().insert(new_worklet);
+    });
+}
+
+/// Frames rendered by every call to `worklet_process`
+#[wasm_bindgen]
+pub fn worklet_quantum() -> usize {
+    RENDER_QUANTUM
+}
+
+/// Render one quantum into two buffers
+///
+/// # Safety
+///
+/// `left` and `right` must come from `allocate_f32_buffer` with `worklet_quantum` floats
+#[wasm_bindgen]
+pub unsafe fn worklet_process(left: *mut f32, right: *mut f32) {
+    WORKLET.with(|worklet| {
+        if let Some(worklet) = worklet.borrow_mut().as_mut() {
+            let left = slice::from_raw_parts_mut(left, RENDER_QUANTUM);
+            let right = slice::from_raw_parts_mut(right, RENDER_QUANTUM);
+
+            worklet.process(left, right);
+        }
+    });
+}
+
+/// Hand the interface over to the worklet behind the rings passed to `worklet_init`,
+/// the worklet gets the entities already on the grid and from now on its state
+/// is rendered while the engine on the main thread stops
+#[wasm_bindgen]
+pub fn connect_message_ring(
+    commands: SharedArrayBuffer,
+    state: SharedArrayBuffer,
+) -> Result<(), JsValue> {
+    let (relay, mut new_controller) = Relay::new(
+        MessageRing::new(SharedRing::new(&commands)),
+        MessageRing::new(SharedRing::new(&state)),
+        QUEUE_SIZE,
+    );
+
+    let mut controller = CONTROLLER.lock().unwrap();
+
+    if let Some(controller) = controller.as_ref() {
+        new_controller.cursor_position = controller.cursor_position;
+        new_controller.window_position = controller.window_position;
+
+        let snapshot = controller.get_snapshot();
+
+        if let Some(rect) = snapshot.get_bounds() {
+            new_controller
+                .send_all(Patch::from_snapshot(snapshot, &rect).commands(rect.position))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    let _ = controller.insert(new_controller);
+    let _ = ENGINE.lock().unwrap().take();
+    RELAY.with(|r| {
+        let _ = r.borrow_mut().insert(relay);
+    });
+
+    Ok(())
+}
+
+/// Send the edits queued by the controller to the worklet and hand its state
+/// to the controller, once the rings are connected
+pub fn update_relay() {
+    RELAY.with(|relay| {
+        if let Some(relay) = relay.borrow_mut().as_mut() {
+            relay.update();
+        }
+    });
+}
+
+fn send(command: Command) -> Result<(), JsValue> {
+    let mut controller = CONTROLLER.lock().unwrap();
+
+    match controller.as_mut() {
+        Some(controller) => controller.send(command).map_err(|e| e.to_string().into()),
+        None => Err("sim is not initialized".into()),
+    }
+}
+
+#[wasm_bindgen]
+pub fn send_add_entity(x: i32, y: i32, name: String) -> Result<(), JsValue> {
+    let entity_type = EntityType::from_name(&name)
+        .ok_or_else(|| JsValue::from(format!("unknown entity {}", name)))?;
+
+    send(Command::AddEntity(Position::new(x, y), entity_type))
+}
+
+#[wasm_bindgen]
+pub fn send_remove_entity(x: i32, y: i32) -> Result<(), JsValue> {
+    send(Command::RemoveEntity(Position::new(x, y)))
+}
+
+/// Change a float setting, at `time` in samples when given
+#[wasm_bindgen]
+pub fn send_float_setting(
+    x: i32,
+    y: i32,
+    name: String,
+    value: f32,
+    time: Option<f64>,
+) -> Result<(), JsValue> {
+    let position = Position::new(x, y);
+    let setting = Setting::new(SettingValue::Float(value), name);
+
+    match time {
+        Some(time) => send(Command::ScheduleSetting(time as u64, position, setting)),
+        None => send(Command::UpdateSetting(position, setting)),
+    }
+}
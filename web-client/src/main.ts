@@ -8,6 +8,17 @@ import init, {
   render_image,
   handle_key_down,
   handle_key_up,
+  handle_midi,
+  receive_midi,
+  export_midi,
+  copy_patch,
+  cut_patch,
+  paste_patch,
+  export_patch,
+  load_sample,
+  handle_pointer,
+  create_message_ring,
+  connect_message_ring,
 } from "sim-web-client";
 
 const channels = 2;
@@ -15,17 +26,53 @@ const sampleRate = 48000;
 const bufferSize = 256;
 const bufferSizeInSeconds = bufferSize / 48_000 / 2;
 
+// bytes of edits, or of state sent back, that can wait for the other side to read them
+const ringSize = 1 << 18;
+
 const viewportWidth = 1080;
 const viewportHeight = 720;
 
 const startButton = document.querySelector("button#start");
 
+// play through an AudioWorkletProcessor running the sim, edits are sent to it through
+// a ring in a SharedArrayBuffer and the state it renders comes back through another
+const startWorklet = async (audioCtx: AudioContext) => {
+  const commands = create_message_ring(ringSize);
+  const state = create_message_ring(ringSize);
+  // set by the wasm-bindgen glue once `init` finished
+  const module = (init as unknown as { __wbindgen_wasm_module: WebAssembly.Module })
+    .__wbindgen_wasm_module;
+
+  await audioCtx.audioWorklet.addModule(new URL("./processor.ts", import.meta.url));
+
+  const node = new AudioWorkletNode(audioCtx, "sim-processor", {
+    numberOfInputs: 0,
+    outputChannelCount: [channels],
+    processorOptions: { module, commands, state },
+  });
+
+  connect_message_ring(commands, state);
+  node.connect(audioCtx.destination);
+};
+
+// copy, cut and paste shortcuts are left to the browser so the clipboard events fire
+const isClipboardShortcut = (e: KeyboardEvent) =>
+  (e.ctrlKey || e.metaKey) && ["c", "x", "v"].includes(e.key);
+
 const handleKeyDown = (e: KeyboardEvent) => {
+  if (isClipboardShortcut(e)) {
+    return;
+  }
+
   e.preventDefault();
   handle_key_down(e.key);
 };
 
 const handleKeyUp = (e: KeyboardEvent) => {
+  if (isClipboardShortcut(e)) {
+    return;
+  }
+
   e.preventDefault();
   handle_key_up(e.key);
 };
@@ -50,6 +97,7 @@ startButton &&
 
         // load new samples from wasm and get the samples
         sample(audioBuffer, bufferSize * 2);
+
         const samples = get_f32_buffer(audioBuffer, bufferSize * 2);
 
         // create buffer
@@ -72,18 +120,136 @@ startButton &&
     const renderBufferSize = viewportWidth * viewportHeight * 4;
     const renderBuffer = allocate_u8_buffer(renderBufferSize);
 
+    // pointer events in pixels of the viewport, the canvas may be scaled by css
+    const handlePointer = (e: PointerEvent | WheelEvent, dx = 0, dy = 0) => {
+      const rect = canvas.getBoundingClientRect();
+      const x = Math.floor(((e.clientX - rect.left) * viewportWidth) / rect.width);
+      const y = Math.floor(((e.clientY - rect.top) * viewportHeight) / rect.height);
+
+      handle_pointer(e.type, x, y, dx, dy, e.shiftKey, e.ctrlKey, e.altKey, e.metaKey);
+    };
+
+    canvas.addEventListener("pointermove", (e) => handlePointer(e));
+    canvas.addEventListener("pointerdown", (e) => {
+      if (e.button === 0) {
+        canvas.setPointerCapture(e.pointerId);
+        handlePointer(e);
+      }
+    });
+    canvas.addEventListener("pointerup", (e) => {
+      if (e.button === 0) {
+        handlePointer(e);
+      }
+    });
+    // wave files dropped on the canvas play with the sampler at the cursor,
+    // patch files are pasted at the cursor
+    canvas.addEventListener("dragover", (e) => e.preventDefault());
+    canvas.addEventListener("drop", (e) => {
+      e.preventDefault();
+
+      for (const file of Array.from(e.dataTransfer?.files ?? [])) {
+        if (file.name.endsWith(".json")) {
+          file.text().then(paste_patch);
+        } else {
+          file
+            .arrayBuffer()
+            .then((buffer) => load_sample(file.name, new Uint8Array(buffer)))
+            .catch((e) => console.warn(e));
+        }
+      }
+    });
+    canvas.addEventListener(
+      "wheel",
+      (e) => {
+        e.preventDefault();
+        // one step per event, however far the device scrolled
+        handlePointer(e, Math.sign(e.deltaX), Math.sign(e.deltaY));
+      },
+      { passive: false },
+    );
+
     const graphicsCallback = () => {
       ctx.clearRect(0, 0, viewportWidth, viewportHeight);
       render_image(renderBuffer, renderBufferSize);
       const buffer = get_u8_buffer(renderBuffer, renderBufferSize);
       const imageData = new ImageData(buffer, viewportWidth, viewportHeight);
       ctx.putImageData(imageData, 0, 0);
+      sendMidi();
 
       window.requestAnimationFrame(graphicsCallback);
     };
 
-    window.setInterval(audioCallback, 0);
+    const audioInterval = window.setInterval(audioCallback, 0);
     window.requestAnimationFrame(graphicsCallback);
+
+    // the worklet needs a cross origin isolated page for the shared rings,
+    // once it plays the sim on the main thread stops sampling
+    if (window.crossOriginIsolated && audioCtx.audioWorklet) {
+      startWorklet(audioCtx).then(
+        () => window.clearInterval(audioInterval),
+        (e) => console.warn(e),
+      );
+    }
+  });
+
+// the subset of web midi used here, not every typescript dom library declares it
+type MidiAccess = {
+  inputs: Map<string, { onmidimessage: ((e: { data: Uint8Array }) => void) | null }>;
+  outputs: Map<string, { send: (data: Uint8Array) => void }>;
+  onstatechange: (() => void) | null;
+};
+
+let midiAccess: MidiAccess | undefined;
+
+// forward the bytes of every connected midi input, the sim parses them itself
+const connectMidi = (access: MidiAccess) => {
+  const listen = () =>
+    access.inputs.forEach((input) => {
+      input.onmidimessage = (e) => handle_midi(e.data);
+    });
+
+  listen();
+  access.onstatechange = listen;
+  midiAccess = access;
+};
+
+// send the notes of midi out entities to every connected output
+const sendMidi = () => {
+  const bytes = receive_midi();
+
+  if (bytes.length > 0) {
+    midiAccess?.outputs.forEach((output) => output.send(bytes));
+  }
+};
+
+const download = (data: BlobPart, type: string, name: string) => {
+  const link = document.createElement("a");
+  link.href = URL.createObjectURL(new Blob([data], { type }));
+  link.download = name;
+  link.click();
+  URL.revokeObjectURL(link.href);
+};
+
+const exportButton = document.querySelector("button#export");
+const exportPatchButton = document.querySelector("button#export-patch");
+
+exportButton &&
+  exportButton.addEventListener("click", () => {
+    const bytes = export_midi(4, 120);
+
+    if (bytes.length > 0) {
+      download(bytes, "audio/midi", "sim.mid");
+    }
+  });
+
+// the patch with its samples embedded, dropping the file on the canvas loads it again
+exportPatchButton &&
+  exportPatchButton.addEventListener("click", () => {
+    const text = export_patch();
+
+    if (text.length > 0) {
+      download(text, "application/json", "sim.json");
+    }
   });
 
 init().then(() => {
@@ -92,4 +258,30 @@ init().then(() => {
 
   window.addEventListener("keyup", handleKeyUp);
   window.addEventListener("keydown", handleKeyDown);
+
+  // selections are exchanged with the system clipboard as patch json
+  document.addEventListener("copy", (e) => {
+    e.clipboardData?.setData("text/plain", copy_patch());
+    e.preventDefault();
+  });
+  document.addEventListener("cut", (e) => {
+    e.clipboardData?.setData("text/plain", cut_patch());
+    e.preventDefault();
+  });
+  document.addEventListener("paste", (e) => {
+    const text = e.clipboardData?.getData("text/plain");
+
+    if (text) {
+      paste_patch(text);
+    }
+    e.preventDefault();
+  });
+
+  const requestMidiAccess: (() => Promise<MidiAccess>) | undefined = (
+    navigator as any
+  ).requestMIDIAccess?.bind(navigator);
+
+  if (requestMidiAccess) {
+    requestMidiAccess().then(connectMidi, (e) => console.warn(e));
+  }
 });
@@ -0,0 +1,69 @@
+import {
+  initSync,
+  worklet_init,
+  worklet_process,
+  worklet_quantum,
+  allocate_f32_buffer,
+  get_f32_buffer,
+} from "sim-web-client";
+
+// the globals of the audio worklet scope, not declared by the dom library
+declare const sampleRate: number;
+declare function registerProcessor(name: string, processor: unknown): void;
+declare class AudioWorkletProcessor {
+  readonly port: MessagePort;
+}
+
+type ProcessorOptions = {
+  processorOptions: {
+    // the module compiled on the main thread, instantiated again in this scope
+    module: WebAssembly.Module;
+    // edits sent by the main thread, see `connect_message_ring`
+    commands: SharedArrayBuffer;
+    // snapshots and outputs sent back to the main thread
+    state: SharedArrayBuffer;
+  };
+};
+
+// renders the sim one quantum at a time, without allocating once it is running
+class SimProcessor extends AudioWorkletProcessor {
+  quantum: number;
+  left: number;
+  right: number;
+  views: [Float32Array, Float32Array];
+
+  constructor(options: ProcessorOptions) {
+    super();
+    const { module, commands, state } = options.processorOptions;
+
+    initSync({ module });
+    worklet_init(sampleRate, commands, state);
+
+    this.quantum = worklet_quantum();
+    this.left = allocate_f32_buffer(this.quantum);
+    this.right = allocate_f32_buffer(this.quantum);
+    this.views = this.createViews();
+  }
+
+  // views of the wasm memory are detached when the memory grows
+  createViews(): [Float32Array, Float32Array] {
+    return [
+      get_f32_buffer(this.left, this.quantum),
+      get_f32_buffer(this.right, this.quantum),
+    ];
+  }
+
+  process(_inputs: Float32Array[][], outputs: Float32Array[][]) {
+    worklet_process(this.left, this.right);
+
+    if (this.views[0].length === 0) {
+      this.views = this.createViews();
+    }
+
+    outputs[0].forEach((channel, i) => channel.set(this.views[i % 2]));
+
+    return true;
+  }
+}
+
+registerProcessor("sim-processor", SimProcessor);
@@ -3,6 +3,14 @@ import checker from "vite-plugin-checker";
 import wasmPack from "vite-plugin-wasm-pack";
 
 // https://vitejs.dev/config/
+// a cross origin isolated page can share memory with the audio worklet
+const headers = {
+  "Cross-Origin-Opener-Policy": "same-origin",
+  "Cross-Origin-Embedder-Policy": "require-corp",
+};
+
 export default defineConfig({
   plugins: [checker({ typescript: true }), wasmPack("./sim-web-client")],
+  server: { headers },
+  preview: { headers },
 });